@@ -1,4 +1,4 @@
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshDeserialize;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
     commitment_config::CommitmentConfig,
@@ -9,7 +9,10 @@ use solana_sdk::{
     transaction::Transaction,
 };
 use std::str::FromStr;
-use counter_program::CounterInstruction;
+use solana_counter_program::{
+    find_config_address, find_metadata_address, find_registry_address, CounterInstruction,
+    CounterMetadata, MetadataAccount, RegistryAccount,
+};
 
 #[tokio::main]
 async fn main() {
@@ -17,6 +20,9 @@ async fn main() {
     let program_id = Pubkey::from_str("3T8DsLJF1UYYq6zzaVrZTPmEckZktdY5dxHWHWeJVS6r")
         .expect("Invalid program ID");
 
+    // Every counter instruction passes the program config PDA last
+    let (config, _) = find_config_address(&program_id);
+
     // Connect to local Solana cluster (devnet or testnet)
     let rpc_url = String::from("http://localhost:8899");
     let client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
@@ -49,9 +55,17 @@ async fn main() {
     let initial_value = 100u64;
 
     // Create initialize instruction
-    let init_instruction_data = borsh::to_vec(&CounterInstruction::InitializeCounter { 
-        initial_value 
-    }).expect("Failed to serialize instruction");
+    let (metadata_address, _) = find_metadata_address(&program_id, &counter_keypair.pubkey());
+    let (registry_address, _) = find_registry_address(&program_id, &payer.pubkey());
+
+    let init_instruction_data = CounterInstruction::InitializeCounter { 
+        initial_value,
+        metadata: Some(CounterMetadata {
+            name: String::from("page-views"),
+            description: String::from("Number of times the landing page was opened"),
+        }),
+        history_capacity: 0,
+    }.pack();
 
     let initialize_instruction = Instruction::new_with_bytes(
         program_id,
@@ -60,6 +74,9 @@ async fn main() {
             AccountMeta::new(counter_keypair.pubkey(), true),     // New counter account (signer)
             AccountMeta::new(payer.pubkey(), true),               // Payer account (signer)
             AccountMeta::new_readonly(system_program::id(), false), // System program
+            AccountMeta::new(registry_address, false),              // Payer's counter registry
+            AccountMeta::new(metadata_address, false),              // Metadata PDA
+            AccountMeta::new_readonly(config, false),                // Program config
         ],
     );
 
@@ -91,14 +108,18 @@ async fn main() {
     // --- Increment Counter (default step = 1) ---
     println!("\n2. Incrementing counter by default step (1)...");
     
-    let increment_data = borsh::to_vec(&CounterInstruction::IncrementCounter { 
+    let increment_data = CounterInstruction::IncrementCounter { 
         step: None  // Default step of 1
-    }).expect("Failed to serialize instruction");
+    }.pack();
 
     let increment_instruction = Instruction::new_with_bytes(
         program_id,
         &increment_data,
-        vec![AccountMeta::new(counter_keypair.pubkey(), false)], // Writable, not signer
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),      // Counter account (writable)
+            AccountMeta::new_readonly(payer.pubkey(), true),        // Counter authority (signer)
+            AccountMeta::new_readonly(config, false),               // Program config
+        ],
     );
 
     let mut transaction = Transaction::new_with_payer(
@@ -122,14 +143,18 @@ async fn main() {
     // --- Increment Counter (custom step = 5) ---
     println!("\n3. Incrementing counter by custom step (5)...");
     
-    let increment_by_5_data = borsh::to_vec(&CounterInstruction::IncrementCounter { 
+    let increment_by_5_data = CounterInstruction::IncrementCounter { 
         step: Some(5)  // Custom step of 5
-    }).expect("Failed to serialize instruction");
+    }.pack();
 
     let increment_by_5_instruction = Instruction::new_with_bytes(
         program_id,
         &increment_by_5_data,
-        vec![AccountMeta::new(counter_keypair.pubkey(), false)],
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
     );
 
     let mut transaction = Transaction::new_with_payer(
@@ -153,14 +178,18 @@ async fn main() {
     // --- Decrement Counter (default step = 1) ---
     println!("\n4. Decrementing counter by default step (1)...");
     
-    let decrement_data = borsh::to_vec(&CounterInstruction::DecrementCounter { 
+    let decrement_data = CounterInstruction::DecrementCounter { 
         step: None  // Default step of 1
-    }).expect("Failed to serialize instruction");
+    }.pack();
 
     let decrement_instruction = Instruction::new_with_bytes(
         program_id,
         &decrement_data,
-        vec![AccountMeta::new(counter_keypair.pubkey(), false)],
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
     );
 
     let mut transaction = Transaction::new_with_payer(
@@ -184,14 +213,18 @@ async fn main() {
     // --- Decrement Counter (custom step = 3) ---
     println!("\n5. Decrementing counter by custom step (3)...");
     
-    let decrement_by_3_data = borsh::to_vec(&CounterInstruction::DecrementCounter { 
+    let decrement_by_3_data = CounterInstruction::DecrementCounter { 
         step: Some(3)  // Custom step of 3
-    }).expect("Failed to serialize instruction");
+    }.pack();
 
     let decrement_by_3_instruction = Instruction::new_with_bytes(
         program_id,
         &decrement_by_3_data,
-        vec![AccountMeta::new(counter_keypair.pubkey(), false)],
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
     );
 
     let mut transaction = Transaction::new_with_payer(
@@ -217,14 +250,59 @@ async fn main() {
     
     match client.get_account_data(&counter_keypair.pubkey()) {
         Ok(account_data) => {
-            let counter = counter_program::CounterAccount::try_from_slice(&account_data)
-                .expect("Failed to deserialize counter data");
+            let counter = solana_counter_program::CounterAccount::load(&account_data)
+                .expect("Failed to read counter data");
             
-            println!("📊 Final counter value: {}", counter.count);
-            println!("🎉 All operations completed successfully!");
+            println!("📊 Final counter value: {}", counter.count());
         }
         Err(err) => {
             eprintln!("❌ Failed to read counter value: {}", err);
+            return;
+        }
+    }
+
+    // --- Read Counter Metadata ---
+    println!("\n7. Reading counter metadata...");
+
+    match client.get_account_data(&metadata_address) {
+        Ok(account_data) => {
+            let metadata = MetadataAccount::try_from_slice(&account_data)
+                .expect("Failed to deserialize counter metadata")
+                .metadata;
+
+            println!("🏷️  Name: {}", metadata.name);
+            println!("   Description: {}", metadata.description);
+        }
+        Err(err) => {
+            eprintln!("❌ Failed to read counter metadata: {}", err);
+            return;
+        }
+    }
+
+    // --- List Counters Owned By The Payer ---
+    println!("\n8. Listing counters in the payer's registry...");
+
+    match client.get_account_data(&registry_address) {
+        Ok(account_data) => {
+            let registry = RegistryAccount::try_from_slice(&account_data)
+                .expect("Failed to deserialize counter registry");
+
+            let page_size = 10;
+            let mut page = 0;
+            loop {
+                let counters = registry.page(page, page_size);
+                if counters.is_empty() {
+                    break;
+                }
+                for counter in counters {
+                    println!("   {}", counter);
+                }
+                page += 1;
+            }
+            println!("🎉 All operations completed successfully!");
+        }
+        Err(err) => {
+            eprintln!("❌ Failed to read counter registry: {}", err);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+//! Fuzz `process_instruction` with arbitrary instruction data and account lists.
+//!
+//!     cargo +nightly fuzz run process_instruction
+
+#![no_main]
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use arbitrary::Arbitrary;
+use common::NativeSvm;
+use libfuzzer_sys::fuzz_target;
+use solana_counter_program::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+/// History capacity of the first counter
+const HISTORY_CAPACITY: u16 = 3;
+
+#[derive(Arbitrary, Debug)]
+struct FuzzInstruction {
+    data: Vec<u8>,
+    /// Index into the key pool and writable flag of each account
+    accounts: Vec<(u8, bool)>,
+}
+
+/// Keys are derived from fixed seeds so every input replays deterministically
+fn keypair(seed: u8) -> Keypair {
+    Keypair::new_from_array([seed; 32])
+}
+
+fuzz_target!(|instructions: Vec<FuzzInstruction>| {
+    let mut svm = NativeSvm::new();
+    let program_id = Pubkey::new_from_array([0xc0; 32]);
+    svm.add_program(program_id, process_instruction);
+
+    let authority = keypair(1);
+    let delegate = keypair(2);
+    let fresh = keypair(3);
+    let counters = [keypair(4), keypair(5)];
+    for signer in [&authority, &delegate, &fresh] {
+        svm.airdrop(&signer.pubkey(), 1_000_000_000);
+    }
+
+    let (registry, _) = find_registry_address(&program_id, &authority.pubkey());
+    let (config, _) = find_config_address(&program_id);
+    for (counter, history_capacity) in counters.iter().zip([HISTORY_CAPACITY, 0]) {
+        let instruction = counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeCounter {
+                initial_value: 10,
+                metadata: None,
+                history_capacity,
+            },
+            vec![
+                AccountMeta::new(counter.pubkey(), true),
+                AccountMeta::new(authority.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(registry, false),
+                AccountMeta::new_readonly(config, false),
+            ],
+        );
+        svm.process(&[instruction], &[&authority, counter])
+            .expect("Initialize should succeed");
+    }
+
+    let signers = [&authority, &delegate, &fresh, &counters[0], &counters[1]];
+    let pool = [
+        counters[0].pubkey(),
+        counters[1].pubkey(),
+        authority.pubkey(),
+        delegate.pubkey(),
+        fresh.pubkey(),
+        registry,
+        config,
+        system_program::id(),
+        find_registry_address(&program_id, &fresh.pubkey()).0,
+        find_roles_address(&program_id, &counters[0].pubkey()).0,
+        find_delegate_address(&program_id, &counters[0].pubkey(), &delegate.pubkey()).0,
+        find_metadata_address(&program_id, &counters[0].pubkey()).0,
+    ];
+
+    for instruction in instructions.iter().take(16) {
+        let accounts = instruction
+            .accounts
+            .iter()
+            .take(16)
+            .map(|(index, writable)| {
+                let key = pool[*index as usize % pool.len()];
+                let signer = signers.iter().any(|signer| signer.pubkey() == key);
+                if *writable {
+                    AccountMeta::new(key, signer)
+                } else {
+                    AccountMeta::new_readonly(key, signer)
+                }
+            })
+            .collect();
+        let instruction = Instruction::new_with_bytes(program_id, &instruction.data, accounts);
+        let _ = svm.process(&[instruction], &signers);
+
+        // Counters may be closed and recreated, but always hold a valid layout
+        for counter in &counters {
+            if let Some(account) = svm.get_account(&counter.pubkey()) {
+                if account.owner == program_id {
+                    assert!(CounterAccount::load(&account.data).is_ok());
+                    assert!(decode_history(&account.data).is_ok());
+                }
+            }
+        }
+    }
+});
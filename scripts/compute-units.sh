@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Builds the program and the last Borsh build, then runs the compute unit benchmark.
+#
+#     scripts/compute-units.sh                  # compare against tests/compute_units_baseline.txt
+#     UPDATE_CU_BASELINE=1 scripts/compute-units.sh   # record a new baseline
+#
+# Extra arguments are passed to the test binary. Needs the Solana toolchain (cargo build-sbf).
+set -euo pipefail
+
+# Last commit that decoded and re-encoded the whole counter with Borsh on every increment.
+BORSH_REV=88c98c2~1
+
+root=$(git rev-parse --show-toplevel)
+cd "$root"
+
+src=$(mktemp -d)
+trap 'rm -rf "$src"' EXIT
+
+git archive "$BORSH_REV" | tar -x -C "$src"
+(cd "$src" && cargo build-sbf --sbf-out-dir "$src/deploy")
+mkdir -p target/deploy
+cp "$src/deploy/solana_counter_program.so" target/deploy/solana_counter_program_borsh.so
+
+cargo build-sbf
+cargo test --features test-sbf --test compute_units -- --nocapture "$@"
@@ -1,20 +1,25 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use shank::{ShankAccount, ShankInstruction, ShankType};
+use thiserror::Error;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
-    program::invoke,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
-    sysvar::{rent::Rent, Sysvar},
+    bpf_loader_upgradeable, system_instruction, system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
-/// This program extends the official Solana counter example
-/// by adding decrement functionality and customizable step size
-/// Original example: https://solana.com/ru/docs/programs/rust/program-structure
-
+// This program extends the official Solana counter example
+// by adding decrement functionality and customizable step size
+// Original example: https://solana.com/ru/docs/programs/rust/program-structure
 
 entrypoint!(process_instruction);
 
@@ -24,436 +29,5097 @@ pub fn process_instruction(
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    // Deserialize instruction data into CounterInstruction enum
-    let instruction = CounterInstruction::try_from_slice(instruction_data)
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    // Single-byte legacy tags keep the payloads, accounts and semantics of the first release
+    // on baseline counters, and act like the matching instruction on every other counter
+    let instruction = match LegacyInstruction::unpack(instruction_data)? {
+        Some(instruction) if instruction.targets_baseline(program_id, accounts) => {
+            return process_legacy_instruction(program_id, accounts, instruction);
+        }
+        Some(instruction) => CounterInstruction::from(instruction),
+        None => CounterInstruction::unpack(instruction_data)?,
+    };
 
-    // Route to appropriate instruction handler
+    // Program-wide configuration instructions bypass the pause switch
     match instruction {
-        CounterInstruction::InitializeCounter { initial_value } => {
-            process_initialize_counter(program_id, accounts, initial_value)?
+        CounterInstruction::InitializeConfig => {
+            return process_initialize_config(program_id, accounts);
         }
+        CounterInstruction::UpdateConfig {
+            admin,
+            default_step,
+            default_overflow_mode,
+        } => {
+            return process_update_config(
+                program_id,
+                accounts,
+                admin,
+                default_step,
+                default_overflow_mode,
+            );
+        }
+        CounterInstruction::SetPaused { paused } => {
+            return process_set_paused(program_id, accounts, paused);
+        }
+        _ => {}
+    }
+
+    // Every other instruction passes the config PDA as its last account
+    let (config_account, accounts) = accounts
+        .split_last()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let config = load_config(program_id, config_account)?;
+
+    // Refuse all mutations while the program is paused
+    if config.paused {
+        msg!("Program is paused");
+        return Err(CounterError::ProgramPaused.into());
+    }
+
+    // Route to appropriate instruction handler
+    match instruction {
+        CounterInstruction::InitializeCounter {
+            initial_value,
+            metadata,
+            history_capacity,
+        } => process_initialize_counter(
+            program_id,
+            accounts,
+            initial_value,
+            metadata,
+            history_capacity,
+            &config,
+        )?,
         CounterInstruction::IncrementCounter { step } => {
-            process_increment_counter(program_id, accounts, step)?
+            process_increment_counter(program_id, accounts, step, config.default_step)?
         }
         CounterInstruction::DecrementCounter { step } => {
-            process_decrement_counter(program_id, accounts, step)?
+            process_decrement_counter(program_id, accounts, step, config.default_step)?
+        }
+        CounterInstruction::Batch { ops } => {
+            process_batch(program_id, accounts, ops, config.default_step)?
+        }
+        CounterInstruction::TransferBetweenCounters { amount } => {
+            process_transfer_between_counters(program_id, accounts, amount)?
         }
+        CounterInstruction::ApproveDelegate {
+            increment_allowance,
+            decrement_allowance,
+        } => process_approve_delegate(
+            program_id,
+            accounts,
+            increment_allowance,
+            decrement_allowance,
+        )?,
+        CounterInstruction::RevokeDelegate => process_revoke_delegate(program_id, accounts)?,
+        CounterInstruction::InitializeRoles => process_initialize_roles(program_id, accounts)?,
+        CounterInstruction::AddRoleHolder { role, holder } => {
+            process_add_role_holder(program_id, accounts, role, holder)?
+        }
+        CounterInstruction::RemoveRoleHolder { role, holder } => {
+            process_remove_role_holder(program_id, accounts, role, holder)?
+        }
+        CounterInstruction::SetRolePublic { role, public } => {
+            process_set_role_public(program_id, accounts, role, public)?
+        }
+        CounterInstruction::ResetCounter => process_reset_counter(program_id, accounts)?,
+        CounterInstruction::CloseCounter => process_close_counter(program_id, accounts)?,
+        CounterInstruction::InitializeMultisig { threshold } => {
+            process_initialize_multisig(program_id, accounts, threshold)?
+        }
+        CounterInstruction::SetAuthority { new_authority } => {
+            process_set_authority(program_id, accounts, new_authority)?
+        }
+        CounterInstruction::FreezeCounter => process_set_frozen(program_id, accounts, true)?,
+        CounterInstruction::ThawCounter => process_set_frozen(program_id, accounts, false)?,
+        CounterInstruction::UpdateMetadata { metadata } => {
+            process_update_metadata(program_id, accounts, metadata)?
+        }
+        CounterInstruction::SetIncrementFee {
+            fee_lamports,
+            treasury,
+        } => process_set_increment_fee(program_id, accounts, fee_lamports, treasury)?,
+        CounterInstruction::SetTokenGate { mint, min_balance } => {
+            process_set_token_gate(program_id, accounts, mint, min_balance)?
+        }
+        CounterInstruction::SetTokenPayment {
+            mint,
+            amount_per_step,
+            mode,
+            destination,
+        } => process_set_token_payment(program_id, accounts, mint, amount_per_step, mode, destination)?,
+        CounterInstruction::SetUnitsMint { mint } => process_set_units_mint(program_id, accounts, mint)?,
+        CounterInstruction::SetTrigger {
+            index,
+            threshold,
+            program,
+            data,
+        } => process_set_trigger(program_id, accounts, index, threshold, program, data)?,
+        CounterInstruction::SetHook { program, pre, post } => {
+            process_set_hook(program_id, accounts, program, pre, post)?
+        }
+        CounterInstruction::SetExpiry { expires_at } => process_set_expiry(program_id, accounts, expires_at)?,
+        CounterInstruction::CloseExpiredCounter => process_close_expired_counter(program_id, accounts)?,
+        CounterInstruction::SetTimelock { delay } => process_set_timelock(program_id, accounts, delay)?,
+        CounterInstruction::ScheduleChange { action } => process_schedule_change(program_id, accounts, action)?,
+        CounterInstruction::ExecuteChange => process_execute_change(program_id, accounts)?,
+        CounterInstruction::CancelChange => process_cancel_change(program_id, accounts)?,
+        CounterInstruction::MigrateCounter => process_migrate_counter(program_id, accounts)?,
+        // Handled above
+        CounterInstruction::InitializeConfig
+        | CounterInstruction::UpdateConfig { .. }
+        | CounterInstruction::SetPaused { .. } => {}
     };
 
     Ok(())
 }
 
-/// Data structure stored in counter account
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+/// Data structure stored in counter account.
+/// Read and written in place over the account data; Borsh is only used for
+/// instruction data and the variable-size accounts.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable, ShankAccount)]
 pub struct CounterAccount {
-    pub count: u64,
+    count: u64,
+    /// Key allowed to mutate this counter and manage its delegates (the initializing payer)
+    pub authority: Pubkey,
+    /// Frozen counters stay readable but reject every mutation
+    frozen: u8,
+    /// How increments and decrements behave at the ends of the u64 range
+    overflow_mode: u8,
+    /// Lamports charged to the caller of every `IncrementCounter` (0 = free)
+    fee_lamports: u64,
+    /// Account receiving the increment fees
+    treasury: Pubkey,
+    /// Mint whose holders may increment and decrement (default key = ungated)
+    gate_mint: Pubkey,
+    /// Minimum balance of `gate_mint` the signer must hold
+    gate_min_balance: u64,
+    /// Mint of the tokens paid for every increment
+    payment_mint: Pubkey,
+    /// Token account receiving the payments (unused when burning)
+    payment_destination: Pubkey,
+    /// Tokens paid per unit of step (0 = free)
+    payment_per_step: u64,
+    /// Whether payments are transferred or burned
+    payment_mode: u8,
+    /// Mint whose supply tracks the count, minted on increment and burned on decrement (default key = off)
+    units_mint: Pubkey,
+    /// Bump of the mint authority PDA of `units_mint`
+    units_authority_bump: u8,
+    /// Milestones invoking a program once the count reaches them.
+    /// `MAX_TRIGGERS` spelled out, as the IDL generator only reads literal lengths
+    triggers: [Trigger; 4],
+    /// Program invoked around every increment and decrement (default key = none)
+    hook_program: Pubkey,
+    /// Whether the hook runs before the change is applied
+    hook_pre: u8,
+    /// Whether the hook runs after the change is applied
+    hook_post: u8,
+    /// Unix timestamp after which mutations are refused and anyone may close the counter (0 = never)
+    expires_at: i64,
+    /// Payer of the counter rent, refunded when an expired counter is closed
+    rent_payer: Pubkey,
+    /// Seconds a scheduled change waits before it can execute; while non-zero, sets, resets
+    /// and settings changes have to be scheduled (0 = changes apply immediately)
+    timelock_delay: i64,
+    /// Layout version, `CounterAccount::VERSION` for counters created or migrated by this program
+    version: u8,
+    /// Number of open delegate records; the counter cannot be closed while any is left
+    delegate_count: u32,
+    /// Zeroed space that later fields are taken from, so the account never has to grow again.
+    /// Spelled out as a literal for the IDL generator
+    reserved: [u8; 252],
 }
 
-/// Available instructions for the counter program
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum CounterInstruction {
-    /// Initialize counter with starting value
-    InitializeCounter { 
-        initial_value: u64 
-    },
-    /// Increment counter by specified step (None = default step of 1)
-    IncrementCounter {
-        step: Option<u64>
-    },
-    /// Decrement counter by specified step (None = default step of 1)
-    DecrementCounter {
-        step: Option<u64>
-    },
-}
+impl CounterAccount {
+    /// Size of the counter fields in the account
+    pub const LEN: usize = std::mem::size_of::<Self>();
 
-/// Initialize a new counter account with starting value
-fn process_initialize_counter(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    initial_value: u64,
-) -> ProgramResult {
-    msg!("Initializing counter with value: {}", initial_value);
-    
-    let accounts_iter = &mut accounts.iter();
+    /// Current layout version
+    pub const VERSION: u8 = 1;
 
-    // Accounts expected in order:
-    // 0. [writable] Counter account (to be created)
-    // 1. [signer, writable] Payer account
-    // 2. [] System program
-    let counter_account = next_account_info(accounts_iter)?;
-    let payer_account = next_account_info(accounts_iter)?;
-    let system_program = next_account_info(accounts_iter)?;
+    pub fn new(count: u64, authority: Pubkey, overflow_mode: OverflowMode) -> Self {
+        Self {
+            count,
+            authority,
+            frozen: 0,
+            overflow_mode: overflow_mode as u8,
+            fee_lamports: 0,
+            treasury: Pubkey::default(),
+            gate_mint: Pubkey::default(),
+            gate_min_balance: 0,
+            payment_mint: Pubkey::default(),
+            payment_destination: Pubkey::default(),
+            payment_per_step: 0,
+            payment_mode: TokenPaymentMode::Transfer as u8,
+            units_mint: Pubkey::default(),
+            units_authority_bump: 0,
+            triggers: [Trigger::zeroed(); MAX_TRIGGERS],
+            hook_program: Pubkey::default(),
+            hook_pre: 0,
+            hook_post: 0,
+            expires_at: 0,
+            // The initializing payer is both the first authority and the rent payer
+            rent_payer: authority,
+            timelock_delay: 0,
+            version: Self::VERSION,
+            delegate_count: 0,
+            reserved: [0; 252],
+        }
+    }
 
-    // Check if account is already initialized
-    if counter_account.data.borrow().len() > 0 {
-        return Err(ProgramError::AccountAlreadyInitialized);
+    pub fn version(&self) -> u8 {
+        self.version
     }
 
-    // Calculate required account space and rent
-    let account_space = std::mem::size_of::<CounterAccount>();
-    let rent = Rent::get()?;
-    let required_lamports = rent.minimum_balance(account_space);
+    pub fn delegate_count(&self) -> u32 {
+        self.delegate_count
+    }
 
-    // Create counter account via system program
-    invoke(
-        &system_instruction::create_account(
-            payer_account.key,
-            counter_account.key,
-            required_lamports,
-            account_space as u64,
-            program_id,
-        ),
-        &[
-            payer_account.clone(),
-            counter_account.clone(),
-            system_program.clone(),
-        ],
-    )?;
+    pub fn count(&self) -> u64 {
+        self.count
+    }
 
-    // Initialize counter data
-    let counter_data = CounterAccount {
-        count: initial_value,
-    };
+    pub fn set_count(&mut self, count: u64) {
+        self.count = count;
+    }
 
-    // Serialize data into account
-    let mut account_data = counter_account.data.borrow_mut();
-    counter_data.serialize(&mut &mut account_data[..])?;
+    pub fn is_frozen(&self) -> bool {
+        self.frozen != 0
+    }
 
-    msg!("Counter initialized successfully with value: {}", initial_value);
-    Ok(())
-}
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen as u8;
+    }
 
-/// Increment counter by specified step (default: 1)
-fn process_increment_counter(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    step: Option<u64>,
-) -> ProgramResult {
-    // Use default step of 1 if not specified
-    let step_value = step.unwrap_or(1);
-    msg!("Incrementing counter by: {}", step_value);
-    
-    let accounts_iter = &mut accounts.iter();
-    
-    // 0. [writable] Counter account
-    let counter_account = next_account_info(accounts_iter)?;
+    pub fn overflow_mode(&self) -> Result<OverflowMode, ProgramError> {
+        OverflowMode::try_from(self.overflow_mode)
+    }
 
-    // Verify account ownership
-    if counter_account.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    pub fn fee_lamports(&self) -> u64 {
+        self.fee_lamports
     }
 
-    // Check if account is initialized
-    if counter_account.data.borrow().len() == 0 {
-        return Err(ProgramError::UninitializedAccount);
+    pub fn treasury(&self) -> Pubkey {
+        self.treasury
     }
 
-    // Deserialize and update counter data
-    let mut data = counter_account.data.borrow_mut();
-    let mut counter_data = CounterAccount::try_from_slice(&data)?;
+    pub fn set_increment_fee(&mut self, fee_lamports: u64, treasury: Pubkey) {
+        self.fee_lamports = fee_lamports;
+        self.treasury = treasury;
+    }
 
-    // Safely increment counter with overflow check
-    counter_data.count = counter_data
-        .count
-        .checked_add(step_value)
-        .ok_or(ProgramError::InvalidAccountData)?;
+    pub fn gate_mint(&self) -> Pubkey {
+        self.gate_mint
+    }
 
-    // Serialize updated data back to account
-    counter_data.serialize(&mut &mut data[..])?;
+    pub fn gate_min_balance(&self) -> u64 {
+        self.gate_min_balance
+    }
 
-    msg!("Counter incremented to: {}", counter_data.count);
-    Ok(())
-}
+    pub fn is_token_gated(&self) -> bool {
+        self.gate_mint != Pubkey::default()
+    }
 
-/// Decrement counter by specified step (default: 1)
-fn process_decrement_counter(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    step: Option<u64>,
-) -> ProgramResult {
-    // Use default step of 1 if not specified
-    let step_value = step.unwrap_or(1);
-    msg!("Decrementing counter by: {}", step_value);
-    
-    let accounts_iter = &mut accounts.iter();
-    
-    // 0. [writable] Counter account
-    let counter_account = next_account_info(accounts_iter)?;
+    pub fn set_token_gate(&mut self, mint: Pubkey, min_balance: u64) {
+        self.gate_mint = mint;
+        self.gate_min_balance = min_balance;
+    }
 
-    // Verify account ownership
-    if counter_account.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    pub fn payment_mint(&self) -> Pubkey {
+        self.payment_mint
     }
 
-    // Check if account is initialized
-    if counter_account.data.borrow().len() == 0 {
-        return Err(ProgramError::UninitializedAccount);
+    pub fn payment_destination(&self) -> Pubkey {
+        self.payment_destination
     }
 
-    // Deserialize and update counter data
-    let mut data = counter_account.data.borrow_mut();
-    let mut counter_data = CounterAccount::try_from_slice(&data)?;
+    pub fn payment_per_step(&self) -> u64 {
+        self.payment_per_step
+    }
 
-    // Safely decrement counter with underflow check
-    counter_data.count = counter_data
-        .count
-        .checked_sub(step_value)
-        .ok_or(ProgramError::InvalidAccountData)?;
+    pub fn payment_mode(&self) -> Result<TokenPaymentMode, ProgramError> {
+        TokenPaymentMode::try_from(self.payment_mode)
+    }
 
-    // Serialize updated data back to account
-    counter_data.serialize(&mut &mut data[..])?;
+    pub fn set_token_payment(
+        &mut self,
+        mint: Pubkey,
+        amount_per_step: u64,
+        mode: TokenPaymentMode,
+        destination: Pubkey,
+    ) {
+        self.payment_mint = mint;
+        self.payment_per_step = amount_per_step;
+        self.payment_mode = mode as u8;
+        self.payment_destination = destination;
+    }
 
-    msg!("Counter decremented to: {}", counter_data.count);
-    Ok(())
+    pub fn units_mint(&self) -> Pubkey {
+        self.units_mint
+    }
+
+    pub fn is_tokenized(&self) -> bool {
+        self.units_mint != Pubkey::default()
+    }
+
+    pub fn set_units_mint(&mut self, mint: Pubkey, authority_bump: u8) {
+        self.units_mint = mint;
+        self.units_authority_bump = authority_bump;
+    }
+
+    pub fn triggers(&self) -> &[Trigger; MAX_TRIGGERS] {
+        &self.triggers
+    }
+
+    pub fn triggers_mut(&mut self) -> &mut [Trigger; MAX_TRIGGERS] {
+        &mut self.triggers
+    }
+
+    pub fn hook_program(&self) -> Pubkey {
+        self.hook_program
+    }
+
+    /// Whether the hook runs in `phase`
+    pub fn hook_runs(&self, phase: HookPhase) -> bool {
+        match phase {
+            HookPhase::Pre => self.hook_pre != 0,
+            HookPhase::Post => self.hook_post != 0,
+        }
+    }
+
+    /// Whether increments and decrements pass hook accounts
+    pub fn has_hook(&self) -> bool {
+        self.hook_program != Pubkey::default() && (self.hook_pre != 0 || self.hook_post != 0)
+    }
+
+    pub fn set_hook(&mut self, program: Pubkey, pre: bool, post: bool) {
+        self.hook_program = program;
+        self.hook_pre = pre as u8;
+        self.hook_post = post as u8;
+    }
+
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at
+    }
+
+    pub fn set_expires_at(&mut self, expires_at: i64) {
+        self.expires_at = expires_at;
+    }
+
+    /// Whether the counter has an expiry that lies at or before `unix_timestamp`
+    pub fn is_expired(&self, unix_timestamp: i64) -> bool {
+        self.expires_at != 0 && unix_timestamp >= self.expires_at
+    }
+
+    pub fn rent_payer(&self) -> Pubkey {
+        self.rent_payer
+    }
+
+    pub fn timelock_delay(&self) -> i64 {
+        self.timelock_delay
+    }
+
+    pub fn set_timelock_delay(&mut self, delay: i64) {
+        self.timelock_delay = delay;
+    }
+
+    /// Slot indexes of the triggers that have not fired yet, in slot order
+    pub fn armed_triggers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.triggers
+            .iter()
+            .enumerate()
+            .filter(|(_, trigger)| trigger.is_armed())
+            .map(|(index, _)| index)
+    }
+
+    /// View the counter fields of a plain counter account or a counter followed
+    /// by a well-formed history extension
+    ///
+    /// Baseline counters of `BASELINE_COUNTER_LEN` bytes fail to load until they are
+    /// migrated with `CounterInstruction::MigrateCounter`.
+    pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+        Self::check_extension(data)?;
+        let counter: &Self =
+            bytemuck::try_from_bytes(&data[..Self::LEN]).map_err(|_| ProgramError::InvalidAccountData)?;
+        counter.check_version()?;
+        Ok(counter)
+    }
+
+    /// Mutable view of the counter fields, see `load`
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        Self::check_extension(data)?;
+        let counter: &mut Self = bytemuck::try_from_bytes_mut(&mut data[..Self::LEN])
+            .map_err(|_| ProgramError::InvalidAccountData)?;
+        counter.check_version()?;
+        Ok(counter)
+    }
+
+    fn check_version(&self) -> ProgramResult {
+        if self.version == 0 || self.version > Self::VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+
+    fn check_extension(data: &[u8]) -> ProgramResult {
+        if data.len() == Self::LEN {
+            return Ok(());
+        }
+
+        let header = data
+            .get(Self::LEN..Self::LEN + HistoryHeader::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let header: &HistoryHeader = bytemuck::from_bytes(header);
+        if header.capacity == 0
+            || header.len > header.capacity
+            || header.next >= header.capacity
+            || data.len() != Self::LEN + history_space(header.capacity)
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use litesvm::LiteSVM;
-    use solana_sdk::{
-        account::ReadableAccount,
-        instruction::{AccountMeta, Instruction},
-        message::Message,
-        signature::{Keypair, Signer},
-        system_program,
-        transaction::Transaction,
-    };
+/// Size of the counter accounts created by the first release of the program, which held
+/// only the `u64` count
+pub const BASELINE_COUNTER_LEN: usize = 8;
 
-    #[test]
-    fn test_counter_program() {
-        let mut svm = LiteSVM::new();
+/// Upper bound on the number of history entries a counter can keep
+pub const MAX_HISTORY_CAPACITY: u16 = 128;
 
-        let payer = Keypair::new();
+/// Header of the optional history extension stored right after the counter fields
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable, ShankType)]
+pub struct HistoryHeader {
+    /// Number of entry slots in the ring buffer
+    pub capacity: u16,
+    /// Number of slots holding an entry
+    pub len: u16,
+    /// Slot the next entry is written to
+    pub next: u16,
+}
 
-        svm.airdrop(&payer.pubkey(), 1_000_000_000)
-            .expect("Failed to airdrop");
-        
-        let program_keypair = Keypair::new();
-        let program_id = program_keypair.pubkey();
+impl HistoryHeader {
+    /// Size of the history header
+    pub const LEN: usize = std::mem::size_of::<Self>();
+}
 
-        svm.add_program_from_file(
-            program_id,
-            "target/deploy/solana_counter_program.so"
-        ).expect("Failed to load program");
+/// One recorded change of a counter value
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable, ShankType)]
+pub struct HistoryEntry {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    /// Key that signed the change (a multisig account for multisig authorities)
+    pub signer: Pubkey,
+    /// Resulting value minus previous value
+    pub delta: i128,
+    pub resulting_value: u64,
+}
 
-        // Create new counter account with initial value
-        let counter_keypair = Keypair::new();
-        let initial_value: u64 = 42;
+impl HistoryEntry {
+    /// Size of a history entry
+    pub const LEN: usize = std::mem::size_of::<Self>();
+}
 
-        println!("Testing counter initialization...");
+/// Space taken by a history extension of `capacity` entries (0 when disabled)
+pub fn history_space(capacity: u16) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    HistoryHeader::LEN + capacity as usize * HistoryEntry::LEN
+}
 
-        let init_instruction_data =
-            borsh::to_vec(&CounterInstruction::InitializeCounter { initial_value })
-                .expect("Failed to serialize instruction");
+/// Decode the history of a counter account, oldest entry first
+pub fn decode_history(data: &[u8]) -> Result<Vec<HistoryEntry>, ProgramError> {
+    CounterAccount::load(data)?;
+    let Some(history) = data.get(CounterAccount::LEN..).filter(|h| !h.is_empty()) else {
+        return Ok(Vec::new());
+    };
 
-        let initialize_instruction = Instruction::new_with_bytes(
-            program_id,
-            &init_instruction_data,
-            vec![
-                AccountMeta::new(counter_keypair.pubkey(), true),
-                AccountMeta::new(payer.pubkey(), true),
-                AccountMeta::new_readonly(system_program::id(), false),
-            ],
-        );
+    let (header, entries) = history.split_at(HistoryHeader::LEN);
+    let header: &HistoryHeader = bytemuck::from_bytes(header);
+    let capacity = header.capacity as usize;
+    let oldest = (header.next as usize + capacity - header.len as usize) % capacity;
 
-        let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer, &counter_keypair],
-            message,
-            svm.latest_blockhash()
-        );
+    let history = (0..header.len as usize)
+        .map(|position| {
+            let offset = (oldest + position) % capacity * HistoryEntry::LEN;
+            *bytemuck::from_bytes(&entries[offset..offset + HistoryEntry::LEN])
+        })
+        .collect();
+    Ok(history)
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Initialize transaction should succeed");
+/// Number of trigger slots in a counter
+pub const MAX_TRIGGERS: usize = 4;
 
-        let logs = result.unwrap().logs;
-        println!("Transaction logs:\n{:#?}", logs);  
+/// Maximum length of the instruction data a trigger sends
+pub const MAX_TRIGGER_DATA_LEN: usize = 32;
 
-        // Verify counter was initialized correctly
-        let account = svm
-            .get_account(&counter_keypair.pubkey())
-            .expect("Failed to get counter account");
+/// Milestone that invokes `program` with the stored instruction data the first
+/// time an increment takes the count from below `threshold` to at least `threshold`.
+/// The invoked instruction receives the counter as its only, read-only account.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable, ShankType)]
+pub struct Trigger {
+    pub threshold: u64,
+    /// Program to invoke (default key = empty slot)
+    pub program: Pubkey,
+    /// Non-zero once the trigger has fired
+    pub fired: u8,
+    /// Slot the trigger fired in
+    pub fired_slot: u64,
+    data_len: u8,
+    /// `MAX_TRIGGER_DATA_LEN` spelled out, as the IDL generator only reads literal lengths
+    data: [u8; 32],
+}
 
-        let counter: CounterAccount = CounterAccount::try_from_slice(account.data())
-            .expect("Failed to deserialize counter data");
+impl Trigger {
+    pub fn new(threshold: u64, program: Pubkey, data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() > MAX_TRIGGER_DATA_LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let mut trigger = Self {
+            threshold,
+            program,
+            data_len: data.len() as u8,
+            ..Self::zeroed()
+        };
+        trigger.data[..data.len()].copy_from_slice(data);
+        Ok(trigger)
+    }
 
-        assert_eq!(counter.count, 42);
-        println!("Counter initialized successfully with value: {}", counter.count);   
-            
-        // Test default increment (step = 1)
-        println!("Testing default increment (step = 1)...");
+    /// Instruction data sent to the program
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.data_len as usize]
+    }
 
-        let increment_instruction_data =
-            borsh::to_vec(&CounterInstruction::IncrementCounter { step: None })
-                .expect("Failed to serialize instruction");
+    pub fn has_fired(&self) -> bool {
+        self.fired != 0
+    }
 
-        let increment_instruction = Instruction::new_with_bytes(
-            program_id,
-            &increment_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+    /// Occupied and still waiting for its threshold
+    pub fn is_armed(&self) -> bool {
+        self.program != Pubkey::default() && !self.has_fired()
+    }
+}
 
-        let message = Message::new(&[increment_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+/// Behaviour of a counter when a step would leave the u64 range
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Fail the instruction
+    #[default]
+    Checked,
+    /// Clamp at 0 or u64::MAX
+    Saturating,
+    /// Wrap around
+    Wrapping,
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Increment transaction should succeed");
+impl TryFrom<u8> for OverflowMode {
+    type Error = ProgramError;
 
-        let account = svm
-            .get_account(&counter_keypair.pubkey())
-            .expect("Failed to get counter account");
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OverflowMode::Checked),
+            1 => Ok(OverflowMode::Saturating),
+            2 => Ok(OverflowMode::Wrapping),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
 
-        let counter: CounterAccount = CounterAccount::try_from_slice(account.data())
-            .expect("Failed to deserialize counter data");
-        assert_eq!(counter.count, 43);
-        println!("Counter incremented by 1 to: {}", counter.count);
+/// What happens to the tokens paid for an increment
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenPaymentMode {
+    /// Move the tokens to the payment destination
+    #[default]
+    Transfer,
+    /// Burn the tokens from the caller's account
+    Burn,
+}
 
-        // Test custom increment (step = 5)
-        println!("Testing custom increment (step = 5)...");
+impl TryFrom<u8> for TokenPaymentMode {
+    type Error = ProgramError;
 
-        let increment_by_5_instruction_data =
-            borsh::to_vec(&CounterInstruction::IncrementCounter { step: Some(5) })
-                .expect("Failed to serialize instruction");
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TokenPaymentMode::Transfer),
+            1 => Ok(TokenPaymentMode::Burn),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
 
-        let increment_by_5_instruction = Instruction::new_with_bytes(
-            program_id,
-            &increment_by_5_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+impl OverflowMode {
+    /// Add `step` to `count` following this mode
+    pub fn add(self, count: u64, step: u64) -> Result<u64, ProgramError> {
+        match self {
+            OverflowMode::Checked => count
+                .checked_add(step)
+                .ok_or(ProgramError::InvalidAccountData),
+            OverflowMode::Saturating => Ok(count.saturating_add(step)),
+            OverflowMode::Wrapping => Ok(count.wrapping_add(step)),
+        }
+    }
 
-        let message = Message::new(&[increment_by_5_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+    /// Subtract `step` from `count` following this mode
+    pub fn sub(self, count: u64, step: u64) -> Result<u64, ProgramError> {
+        match self {
+            OverflowMode::Checked => count
+                .checked_sub(step)
+                .ok_or(ProgramError::InvalidAccountData),
+            OverflowMode::Saturating => Ok(count.saturating_sub(step)),
+            OverflowMode::Wrapping => Ok(count.wrapping_sub(step)),
+        }
+    }
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Increment by 5 transaction should succeed");
+/// Seed of the program-wide config PDA
+pub const CONFIG_SEED: &[u8] = b"config";
 
-        let account = svm
-            .get_account(&counter_keypair.pubkey())
-            .expect("Failed to get counter account");
+/// Program-wide settings stored in the config PDA.
+/// Until the config is initialized every instruction uses `ConfigAccount::default()`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct ConfigAccount {
+    /// Key allowed to update the config and pause the program
+    pub admin: Pubkey,
+    /// While set, every counter instruction is refused
+    pub paused: bool,
+    /// Step used when an increment or decrement does not specify one
+    pub default_step: u64,
+    /// Overflow mode given to newly initialized counters
+    pub default_overflow_mode: OverflowMode,
+    pub bump: u8,
+}
 
-        let counter: CounterAccount = CounterAccount::try_from_slice(account.data())
-            .expect("Failed to deserialize counter data");
-        assert_eq!(counter.count, 48);
-        println!("Counter incremented by 5 to: {}", counter.count);
+impl ConfigAccount {
+    /// Serialized size of the config account
+    pub const LEN: usize = 32 + 1 + 8 + 1 + 1;
+}
 
-        // Test default decrement (step = 1)
-        println!("Testing default decrement (step = 1)...");
+impl Default for ConfigAccount {
+    fn default() -> Self {
+        Self {
+            admin: Pubkey::default(),
+            paused: false,
+            default_step: 1,
+            default_overflow_mode: OverflowMode::Checked,
+            bump: 0,
+        }
+    }
+}
 
-        let decrement_instruction_data =
-            borsh::to_vec(&CounterInstruction::DecrementCounter { step: None })
-                .expect("Failed to serialize instruction");
+/// Derive the program-wide config address
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
 
-        let decrement_instruction = Instruction::new_with_bytes(
-            program_id,
-            &decrement_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+/// Size of the ProgramData header in front of the program bytes
+const PROGRAM_DATA_METADATA_LEN: usize = 4 + 8 + 1 + 32;
 
-        let message = Message::new(&[decrement_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+/// Derive the ProgramData address holding the upgrade authority of the program
+pub fn find_program_data_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id())
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Decrement transaction should succeed");
+/// Seed of the per-authority counter registry PDA
+pub const REGISTRY_SEED: &[u8] = b"registry";
 
-        let account = svm
-            .get_account(&counter_keypair.pubkey())
-            .expect("Failed to get counter account");
+/// Registry PDA derived from ["registry", authority] listing every counter the
+/// authority controls, sized to its contents
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct RegistryAccount {
+    pub authority: Pubkey,
+    /// Number of counters ever registered, usable as the next sequential index
+    pub next_index: u64,
+    pub bump: u8,
+    /// Registered counters in creation order
+    pub counters: Vec<Pubkey>,
+}
 
-        let counter: CounterAccount = CounterAccount::try_from_slice(account.data())
-            .expect("Failed to deserialize counter data");
-        assert_eq!(counter.count, 47);
-        println!("Counter decremented by 1 to: {}", counter.count);
+impl RegistryAccount {
+    /// Size of the fields before the first counter entry
+    pub const HEADER_LEN: usize = 32 + 8 + 1 + 4;
 
-        // Test custom decrement (step = 3)
-        println!("Testing custom decrement (step = 3)...");
+    /// Counters on page `page` (zero-based) of `page_size` entries
+    pub fn page(&self, page: usize, page_size: usize) -> &[Pubkey] {
+        let start = page.saturating_mul(page_size).min(self.counters.len());
+        let end = start.saturating_add(page_size).min(self.counters.len());
+        &self.counters[start..end]
+    }
 
-        let decrement_by_3_instruction_data =
-            borsh::to_vec(&CounterInstruction::DecrementCounter { step: Some(3) })
-                .expect("Failed to serialize instruction");
+    /// Byte range of `count` entries starting at entry `start`, for fetching a
+    /// page with an RPC data slice instead of the whole account
+    pub fn entry_range(start: usize, count: usize) -> std::ops::Range<usize> {
+        let offset = Self::HEADER_LEN + start * 32;
+        offset..offset + count * 32
+    }
 
-        let decrement_by_3_instruction = Instruction::new_with_bytes(
-            program_id,
-            &decrement_by_3_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+    /// Decode counter entries from a data slice fetched with `entry_range`
+    pub fn decode_entries(data: &[u8]) -> Vec<Pubkey> {
+        data.chunks_exact(32)
+            .map(|chunk| Pubkey::new_from_array(chunk.try_into().expect("chunk is 32 bytes")))
+            .collect()
+    }
+}
 
-        let message = Message::new(&[decrement_by_3_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+/// Derive the counter registry address of an authority
+pub fn find_registry_address(program_id: &Pubkey, authority: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRY_SEED, authority.as_ref()], program_id)
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Decrement by 3 transaction should succeed");
+/// Seed of the metadata PDA attached to a counter
+pub const METADATA_SEED: &[u8] = b"metadata";
 
-        let account = svm
-            .get_account(&counter_keypair.pubkey())
-            .expect("Failed to get counter account");
+/// Maximum length of a counter name in bytes
+pub const MAX_NAME_LEN: usize = 32;
 
-        let counter: CounterAccount = CounterAccount::try_from_slice(account.data())
-            .expect("Failed to deserialize counter data");
-        assert_eq!(counter.count, 44);
-        println!("Counter decremented by 3 to: {}", counter.count);
+/// Maximum length of a counter description in bytes
+pub const MAX_DESCRIPTION_LEN: usize = 256;
 
-        // Test underflow protection
-        println!("Testing underflow protection...");
+/// Human-readable label of a counter
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CounterMetadata {
+    /// Short UTF-8 name, at most `MAX_NAME_LEN` bytes
+    pub name: String,
+    /// UTF-8 description, at most `MAX_DESCRIPTION_LEN` bytes
+    pub description: String,
+}
 
-        // Reset counter to 0
-        let reset_instruction_data =
-            borsh::to_vec(&CounterInstruction::DecrementCounter { step: Some(44) })
-                .expect("Failed to serialize instruction");
+impl CounterMetadata {
+    /// Check the name and description length limits
+    pub fn validate(&self) -> ProgramResult {
+        if self.name.len() > MAX_NAME_LEN || self.description.len() > MAX_DESCRIPTION_LEN {
+            return Err(CounterError::MetadataTooLong.into());
+        }
+        Ok(())
+    }
+}
 
-        let reset_instruction = Instruction::new_with_bytes(
-            program_id,
-            &reset_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+/// Metadata PDA derived from ["metadata", counter], sized to its contents
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct MetadataAccount {
+    pub counter: Pubkey,
+    pub metadata: CounterMetadata,
+    pub bump: u8,
+}
 
-        let message = Message::new(&[reset_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+/// Derive the metadata address of a counter
+pub fn find_metadata_address(program_id: &Pubkey, counter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[METADATA_SEED, counter.as_ref()], program_id)
+}
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_ok(), "Reset transaction should succeed");
+/// Maximum number of keys a multisig authority can list
+pub const MAX_MULTISIG_SIGNERS: usize = 11;
 
-        // Attempt to decrement below 0 should fail
-        let decrement_below_zero_instruction_data =
-            borsh::to_vec(&CounterInstruction::DecrementCounter { step: Some(1) })
-                .expect("Failed to serialize instruction");
+/// M-of-N authority: usable anywhere a counter authority is checked by passing
+/// the multisig account as the authority followed by the signing keys
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct MultisigAccount {
+    /// `MultisigAccount::DISCRIMINATOR`, telling multisigs apart from other program accounts
+    pub discriminator: [u8; 8],
+    /// Number of listed signers required
+    pub threshold: u8,
+    /// Number of valid entries in `signers`
+    pub signer_count: u8,
+    /// `MAX_MULTISIG_SIGNERS` spelled out, as the IDL generator only reads literal lengths
+    pub signers: [Pubkey; 11],
+}
 
-        let decrement_below_zero_instruction = Instruction::new_with_bytes(
-            program_id,
-            &decrement_below_zero_instruction_data,
-            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
-        );
+impl MultisigAccount {
+    /// First 8 bytes of `sha256("account:MultisigAccount")`
+    pub const DISCRIMINATOR: [u8; 8] = [77, 9, 180, 199, 183, 246, 156, 81];
 
-        let message = Message::new(&[decrement_below_zero_instruction], Some(&payer.pubkey()));
-        let transaction = Transaction::new(
-            &[&payer],
-            message,
-            svm.latest_blockhash()
-        );
+    /// Serialized size of the multisig account
+    pub const LEN: usize = 8 + 1 + 1 + 32 * MAX_MULTISIG_SIGNERS;
 
-        let result = svm.send_transaction(transaction);
-        assert!(result.is_err(), "Decrement below zero should fail");
-        println!("Underflow protection test passed!");
+    /// Whether program-owned `data` holds a multisig rather than another program account
+    pub fn is_multisig(data: &[u8]) -> bool {
+        data.len() == Self::LEN && data[..8] == Self::DISCRIMINATOR
     }
 }
+
+/// Seed prefix of delegate record PDAs: `[DELEGATE_SEED, counter, delegate]`
+pub const DELEGATE_SEED: &[u8] = b"delegate";
+
+/// Allowances granted by a counter authority to a delegate
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct DelegateAccount {
+    pub counter: Pubkey,
+    pub delegate: Pubkey,
+    /// Remaining units the delegate may increment by
+    pub increment_allowance: u64,
+    /// Remaining units the delegate may decrement by
+    pub decrement_allowance: u64,
+    pub bump: u8,
+}
+
+impl DelegateAccount {
+    /// Serialized size of the delegate record
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 1;
+}
+
+/// Seed prefix of role PDAs: `[ROLES_SEED, counter]`
+pub const ROLES_SEED: &[u8] = b"roles";
+
+/// Permission groups checked by the counter handlers
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// May increment the counter
+    Incrementer,
+    /// May decrement the counter
+    Decrementer,
+    /// May reset or close the counter and manage role holders
+    Admin,
+}
+
+/// Keys holding a role, or everyone when `public` is set
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct RoleHolders {
+    pub public: bool,
+    pub holders: Vec<Pubkey>,
+}
+
+impl RoleHolders {
+    /// Whether `key` holds this role
+    pub fn contains(&self, key: &Pubkey) -> bool {
+        self.public || self.holders.contains(key)
+    }
+}
+
+/// Role assignments attached to a counter.
+/// The counter authority implicitly holds every role.
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct RolesAccount {
+    pub counter: Pubkey,
+    pub incrementers: RoleHolders,
+    pub decrementers: RoleHolders,
+    pub admins: RoleHolders,
+    pub bump: u8,
+}
+
+impl RolesAccount {
+    /// Holders of the given role
+    pub fn holders(&self, role: Role) -> &RoleHolders {
+        match role {
+            Role::Incrementer => &self.incrementers,
+            Role::Decrementer => &self.decrementers,
+            Role::Admin => &self.admins,
+        }
+    }
+
+    /// Mutable holders of the given role
+    pub fn holders_mut(&mut self, role: Role) -> &mut RoleHolders {
+        match role {
+            Role::Incrementer => &mut self.incrementers,
+            Role::Decrementer => &mut self.decrementers,
+            Role::Admin => &mut self.admins,
+        }
+    }
+}
+
+/// Derive the roles account address for a counter
+pub fn find_roles_address(program_id: &Pubkey, counter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ROLES_SEED, counter.as_ref()], program_id)
+}
+
+/// Derive the delegate record address for a counter and delegate
+pub fn find_delegate_address(program_id: &Pubkey, counter: &Pubkey, delegate: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[DELEGATE_SEED, counter.as_ref(), delegate.as_ref()],
+        program_id,
+    )
+}
+
+/// Seed prefix of the PDA minting tokenized counter units: `[MINT_AUTHORITY_SEED, counter]`
+pub const MINT_AUTHORITY_SEED: &[u8] = b"mint_authority";
+
+/// Derive the mint authority address of a tokenized counter
+pub fn find_mint_authority_address(program_id: &Pubkey, counter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MINT_AUTHORITY_SEED, counter.as_ref()], program_id)
+}
+
+/// Seed prefix of the extra account list PDA a hook program keeps for each counter:
+/// `[EXTRA_ACCOUNT_METAS_SEED, counter]`, derived from and owned by the hook program
+pub const EXTRA_ACCOUNT_METAS_SEED: &[u8] = b"extra-account-metas";
+
+/// Derive the extra account list address of a counter under its hook program
+pub fn find_extra_account_metas_address(hook_program: &Pubkey, counter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[EXTRA_ACCOUNT_METAS_SEED, counter.as_ref()], hook_program)
+}
+
+/// Account a hook program needs beyond the counter, signer and extra account list
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtraAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl From<&ExtraAccountMeta> for AccountMeta {
+    fn from(meta: &ExtraAccountMeta) -> Self {
+        AccountMeta {
+            pubkey: meta.pubkey,
+            is_signer: meta.is_signer,
+            is_writable: meta.is_writable,
+        }
+    }
+}
+
+/// Extra accounts of a hook, stored by the hook program at its extra account list PDA.
+/// Clients append `accounts` after the PDA when building increments and decrements.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, Default, ShankAccount)]
+pub struct ExtraAccountMetaList {
+    pub accounts: Vec<ExtraAccountMeta>,
+}
+
+/// When a hook runs relative to the counter change
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+    Pre,
+    Post,
+}
+
+/// Instruction the counter program sends to a hook program.
+///
+/// Accounts passed to the hook:
+/// - `[]` Counter account
+/// - `[]` Signer of the increment or decrement (not marked as signer)
+/// - `[]` Extra account list PDA
+/// - Extra accounts, with the flags from the list
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum HookInstruction {
+    /// Validate (or react to) a counter changing from `old_value` to `new_value`;
+    /// an error aborts the change
+    Execute {
+        phase: HookPhase,
+        old_value: u64,
+        new_value: u64,
+        signer: Pubkey,
+    },
+}
+
+/// Seed prefix of the pending change PDA of a counter: `[SCHEDULED_CHANGE_SEED, counter]`
+pub const SCHEDULED_CHANGE_SEED: &[u8] = b"scheduled";
+
+/// Derive the pending change address of a counter
+pub fn find_scheduled_change_address(program_id: &Pubkey, counter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SCHEDULED_CHANGE_SEED, counter.as_ref()], program_id)
+}
+
+/// Longest timelock delay a counter may have, 30 days in seconds
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * 24 * 60 * 60;
+
+/// Authority action that can be scheduled behind a counter's timelock
+///
+/// Each variant mirrors the instruction that a timelocked counter rejects; authority
+/// decrements, batches and transfers are scheduled as `SetCounter`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ScheduledAction {
+    /// Overwrite the count. Executed with the program of every armed trigger, in slot order
+    SetCounter { value: u64 },
+    /// Set the count to zero
+    Reset,
+    SetIncrementFee { fee_lamports: u64, treasury: Pubkey },
+    SetTokenGate { mint: Pubkey, min_balance: u64 },
+    /// Change the timelock delay, including lowering or removing it
+    SetTimelock { delay: i64 },
+    Freeze,
+    Thaw,
+    /// Executed with the current authority (writable, refunded its registry entry), its
+    /// registry, the new authority's registry, a signing payer and the system program
+    SetAuthority { new_authority: Pubkey },
+    SetTokenPayment {
+        mint: Pubkey,
+        amount_per_step: u64,
+        mode: TokenPaymentMode,
+        destination: Pubkey,
+    },
+    /// Executed with the units mint, unless tokenization is turned off
+    SetUnitsMint { mint: Pubkey },
+    SetTrigger {
+        index: u8,
+        threshold: u64,
+        program: Pubkey,
+        data: Vec<u8>,
+    },
+    SetHook { program: Pubkey, pre: bool, post: bool },
+    SetExpiry { expires_at: i64 },
+    /// Executed with the metadata PDA, a signing payer and the system program
+    UpdateMetadata { metadata: CounterMetadata },
+    /// Executed with the delegate record PDA, a signing payer and the system program
+    ApproveDelegate {
+        delegate: Pubkey,
+        increment_allowance: u64,
+        decrement_allowance: u64,
+    },
+    /// Executed with the authority (writable, refunded the record rent) and the delegate record PDA
+    RevokeDelegate { delegate: Pubkey },
+    /// Executed with the roles PDA, a signing payer and the system program
+    InitializeRoles,
+    /// Executed with the roles PDA, a signing payer and the system program
+    AddRoleHolder { role: Role, holder: Pubkey },
+    /// Executed with the roles PDA, the authority (writable, refunded the freed rent) and the system program
+    RemoveRoleHolder { role: Role, holder: Pubkey },
+    /// Executed with the roles PDA
+    SetRolePublic { role: Role, public: bool },
+    /// Close the counter with its PDAs, sending their rent to `destination`. Executed with
+    /// the destination, the authority's registry, the roles PDA and the metadata PDA.
+    Close { destination: Pubkey },
+}
+
+/// Pending change PDA derived from ["scheduled", counter], sized to its contents
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankAccount)]
+pub struct ScheduledChangeAccount {
+    pub counter: Pubkey,
+    /// Counter authority that scheduled the change; it must still be the authority at execution
+    pub authority: Pubkey,
+    /// Receives the rent once the change is executed or cancelled
+    pub rent_payer: Pubkey,
+    /// Unix timestamp from which anyone may execute the change
+    pub execute_after: i64,
+    pub action: ScheduledAction,
+    pub bump: u8,
+}
+
+/// Available instructions for the counter program
+///
+/// Optional accounts are omitted when unused rather than replaced by the program id.
+/// Each variant has a fixed legacy tag, its Borsh discriminant, and a stable 8-byte
+/// discriminator in `INSTRUCTION_DISCRIMINATORS`; see `CounterInstruction::pack`. The
+/// single-byte tag format only carries the `LegacyInstruction` payloads of the first release.
+#[derive(BorshSerialize, BorshDeserialize, Debug, ShankInstruction)]
+#[borsh(use_discriminant = true)]
+#[legacy_optional_accounts_strategy]
+#[repr(u8)]
+pub enum CounterInstruction {
+    /// Initialize counter with starting value and optional name/description
+    #[account(0, writable, signer, name = "counter", desc = "Counter account (to be created)")]
+    #[account(1, writable, signer, name = "payer", desc = "Payer account")]
+    #[account(2, name = "system_program", desc = "System program")]
+    #[account(3, writable, name = "registry", desc = "Payer's counter registry PDA")]
+    #[account(4, writable, optional, name = "metadata", desc = "Metadata PDA (only when metadata is passed)")]
+    #[account(5, name = "config", desc = "Config PDA, passed after all other accounts")]
+    InitializeCounter {
+        initial_value: u64,
+        metadata: Option<CounterMetadata>,
+        /// Entries kept in the history extension (0 = no history)
+        history_capacity: u16,
+    } = 0,
+    /// Increment counter by specified step (None = default step from the program config)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority, role holder or approved delegate (a multisig authority is followed by its signers)")]
+    #[account(2, writable, signer, optional, name = "fee_payer", desc = "Pays the increment fee (only when the counter charges one)")]
+    #[account(3, writable, optional, name = "treasury", desc = "Treasury of the counter (only when the counter charges a fee)")]
+    #[account(4, optional, name = "system_program", desc = "System program (only when the counter charges a fee)")]
+    #[account(5, optional, name = "gate_token_account", desc = "Signer's token account of the gate mint (only for token-gated counters)")]
+    #[account(6, writable, optional, name = "payment_source", desc = "Signer's token account paying for the increment (only when the counter charges tokens)")]
+    #[account(7, writable, optional, name = "payment_destination", desc = "Payment destination, or the payment mint when burning (only when the counter charges tokens)")]
+    #[account(8, optional, name = "token_program", desc = "SPL token program (only when the counter charges tokens)")]
+    #[account(9, writable, optional, name = "units_mint", desc = "Units mint of the counter (only for tokenized counters)")]
+    #[account(10, writable, optional, name = "units_destination", desc = "Token account receiving the minted units (only for tokenized counters)")]
+    #[account(11, optional, name = "mint_authority", desc = "Mint authority PDA [\"mint_authority\", counter] (only for tokenized counters)")]
+    #[account(12, optional, name = "units_token_program", desc = "SPL token program (only for tokenized counters)")]
+    #[account(13, optional, name = "trigger_programs", desc = "Program of every armed trigger, in slot order (only for counters with armed triggers)")]
+    #[account(14, optional, name = "hook_program", desc = "Hook program (only for counters with a hook)")]
+    #[account(15, optional, name = "extra_account_metas", desc = "Extra account list PDA of the hook, followed by the listed accounts (only for counters with a hook)")]
+    #[account(16, writable, optional, name = "roles_or_delegate", desc = "Roles account or delegate record (only when not signed by the authority)")]
+    #[account(17, name = "config", desc = "Config PDA, passed after all other accounts")]
+    IncrementCounter {
+        step: Option<u64>
+    } = 1,
+    /// Decrement counter by specified step (None = default step from the program config)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority, role holder or approved delegate (a multisig authority is followed by its signers)")]
+    #[account(2, optional, name = "gate_token_account", desc = "Signer's token account of the gate mint (only for token-gated counters)")]
+    #[account(3, writable, optional, name = "units_source", desc = "Signer's token account the units are burned from (only for tokenized counters)")]
+    #[account(4, writable, optional, name = "units_mint", desc = "Units mint of the counter (only for tokenized counters)")]
+    #[account(5, optional, name = "units_token_program", desc = "SPL token program (only for tokenized counters)")]
+    #[account(6, optional, name = "hook_program", desc = "Hook program (only for counters with a hook)")]
+    #[account(7, optional, name = "extra_account_metas", desc = "Extra account list PDA of the hook, followed by the listed accounts (only for counters with a hook)")]
+    #[account(8, writable, optional, name = "roles_or_delegate", desc = "Roles account or delegate record (only when not signed by the authority)")]
+    #[account(9, name = "config", desc = "Config PDA, passed after all other accounts")]
+    DecrementCounter {
+        step: Option<u64>
+    } = 2,
+    /// Apply operations to the counter accounts at the given indexes
+    #[account(0, writable, name = "counter", desc = "Counter accounts referenced by the operations, one per index up to the highest")]
+    #[account(1, optional_signer, name = "authority", desc = "Authority of every counter (a multisig authority is followed by its signers after the counter extras)")]
+    #[account(2, optional, name = "counter_extras", desc = "For each counter in order: the program of every armed trigger in slot order, then its hook program, extra account list PDA and listed accounts (only for counters with armed triggers or a hook)")]
+    #[account(3, name = "config", desc = "Config PDA, passed after all other accounts")]
+    Batch {
+        ops: Vec<(u8, CounterOp)>
+    } = 3,
+    /// Move value between two counters of the same authority
+    #[account(0, writable, name = "source", desc = "Source counter account")]
+    #[account(1, writable, name = "destination", desc = "Destination counter account")]
+    #[account(2, optional_signer, name = "authority", desc = "Authority of both counters (a multisig authority is followed by its signers after the hook and trigger accounts)")]
+    #[account(3, optional, name = "source_hook_program", desc = "Hook program of the source (only when the source has a hook)")]
+    #[account(4, optional, name = "source_extra_account_metas", desc = "Extra account list PDA of the source hook, followed by the listed accounts (only when the source has a hook)")]
+    #[account(5, optional, name = "trigger_programs", desc = "Program of every armed trigger of the destination, in slot order (only when the destination has armed triggers)")]
+    #[account(6, optional, name = "destination_hook_program", desc = "Hook program of the destination (only when the destination has a hook)")]
+    #[account(7, optional, name = "destination_extra_account_metas", desc = "Extra account list PDA of the destination hook, followed by the listed accounts (only when the destination has a hook)")]
+    #[account(8, name = "config", desc = "Config PDA, passed after all other accounts")]
+    TransferBetweenCounters {
+        amount: u64
+    } = 4,
+    /// Grant (or replace) a delegate's increment/decrement allowances
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, writable, signer, name = "authority", desc = "Counter authority (pays for the delegate record)")]
+    #[account(2, name = "delegate", desc = "Delegate")]
+    #[account(3, writable, name = "delegate_record", desc = "Delegate record PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA, passed after all other accounts")]
+    ApproveDelegate {
+        increment_allowance: u64,
+        decrement_allowance: u64,
+    } = 5,
+    /// Remove a delegate and close its record
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, writable, optional_signer, name = "authority", desc = "Counter authority (receives the record rent) (a multisig authority is followed by its signers)")]
+    #[account(2, name = "delegate", desc = "Delegate")]
+    #[account(3, writable, name = "delegate_record", desc = "Delegate record PDA")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    RevokeDelegate = 6,
+    /// Create the roles account of a counter with no role holders
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, writable, signer, name = "authority", desc = "Counter authority (pays for the roles account)")]
+    #[account(2, writable, name = "roles", desc = "Roles PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    InitializeRoles = 7,
+    /// Grant a role to a key
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, writable, optional_signer, name = "authority", desc = "Counter authority or admin (pays for the extra space) (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "roles", desc = "Roles PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    AddRoleHolder {
+        role: Role,
+        holder: Pubkey,
+    } = 8,
+    /// Take a role away from a key
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, writable, optional_signer, name = "authority", desc = "Counter authority or admin (receives the freed rent) (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "roles", desc = "Roles PDA")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    RemoveRoleHolder {
+        role: Role,
+        holder: Pubkey,
+    } = 9,
+    /// Open a role to everyone (or close it again)
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority or admin (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "roles", desc = "Roles PDA")]
+    #[account(3, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetRolePublic {
+        role: Role,
+        public: bool,
+    } = 10,
+    /// Set the counter back to zero
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority or admin (a multisig authority is followed by its signers)")]
+    #[account(2, optional, name = "roles", desc = "Roles account (only when not signed by the authority)")]
+    #[account(3, name = "config", desc = "Config PDA, passed after all other accounts")]
+    ResetCounter = 11,
+    /// Close the counter account with its roles, metadata and pending change PDAs and reclaim their rent; delegates have to be revoked first
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority or admin (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "destination", desc = "Destination for the reclaimed rent")]
+    #[account(3, writable, name = "registry", desc = "Counter registry PDA of the authority")]
+    #[account(4, writable, name = "roles", desc = "Roles PDA, closed if it exists and checked for the admin role when not signed by the authority")]
+    #[account(5, writable, name = "metadata", desc = "Metadata PDA, closed if it exists")]
+    #[account(6, writable, name = "scheduled_change", desc = "Pending change PDA, closed if it exists")]
+    #[account(7, name = "config", desc = "Config PDA, passed after all other accounts")]
+    CloseCounter = 12,
+    /// Create an M-of-N multisig authority from the passed signer keys
+    #[account(0, writable, signer, name = "multisig", desc = "Multisig account (to be created)")]
+    #[account(1, writable, signer, name = "payer", desc = "Payer account")]
+    #[account(2, name = "system_program", desc = "System program")]
+    #[account(3, name = "signer_keys", desc = "Keys allowed to sign for the multisig, one account per key")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    InitializeMultisig {
+        threshold: u8,
+    } = 13,
+    /// Hand the counter over to a new authority
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, writable, optional_signer, name = "authority", desc = "Current counter authority, refunded the rent of its registry entry (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "registry", desc = "Counter registry PDA of the current authority")]
+    #[account(3, writable, name = "new_registry", desc = "Counter registry PDA of the new authority")]
+    #[account(4, writable, signer, name = "payer", desc = "Payer for the new registry entry")]
+    #[account(5, name = "system_program", desc = "System program")]
+    #[account(6, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetAuthority {
+        new_authority: Pubkey,
+    } = 14,
+    /// Stop all mutations of the counter until it is thawed
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    FreezeCounter = 15,
+    /// Allow mutations of a frozen counter again
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    ThawCounter = 16,
+    /// Create the program-wide config with the signer as admin
+    #[account(0, writable, name = "config", desc = "Config PDA")]
+    #[account(1, writable, signer, name = "admin", desc = "Admin (the program upgrade authority, pays for the config account)")]
+    #[account(2, name = "program_data", desc = "ProgramData account of this program under the upgradeable loader")]
+    #[account(3, name = "system_program", desc = "System program")]
+    InitializeConfig = 17,
+    /// Replace the config admin and defaults
+    #[account(0, writable, name = "config", desc = "Config PDA")]
+    #[account(1, signer, name = "admin", desc = "Config admin")]
+    UpdateConfig {
+        admin: Pubkey,
+        default_step: u64,
+        default_overflow_mode: OverflowMode,
+    } = 18,
+    /// Turn the emergency pause switch on or off
+    #[account(0, writable, name = "config", desc = "Config PDA")]
+    #[account(1, signer, name = "admin", desc = "Config admin")]
+    SetPaused {
+        paused: bool,
+    } = 19,
+    /// Set the counter name and description (authority only)
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "metadata", desc = "Metadata PDA")]
+    #[account(3, writable, signer, name = "payer", desc = "Pays for metadata growth and receives the rent of a shrink")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA, passed after all other accounts")]
+    UpdateMetadata {
+        metadata: CounterMetadata,
+    } = 20,
+    /// Charge a lamport fee to the treasury on every increment (0 = free)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetIncrementFee {
+        fee_lamports: u64,
+        treasury: Pubkey,
+    } = 21,
+    /// Only let holders of `min_balance` tokens of `mint` increment and decrement (default mint = ungated)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetTokenGate {
+        mint: Pubkey,
+        min_balance: u64,
+    } = 22,
+    /// Charge `amount_per_step` tokens of `mint` per unit of step on every increment (0 = free)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetTokenPayment {
+        mint: Pubkey,
+        amount_per_step: u64,
+        mode: TokenPaymentMode,
+        /// Token account receiving transferred payments (ignored when burning)
+        destination: Pubkey,
+    } = 23,
+    /// Tie the counter to a mint whose authority is the counter's mint authority PDA,
+    /// minting units on increment and burning them on decrement (default mint = off)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, optional, name = "mint", desc = "Units mint with a supply equal to the count (omitted when turning tokenization off)")]
+    #[account(3, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetUnitsMint {
+        mint: Pubkey,
+    } = 24,
+    /// Store a milestone trigger in a slot, re-arming it (default program = clear the slot)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetTrigger {
+        /// Slot index, below `MAX_TRIGGERS`
+        index: u8,
+        threshold: u64,
+        program: Pubkey,
+        /// Instruction data, at most `MAX_TRIGGER_DATA_LEN` bytes
+        data: Vec<u8>,
+    } = 25,
+    /// Invoke a hook program before and/or after every increment and decrement
+    /// (default program = no hook)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetHook {
+        program: Pubkey,
+        pre: bool,
+        post: bool,
+    } = 26,
+    /// Set the unix timestamp after which the counter expires (0 = never)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetExpiry {
+        expires_at: i64,
+    } = 27,
+    /// Close an expired counter with its roles, metadata, pending change and delegate record PDAs, refunding its rent payer (callable by anyone)
+    #[account(0, writable, name = "counter", desc = "Expired counter account")]
+    #[account(1, writable, name = "rent_payer", desc = "Rent payer recorded at initialization")]
+    #[account(2, writable, name = "registry", desc = "Counter registry PDA of the authority")]
+    #[account(3, writable, name = "roles", desc = "Roles PDA, closed if it exists")]
+    #[account(4, writable, name = "metadata", desc = "Metadata PDA, closed if it exists")]
+    #[account(5, writable, name = "scheduled_change", desc = "Pending change PDA, closed if it exists")]
+    #[account(6, optional, writable, name = "delegate_records", desc = "Every open delegate record of the counter")]
+    #[account(7, name = "config", desc = "Config PDA, passed after all other accounts")]
+    CloseExpiredCounter = 28,
+    /// Raise the timelock delay of a counter, up to `MAX_TIMELOCK_DELAY`; lowering it has to be scheduled (authority only)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, name = "config", desc = "Config PDA, passed after all other accounts")]
+    SetTimelock {
+        delay: i64,
+    } = 29,
+    /// Record an action that anyone may execute once the timelock delay has passed (authority only)
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "scheduled_change", desc = "Pending change PDA [\"scheduled\", counter]")]
+    #[account(3, writable, signer, name = "payer", desc = "Pays for the pending change account")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "config", desc = "Config PDA, passed after all other accounts")]
+    ScheduleChange {
+        action: ScheduledAction,
+    } = 30,
+    /// Apply a pending change whose delay has passed (callable by anyone)
+    #[account(0, writable, name = "counter", desc = "Counter account")]
+    #[account(1, writable, name = "scheduled_change", desc = "Pending change PDA")]
+    #[account(2, writable, name = "rent_payer", desc = "Payer of the pending change, refunded on execution")]
+    #[account(3, optional, name = "action_accounts", desc = "Accounts the scheduled action needs, listed on its `ScheduledAction` variant")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    ExecuteChange = 31,
+    /// Drop a pending change (authority only)
+    #[account(0, name = "counter", desc = "Counter account")]
+    #[account(1, optional_signer, name = "authority", desc = "Counter authority (a multisig authority is followed by its signers)")]
+    #[account(2, writable, name = "scheduled_change", desc = "Pending change PDA")]
+    #[account(3, writable, name = "rent_payer", desc = "Payer of the pending change, refunded on cancellation")]
+    #[account(4, name = "config", desc = "Config PDA, passed after all other accounts")]
+    CancelChange = 32,
+    /// Resize a baseline counter that only holds its count to the current layout, assigning its first authority (counter keypair)
+    #[account(0, writable, signer, name = "counter", desc = "Baseline counter account")]
+    #[account(1, name = "authority", desc = "Authority assigned to the counter")]
+    #[account(2, writable, signer, name = "payer", desc = "Pays the rent for the extra space")]
+    #[account(3, name = "system_program", desc = "System program")]
+    #[account(4, writable, name = "registry", desc = "Counter registry PDA of the assigned authority")]
+    #[account(5, name = "config", desc = "Config PDA, passed after all other accounts")]
+    MigrateCounter = 33,
+}
+
+/// Stable 8-byte discriminator of every instruction, indexed by its legacy tag
+///
+/// Each entry is the first 8 bytes of `sha256("global:<snake_case_name>")`, the same
+/// scheme Anchor uses, so it does not depend on where the variant sits in the enum.
+pub const INSTRUCTION_DISCRIMINATORS: [[u8; 8]; 34] = [
+    [67, 89, 100, 87, 231, 172, 35, 124], // initialize_counter
+    [16, 125, 2, 171, 73, 24, 207, 229], // increment_counter
+    [169, 37, 242, 230, 169, 126, 245, 38], // decrement_counter
+    [198, 211, 248, 40, 165, 37, 21, 148], // batch
+    [38, 71, 171, 168, 25, 151, 114, 202], // transfer_between_counters
+    [68, 6, 248, 64, 195, 222, 182, 223], // approve_delegate
+    [142, 66, 98, 126, 102, 60, 92, 163], // revoke_delegate
+    [164, 43, 91, 219, 220, 247, 50, 96], // initialize_roles
+    [156, 205, 179, 194, 136, 250, 105, 95], // add_role_holder
+    [25, 78, 125, 133, 147, 20, 6, 176], // remove_role_holder
+    [13, 29, 159, 223, 119, 19, 75, 156], // set_role_public
+    [245, 154, 57, 72, 251, 63, 131, 79], // reset_counter
+    [4, 236, 52, 248, 107, 146, 187, 49], // close_counter
+    [220, 130, 117, 21, 27, 227, 78, 213], // initialize_multisig
+    [133, 250, 37, 21, 110, 163, 26, 121], // set_authority
+    [246, 130, 129, 146, 70, 93, 26, 248], // freeze_counter
+    [212, 187, 82, 96, 228, 237, 24, 18], // thaw_counter
+    [208, 127, 21, 1, 194, 190, 196, 70], // initialize_config
+    [29, 158, 252, 191, 10, 83, 219, 99], // update_config
+    [91, 60, 125, 192, 176, 225, 166, 218], // set_paused
+    [170, 182, 43, 239, 97, 78, 225, 186], // update_metadata
+    [175, 126, 203, 168, 211, 156, 5, 242], // set_increment_fee
+    [181, 246, 120, 133, 255, 105, 150, 113], // set_token_gate
+    [196, 14, 176, 74, 11, 226, 153, 79], // set_token_payment
+    [164, 174, 70, 191, 40, 174, 180, 79], // set_units_mint
+    [136, 163, 123, 35, 241, 129, 143, 95], // set_trigger
+    [175, 16, 187, 252, 19, 54, 111, 221], // set_hook
+    [3, 38, 117, 33, 73, 152, 213, 179], // set_expiry
+    [135, 82, 179, 90, 228, 135, 140, 50], // close_expired_counter
+    [131, 159, 222, 21, 225, 226, 54, 214], // set_timelock
+    [58, 73, 61, 73, 6, 51, 136, 197], // schedule_change
+    [104, 53, 136, 238, 82, 222, 200, 42], // execute_change
+    [100, 30, 4, 148, 3, 244, 243, 168], // cancel_change
+    [125, 187, 162, 63, 224, 58, 250, 215], // migrate_counter
+];
+
+/// Single-byte tags below this one are decoded as a `LegacyInstruction`
+///
+/// Only InitializeCounter (0), IncrementCounter (1) and DecrementCounter (2) were ever
+/// deployed in the single-byte tag format; no discriminator starts with one of their tags,
+/// so the formats cannot collide.
+pub const LEGACY_TAG_COUNT: u8 = 3;
+
+/// Instructions of the first release, in its single-byte tag format with its Borsh payloads
+///
+/// Clients of that release pass `[counter, payer, system_program]` to InitializeCounter,
+/// which creates a baseline counter of `BASELINE_COUNTER_LEN` bytes, and only `[counter]` to
+/// IncrementCounter and DecrementCounter. The config PDA is optional after these accounts;
+/// when passed, the pause switch applies. Baseline counters keep the original semantics:
+/// anyone may step them, the default step is 1 and overflow fails with `InvalidAccountData`.
+/// Steps on a counter in the current layout act like the matching `CounterInstruction` and
+/// take its accounts.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum LegacyInstruction {
+    /// Create a baseline counter with a starting value
+    InitializeCounter { initial_value: u64 },
+    /// Increment a counter by the step (None = default step)
+    IncrementCounter { step: Option<u64> },
+    /// Decrement a counter by the step (None = default step)
+    DecrementCounter { step: Option<u64> },
+}
+
+impl LegacyInstruction {
+    /// Decode a legacy tag and its payload, or `None` when the data does not start with one
+    pub fn unpack(data: &[u8]) -> Result<Option<Self>, ProgramError> {
+        match data.first() {
+            Some(&tag) if tag < LEGACY_TAG_COUNT => Self::try_from_slice(data)
+                .map(Some)
+                .map_err(|_| ProgramError::InvalidInstructionData),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the instruction acts on a baseline counter, which InitializeCounter always creates
+    fn targets_baseline(&self, program_id: &Pubkey, accounts: &[AccountInfo]) -> bool {
+        match self {
+            Self::InitializeCounter { .. } => true,
+            Self::IncrementCounter { .. } | Self::DecrementCounter { .. } => {
+                accounts.first().is_some_and(|counter_account| {
+                    counter_account.owner == program_id && counter_account.data_len() == BASELINE_COUNTER_LEN
+                })
+            }
+        }
+    }
+}
+
+impl From<LegacyInstruction> for CounterInstruction {
+    fn from(instruction: LegacyInstruction) -> Self {
+        match instruction {
+            LegacyInstruction::InitializeCounter { initial_value } => Self::InitializeCounter {
+                initial_value,
+                metadata: None,
+                history_capacity: 0,
+            },
+            LegacyInstruction::IncrementCounter { step } => Self::IncrementCounter { step },
+            LegacyInstruction::DecrementCounter { step } => Self::DecrementCounter { step },
+        }
+    }
+}
+
+/// Compact opcode of IncrementCounter: `[0xc0]` for the default step, else `[0xc0, varint step]`
+pub const COMPACT_INCREMENT_OPCODE: u8 = 0xc0;
+/// Compact opcode of DecrementCounter, followed by an optional varint step like increments
+pub const COMPACT_DECREMENT_OPCODE: u8 = 0xc1;
+/// Compact opcode of Batch, followed by `(account index, op kind, [varint])` until the end
+///
+/// Op kinds are 0 for a default increment, 1 for an increment by the varint step, 2 and 3
+/// likewise for decrements, and 4 for a set to the varint value.
+pub const COMPACT_BATCH_OPCODE: u8 = 0xc2;
+
+impl CounterInstruction {
+    /// Encode in the smallest format available: compact for increments, decrements and
+    /// batches, the 8-byte discriminator format for everything else
+    pub fn encode(&self) -> Vec<u8> {
+        self.pack_compact().unwrap_or_else(|| self.pack())
+    }
+
+    /// Encode as a single-byte opcode with varint arguments, if the instruction has a compact form
+    pub fn pack_compact(&self) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        match self {
+            Self::IncrementCounter { step } => {
+                data.push(COMPACT_INCREMENT_OPCODE);
+                if let Some(step) = step {
+                    write_varint(&mut data, *step);
+                }
+            }
+            Self::DecrementCounter { step } => {
+                data.push(COMPACT_DECREMENT_OPCODE);
+                if let Some(step) = step {
+                    write_varint(&mut data, *step);
+                }
+            }
+            Self::Batch { ops } => {
+                data.push(COMPACT_BATCH_OPCODE);
+                for (index, op) in ops {
+                    data.push(*index);
+                    let (kind, argument) = match op {
+                        CounterOp::Increment { step: None } => (0, None),
+                        CounterOp::Increment { step } => (1, *step),
+                        CounterOp::Decrement { step: None } => (2, None),
+                        CounterOp::Decrement { step } => (3, *step),
+                        CounterOp::Set { value } => (4, Some(*value)),
+                    };
+                    data.push(kind);
+                    if let Some(argument) = argument {
+                        write_varint(&mut data, argument);
+                    }
+                }
+            }
+            _ => return None,
+        }
+
+        Some(data)
+    }
+
+    /// Decode the compact format, or `None` when the data does not start with a compact opcode
+    fn unpack_compact(data: &[u8]) -> Result<Option<Self>, ProgramError> {
+        let Some((&opcode, mut rest)) = data.split_first() else {
+            return Ok(None);
+        };
+        let instruction = match opcode {
+            COMPACT_INCREMENT_OPCODE => Self::IncrementCounter {
+                step: read_optional_varint(&mut rest)?,
+            },
+            COMPACT_DECREMENT_OPCODE => Self::DecrementCounter {
+                step: read_optional_varint(&mut rest)?,
+            },
+            COMPACT_BATCH_OPCODE => {
+                let mut ops = Vec::new();
+                while let [index, kind, tail @ ..] = rest {
+                    rest = tail;
+                    let op = match kind {
+                        0 => CounterOp::Increment { step: None },
+                        1 => CounterOp::Increment {
+                            step: Some(read_varint(&mut rest)?),
+                        },
+                        2 => CounterOp::Decrement { step: None },
+                        3 => CounterOp::Decrement {
+                            step: Some(read_varint(&mut rest)?),
+                        },
+                        4 => CounterOp::Set {
+                            value: read_varint(&mut rest)?,
+                        },
+                        _ => return Err(ProgramError::InvalidInstructionData),
+                    };
+                    ops.push((*index, op));
+                }
+                Self::Batch { ops }
+            }
+            _ => return Ok(None),
+        };
+        if !rest.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Some(instruction))
+    }
+
+    /// Encode as the 8-byte discriminator followed by the Borsh-encoded fields
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = borsh::to_vec(self).expect("Instruction serialization cannot fail");
+        // The Borsh encoding starts with the legacy tag
+        let discriminator = INSTRUCTION_DISCRIMINATORS[data[0] as usize];
+        data.splice(..1, discriminator);
+        data
+    }
+
+    /// Decode instruction data in the compact or 8-byte discriminator format
+    ///
+    /// Compact opcodes are told apart by their first byte, and data whose first 8 bytes match
+    /// a discriminator must decode as that instruction. Single-byte legacy tags are decoded by
+    /// `LegacyInstruction::unpack` instead.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if let Some(instruction) = Self::unpack_compact(data)? {
+            return Ok(instruction);
+        }
+
+        let tag = data.get(..8).and_then(|prefix| {
+            INSTRUCTION_DISCRIMINATORS
+                .iter()
+                .position(|discriminator| discriminator == prefix)
+        });
+        if let Some(tag) = tag {
+            let legacy_tag = [tag as u8];
+            let mut reader = std::io::Read::chain(&legacy_tag[..], &data[8..]);
+            return borsh::from_reader(&mut reader).map_err(|_| ProgramError::InvalidInstructionData);
+        }
+
+        Err(ProgramError::InvalidInstructionData)
+    }
+}
+
+/// Append `value` as an unsigned LEB128 varint
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+/// Read an unsigned LEB128 varint, rejecting overlong and overflowing encodings
+fn read_varint(data: &mut &[u8]) -> Result<u64, ProgramError> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().enumerate() {
+        let bits = u64::from(byte & 0x7f);
+        let shift = 7 * index as u32;
+        if shift >= 64 || (bits << shift) >> shift != bits {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            // A trailing zero byte would give the same value a second encoding
+            if *byte == 0 && index > 0 {
+                return Err(ProgramError::InvalidInstructionData);
+            }
+            *data = &data[index + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(ProgramError::InvalidInstructionData)
+}
+
+/// Read a varint if any data is left
+fn read_optional_varint(data: &mut &[u8]) -> Result<Option<u64>, ProgramError> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    read_varint(data).map(Some)
+}
+
+/// Build a counter instruction, encoded with `CounterInstruction::encode`
+pub fn counter_instruction(
+    program_id: &Pubkey,
+    instruction: &CounterInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &instruction.encode(), accounts)
+}
+
+/// Build an IncrementCounter signed by a single-key authority, in the compact format
+///
+/// Counters with fees, gates, payments, units, triggers or hooks need more accounts;
+/// build those with `counter_instruction`.
+pub fn increment_counter(program_id: &Pubkey, counter: &Pubkey, authority: &Pubkey, step: Option<u64>) -> Instruction {
+    counter_instruction(
+        program_id,
+        &CounterInstruction::IncrementCounter { step },
+        vec![
+            AccountMeta::new(*counter, false),
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Build a DecrementCounter signed by a single-key authority, in the compact format
+pub fn decrement_counter(program_id: &Pubkey, counter: &Pubkey, authority: &Pubkey, step: Option<u64>) -> Instruction {
+    counter_instruction(
+        program_id,
+        &CounterInstruction::DecrementCounter { step },
+        vec![
+            AccountMeta::new(*counter, false),
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Build a Batch over `counters` signed by their single-key authority, in the compact format
+pub fn batch(program_id: &Pubkey, counters: &[Pubkey], authority: &Pubkey, ops: Vec<(u8, CounterOp)>) -> Instruction {
+    let mut accounts: Vec<AccountMeta> = counters
+        .iter()
+        .map(|counter| AccountMeta::new(*counter, false))
+        .collect();
+    accounts.push(AccountMeta::new_readonly(*authority, true));
+    accounts.push(AccountMeta::new_readonly(find_config_address(program_id).0, false));
+
+    counter_instruction(program_id, &CounterInstruction::Batch { ops }, accounts)
+}
+
+/// Single counter operation applied by `CounterInstruction::Batch`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub enum CounterOp {
+    /// Increment counter by specified step (None = default step from the program config)
+    Increment { step: Option<u64> },
+    /// Decrement counter by specified step (None = default step from the program config)
+    Decrement { step: Option<u64> },
+    /// Overwrite counter with the given value
+    Set { value: u64 },
+}
+
+/// Errors returned by the counter program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum CounterError {
+    /// Delegate has less allowance left than the requested step
+    #[error("Insufficient delegate allowance")]
+    InsufficientAllowance,
+    /// Signer does not hold the role required by the instruction
+    #[error("Signer is missing the required role")]
+    MissingRole,
+    /// Counter is frozen and cannot be mutated
+    #[error("Counter is frozen")]
+    CounterFrozen,
+    /// Program is paused by the config admin
+    #[error("Program is paused")]
+    ProgramPaused,
+    /// Counter name or description exceeds its length limit
+    #[error("Counter metadata is too long")]
+    MetadataTooLong,
+    /// Signer's token account does not hold enough of the counter's gate mint
+    #[error("Signer does not hold the required gate token")]
+    MissingGateToken,
+    /// Counter value is backed by a token supply and can only change by minting or burning
+    #[error("Counter units are tokenized")]
+    CounterTokenized,
+    /// Counter has expired and only accepts being closed
+    #[error("Counter has expired")]
+    CounterExpired,
+    /// Counter has not expired yet
+    #[error("Counter has not expired")]
+    CounterNotExpired,
+    /// Counter is timelocked, the change has to be scheduled
+    #[error("Change has to be scheduled")]
+    TimelockRequired,
+    /// Scheduled change is still waiting for its delay
+    #[error("Scheduled change is not executable yet")]
+    TimelockPending,
+    /// Counter still has delegate records that have to be revoked first
+    #[error("Counter still has delegates")]
+    DelegatesOutstanding,
+}
+
+impl From<CounterError> for ProgramError {
+    fn from(error: CounterError) -> Self {
+        ProgramError::Custom(error as u32)
+    }
+}
+
+/// Event the program logs with `sol_log_data`, as its discriminator followed by
+/// the Borsh encoding of its fields, the same layout Anchor uses for events
+pub trait Event: BorshSerialize + BorshDeserialize {
+    /// First 8 bytes of `sha256("event:<Name>")`
+    const DISCRIMINATOR: [u8; 8];
+
+    /// Log the event
+    fn emit(&self) -> ProgramResult {
+        let mut data = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut data)?;
+        solana_program::log::sol_log_data(&[&data]);
+        Ok(())
+    }
+
+    /// Decode one `sol_log_data` entry, `None` when it holds another event
+    fn decode(data: &[u8]) -> Option<Self> {
+        let fields = data.strip_prefix(&Self::DISCRIMINATOR)?;
+        Self::try_from_slice(fields).ok()
+    }
+}
+
+/// A counter was created
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, ShankType)]
+pub struct CounterInitialized {
+    pub counter: Pubkey,
+    pub authority: Pubkey,
+    pub initial_value: u64,
+}
+
+impl Event for CounterInitialized {
+    const DISCRIMINATOR: [u8; 8] = [115, 205, 233, 189, 129, 219, 117, 64];
+}
+
+/// The count of a counter changed, emitted once per change like a history entry
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, ShankType)]
+pub struct CounterUpdated {
+    pub counter: Pubkey,
+    /// Key that made the change (the authority for scheduled changes)
+    pub signer: Pubkey,
+    pub old_value: u64,
+    pub new_value: u64,
+}
+
+impl Event for CounterUpdated {
+    const DISCRIMINATOR: [u8; 8] = [56, 210, 136, 13, 88, 67, 151, 167];
+}
+
+/// A counter was handed to a new authority
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, ShankType)]
+pub struct AuthorityChanged {
+    pub counter: Pubkey,
+    pub old_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+impl Event for AuthorityChanged {
+    const DISCRIMINATOR: [u8; 8] = [31, 19, 174, 152, 4, 82, 215, 226];
+}
+
+/// A counter was closed and its rent sent to `destination`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, ShankType)]
+pub struct CounterClosed {
+    pub counter: Pubkey,
+    pub destination: Pubkey,
+}
+
+impl Event for CounterClosed {
+    const DISCRIMINATOR: [u8; 8] = [61, 84, 59, 97, 131, 189, 51, 193];
+}
+
+/// Initialize a new counter account with starting value
+fn process_initialize_counter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    initial_value: u64,
+    metadata: Option<CounterMetadata>,
+    history_capacity: u16,
+    config: &ConfigAccount,
+) -> ProgramResult {
+    msg!("Initializing counter with value: {}", initial_value);
+    
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account (to be created)
+    // 1. [signer, writable] Payer account
+    // 2. [] System program
+    // 3. [writable] Payer's counter registry PDA
+    // 4. [writable] Metadata PDA (only when metadata is passed)
+    let counter_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    let registry_account = next_account_info(accounts_iter)?;
+
+    if let Some(metadata) = &metadata {
+        metadata.validate()?;
+    }
+    if history_capacity > MAX_HISTORY_CAPACITY {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Check if account is already initialized
+    if counter_account.data.borrow().len() > 0 {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // Calculate required account space and rent
+    let account_space = CounterAccount::LEN + history_space(history_capacity);
+    let rent = Rent::get()?;
+    let required_lamports = rent.minimum_balance(account_space);
+
+    // Create counter account via system program
+    invoke(
+        &system_instruction::create_account(
+            payer_account.key,
+            counter_account.key,
+            required_lamports,
+            account_space as u64,
+            program_id,
+        ),
+        &[
+            payer_account.clone(),
+            counter_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    // Initialize counter data
+    let counter_data = CounterAccount::new(
+        initial_value,
+        *payer_account.key,
+        config.default_overflow_mode,
+    );
+
+    // Write data into account
+    {
+        let mut account_data = counter_account.data.borrow_mut();
+        account_data[..CounterAccount::LEN].copy_from_slice(bytemuck::bytes_of(&counter_data));
+        if history_capacity > 0 {
+            let header = HistoryHeader {
+                capacity: history_capacity,
+                len: 0,
+                next: 0,
+            };
+            account_data[CounterAccount::LEN..CounterAccount::LEN + HistoryHeader::LEN]
+                .copy_from_slice(bytemuck::bytes_of(&header));
+        }
+    }
+
+    register_counter(
+        program_id,
+        registry_account,
+        payer_account.key,
+        counter_account.key,
+        payer_account,
+        system_program,
+    )?;
+
+    if let Some(metadata) = metadata {
+        let metadata_account = next_account_info(accounts_iter)?;
+        write_metadata_account(
+            program_id,
+            counter_account,
+            payer_account,
+            metadata_account,
+            system_program,
+            metadata,
+        )?;
+    }
+
+    CounterInitialized {
+        counter: *counter_account.key,
+        authority: *payer_account.key,
+        initial_value,
+    }
+    .emit()?;
+
+    msg!("Counter initialized successfully with value: {}", initial_value);
+    Ok(())
+}
+
+/// Process an instruction of the first release on a baseline counter
+fn process_legacy_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction: LegacyInstruction,
+) -> ProgramResult {
+    // Clients of the first release pass no config PDA. Newer clients may pass it after
+    // the original accounts to have the pause switch apply.
+    let original_accounts = match instruction {
+        LegacyInstruction::InitializeCounter { .. } => 3,
+        LegacyInstruction::IncrementCounter { .. } | LegacyInstruction::DecrementCounter { .. } => 1,
+    };
+    if let Some(config_account) = accounts.get(original_accounts) {
+        if load_config(program_id, config_account)?.paused {
+            msg!("Program is paused");
+            return Err(CounterError::ProgramPaused.into());
+        }
+    }
+
+    match instruction {
+        LegacyInstruction::InitializeCounter { initial_value } => {
+            process_initialize_baseline_counter(program_id, accounts, initial_value)
+        }
+        LegacyInstruction::IncrementCounter { step } => process_baseline_step(accounts, step, true),
+        LegacyInstruction::DecrementCounter { step } => process_baseline_step(accounts, step, false),
+    }
+}
+
+/// Create a baseline counter holding only its count, as the first release did
+fn process_initialize_baseline_counter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    initial_value: u64,
+) -> ProgramResult {
+    msg!("Initializing counter with value: {}", initial_value);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account (to be created)
+    // 1. [signer, writable] Payer account
+    // 2. [] System program
+    let counter_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    if !counter_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    invoke(
+        &system_instruction::create_account(
+            payer_account.key,
+            counter_account.key,
+            Rent::get()?.minimum_balance(BASELINE_COUNTER_LEN),
+            BASELINE_COUNTER_LEN as u64,
+            program_id,
+        ),
+        &[
+            payer_account.clone(),
+            counter_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+    counter_account.data.borrow_mut().copy_from_slice(&initial_value.to_le_bytes());
+
+    msg!("Counter initialized successfully with value: {}", initial_value);
+    Ok(())
+}
+
+/// Increment or decrement a baseline counter with the checked arithmetic of the first release
+fn process_baseline_step(accounts: &[AccountInfo], step: Option<u64>, increment: bool) -> ProgramResult {
+    // Baseline counters have no settings, so the default step stays 1
+    let step_value = step.unwrap_or(1);
+
+    // 0. [writable] Baseline counter account
+    let counter_account = next_account_info(&mut accounts.iter())?;
+
+    let mut data = counter_account.data.borrow_mut();
+    let count = u64::from_le_bytes(data[..].try_into().map_err(|_| ProgramError::InvalidAccountData)?);
+    let count = if increment {
+        count.checked_add(step_value)
+    } else {
+        count.checked_sub(step_value)
+    }
+    .ok_or(ProgramError::InvalidAccountData)?;
+    data.copy_from_slice(&count.to_le_bytes());
+
+    msg!("Counter updated to: {}", count);
+    Ok(())
+}
+
+/// Increment counter by specified step (default: 1)
+fn process_increment_counter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    step: Option<u64>,
+    default_step: u64,
+) -> ProgramResult {
+    // Use the configured default step if not specified
+    let step_value = step.unwrap_or(default_step);
+    msg!("Incrementing counter by: {}", step_value);
+    
+    let accounts_iter = &mut accounts.iter();
+    
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority or approved delegate
+    // 2. [signer, writable] Fee payer (only when the counter charges a fee)
+    // 3. [writable] Treasury (only when the counter charges a fee)
+    // 4. [] System program (only when the counter charges a fee)
+    // 5. [] Signer's token account of the gate mint (only for token-gated counters)
+    // 6. [writable] Signer's token account paying for the increment (only when the counter charges tokens)
+    // 7. [writable] Payment destination, or the payment mint when burning (only when the counter charges tokens)
+    // 8. [] SPL token program (only when the counter charges tokens)
+    // 9. [writable] Units mint (only for tokenized counters)
+    // 10. [writable] Token account receiving the minted units (only for tokenized counters)
+    // 11. [] Mint authority PDA (only for tokenized counters)
+    // 12. [] SPL token program (only for tokenized counters)
+    // 13.. [] Program of every armed trigger, in slot order
+    // 14. [] Hook program (only for counters with a hook)
+    // 15. [] Extra account list PDA of the hook, followed by the listed accounts (only for counters with a hook)
+    // 16. [writable] Roles account or delegate record (only when not signed by the authority)
+    //    or [signer] multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let signer_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+
+    // Deserialize counter data
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_frozen(&counter_data)?;
+    check_not_expired(&counter_data)?;
+
+    // Fee accounts are only passed for counters that charge a fee
+    let fee_accounts = if counter_data.fee_lamports() > 0 {
+        Some((
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+        ))
+    } else {
+        None
+    };
+    check_token_gate(&counter_data, signer_account, accounts_iter)?;
+
+    // Token payment accounts are only passed for counters that charge tokens
+    let payment_accounts = if counter_data.payment_per_step() > 0 {
+        Some((
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+        ))
+    } else {
+        None
+    };
+
+    // Units accounts are only passed for tokenized counters
+    let units_accounts = if counter_data.is_tokenized() {
+        Some((
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+        ))
+    } else {
+        None
+    };
+
+    let trigger_programs = next_trigger_programs(&counter_data, accounts_iter)?;
+    let hook_accounts = next_hook_accounts(counter_account.key, &counter_data, accounts_iter)?;
+
+    // A wrapping authority increment can land on any value, like a decrement
+    if signer_account.key == &counter_data.authority
+        && counter_data.overflow_mode()? == OverflowMode::Wrapping
+    {
+        check_not_timelocked(&counter_data)?;
+    }
+
+    // Check the signer may increment, spending delegate allowance if needed
+    authorize_counter_op(
+        program_id,
+        counter_account.key,
+        &counter_data,
+        signer_account,
+        accounts_iter,
+        &CounterOp::Increment { step: Some(step_value) },
+    )?;
+
+    // Increment counter following its overflow mode
+    let previous_count = counter_data.count();
+    let count = counter_data
+        .overflow_mode()?
+        .add(previous_count, step_value)?;
+    invoke_hook(&counter_data, &hook_accounts, counter_account, signer_account, HookPhase::Pre, previous_count, count)?;
+
+    if let Some((fee_payer, treasury_account, system_program)) = fee_accounts {
+        if *treasury_account.key != counter_data.treasury() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        invoke(
+            &system_instruction::transfer(
+                fee_payer.key,
+                treasury_account.key,
+                counter_data.fee_lamports(),
+            ),
+            &[fee_payer.clone(), treasury_account.clone(), system_program.clone()],
+        )?;
+        msg!("Charged increment fee of {} lamports", counter_data.fee_lamports());
+    }
+
+    if let Some((source, destination, token_program)) = payment_accounts {
+        pay_tokens(&counter_data, signer_account, source, destination, token_program, step_value)?;
+    }
+
+    // Mint exactly `step` units; the token program refuses any supply overflow, so
+    // saturating and wrapping counters cannot drift from their supply
+    if let Some((mint, destination, mint_authority, token_program)) = units_accounts {
+        if *mint.key != counter_data.units_mint() || *token_program.key != spl_token::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        invoke_signed(
+            &spl_token::instruction::mint_to(
+                token_program.key,
+                mint.key,
+                destination.key,
+                mint_authority.key,
+                &[],
+                step_value,
+            )?,
+            &[mint.clone(), destination.clone(), mint_authority.clone(), token_program.clone()],
+            &[&[
+                MINT_AUTHORITY_SEED,
+                counter_account.key.as_ref(),
+                &[counter_data.units_authority_bump],
+            ]],
+        )?;
+        msg!("Minted {} counter units", step_value);
+    }
+
+    // Update the count in place
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(count);
+    record_history(counter_account, signer_account.key, previous_count, count)?;
+    fire_triggers(counter_account, &counter_data, &trigger_programs, previous_count, count)?;
+    invoke_hook(&counter_data, &hook_accounts, counter_account, signer_account, HookPhase::Post, previous_count, count)?;
+
+    msg!("Counter incremented to: {}", count);
+    Ok(())
+}
+
+/// Decrement counter by specified step (default: 1)
+fn process_decrement_counter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    step: Option<u64>,
+    default_step: u64,
+) -> ProgramResult {
+    // Use the configured default step if not specified
+    let step_value = step.unwrap_or(default_step);
+    msg!("Decrementing counter by: {}", step_value);
+    
+    let accounts_iter = &mut accounts.iter();
+    
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority or approved delegate
+    // 2. [] Signer's token account of the gate mint (only for token-gated counters)
+    // 3. [writable] Signer's token account the units are burned from (only for tokenized counters)
+    // 4. [writable] Units mint (only for tokenized counters)
+    // 5. [] SPL token program (only for tokenized counters)
+    // 6. [] Hook program (only for counters with a hook)
+    // 7. [] Extra account list PDA of the hook, followed by the listed accounts (only for counters with a hook)
+    // 8. [writable] Roles account or delegate record (only when not signed by the authority)
+    //    or [signer] multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let signer_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+
+    // Deserialize counter data
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_frozen(&counter_data)?;
+    check_not_expired(&counter_data)?;
+    check_token_gate(&counter_data, signer_account, accounts_iter)?;
+
+    // Units accounts are only passed for tokenized counters
+    let units_accounts = if counter_data.is_tokenized() {
+        Some((
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+            next_account_info(accounts_iter)?,
+        ))
+    } else {
+        None
+    };
+    let hook_accounts = next_hook_accounts(counter_account.key, &counter_data, accounts_iter)?;
+
+    // An authority decrement can reset the counter, so a timelocked one schedules SetCounter instead
+    if signer_account.key == &counter_data.authority {
+        check_not_timelocked(&counter_data)?;
+    }
+
+    // Check the signer may decrement, spending delegate allowance if needed
+    authorize_counter_op(
+        program_id,
+        counter_account.key,
+        &counter_data,
+        signer_account,
+        accounts_iter,
+        &CounterOp::Decrement { step: Some(step_value) },
+    )?;
+
+    // Decrement counter following its overflow mode
+    let previous_count = counter_data.count();
+    let count = counter_data
+        .overflow_mode()?
+        .sub(previous_count, step_value)?;
+    invoke_hook(&counter_data, &hook_accounts, counter_account, signer_account, HookPhase::Pre, previous_count, count)?;
+
+    if let Some((source, mint, token_program)) = units_accounts {
+        if *mint.key != counter_data.units_mint() || *token_program.key != spl_token::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        invoke(
+            &spl_token::instruction::burn(
+                token_program.key,
+                source.key,
+                mint.key,
+                signer_account.key,
+                &[],
+                step_value,
+            )?,
+            &[source.clone(), mint.clone(), signer_account.clone(), token_program.clone()],
+        )?;
+        msg!("Burned {} counter units", step_value);
+    }
+
+    // Update the count in place
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(count);
+    record_history(counter_account, signer_account.key, previous_count, count)?;
+    invoke_hook(&counter_data, &hook_accounts, counter_account, signer_account, HookPhase::Post, previous_count, count)?;
+
+    msg!("Counter decremented to: {}", count);
+    Ok(())
+}
+
+/// Apply a list of operations across the passed counter accounts
+fn process_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    ops: Vec<(u8, CounterOp)>,
+    default_step: u64,
+) -> ProgramResult {
+    msg!("Applying batch of {} operations", ops.len());
+
+    // Accounts expected in order:
+    // 0..N. [writable] Counter accounts referenced by the operations (N = highest index)
+    // N+1. [signer] Authority of every counter
+    // N+2.. For each counter in order:
+    //    [] Program of every armed trigger, in slot order
+    //    [] Hook program, extra account list PDA and listed accounts (only for counters with a hook)
+    // then [signer] Multisig signers (only for a multisig authority)
+    let counter_count = ops
+        .iter()
+        .map(|(account_index, _)| *account_index as usize + 1)
+        .max()
+        .unwrap_or(0);
+    if accounts.len() <= counter_count {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (counter_accounts, remaining_accounts) = accounts.split_at(counter_count);
+    let accounts_iter = &mut remaining_accounts.iter();
+    let authority_account = next_account_info(accounts_iter)?;
+
+    // Validate every counter account once and take its trigger and hook accounts
+    let mut counters = Vec::with_capacity(counter_accounts.len());
+    for (index, counter_account) in counter_accounts.iter().enumerate() {
+        // The same counter passed twice would get two sets of trigger and hook accounts
+        if counter_accounts[..index].iter().any(|a| a.key == counter_account.key) {
+            return Err(ProgramError::InvalidArgument);
+        }
+        validate_counter_account(program_id, counter_account)?;
+        let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+        check_not_frozen(&counter_data)?;
+        check_not_expired(&counter_data)?;
+        check_not_tokenized(&counter_data)?;
+        // Batches are authority-only and can move a count anywhere
+        check_not_timelocked(&counter_data)?;
+        let trigger_programs = next_trigger_programs(&counter_data, accounts_iter)?;
+        let hook_accounts = next_hook_accounts(counter_account.key, &counter_data, accounts_iter)?;
+        counters.push((counter_data, trigger_programs, hook_accounts));
+    }
+    for (counter_data, _, _) in &counters {
+        validate_authority(
+            program_id,
+            &counter_data.authority,
+            authority_account,
+            accounts_iter.as_slice(),
+        )?;
+    }
+
+    // Apply the operations in order, each like a single increment, decrement or set
+    for (account_index, op) in ops.iter() {
+        let counter_account = &counter_accounts[*account_index as usize];
+        let (counter_data, trigger_programs, hook_accounts) = &counters[*account_index as usize];
+        let previous_count = CounterAccount::load(&counter_account.data.borrow())?.count();
+        let count = apply_counter_op(previous_count, op, counter_data.overflow_mode()?, default_step)?;
+
+        invoke_hook(counter_data, hook_accounts, counter_account, authority_account, HookPhase::Pre, previous_count, count)?;
+        CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(count);
+        record_history(counter_account, authority_account.key, previous_count, count)?;
+        fire_triggers(counter_account, counter_data, trigger_programs, previous_count, count)?;
+        invoke_hook(counter_data, hook_accounts, counter_account, authority_account, HookPhase::Post, previous_count, count)?;
+    }
+
+    msg!("Batch applied to {} counters", counters.len());
+    Ok(())
+}
+
+/// Decrement the source counter and increment the destination counter of the same authority by the same amount
+fn process_transfer_between_counters(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    msg!("Transferring {} between counters", amount);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Source counter account
+    // 1. [writable] Destination counter account
+    // 2. [signer] Authority of both counters
+    // 3. [] Hook program of the source (only when the source has a hook)
+    // 4. [] Extra account list PDA of the source hook, followed by the listed accounts (only when the source has a hook)
+    // 5.. [] Program of every armed trigger of the destination, in slot order
+    // 6. [] Hook program of the destination (only when the destination has a hook)
+    // 7. [] Extra account list PDA of the destination hook, followed by the listed accounts (only when the destination has a hook)
+    // 8.. [signer] Multisig signers (only for a multisig authority)
+    let source_account = next_account_info(accounts_iter)?;
+    let destination_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    // Transferring to the same counter would be overwritten on serialization
+    if source_account.key == destination_account.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    validate_counter_account(program_id, source_account)?;
+    validate_counter_account(program_id, destination_account)?;
+
+    let source_data = *CounterAccount::load(&source_account.data.borrow())?;
+    let destination_data = *CounterAccount::load(&destination_account.data.borrow())?;
+    check_not_frozen(&source_data)?;
+    check_not_frozen(&destination_data)?;
+    check_not_expired(&source_data)?;
+    check_not_expired(&destination_data)?;
+    check_not_tokenized(&source_data)?;
+    check_not_tokenized(&destination_data)?;
+    check_not_timelocked(&source_data)?;
+    check_not_timelocked(&destination_data)?;
+
+    // Raising the destination skips its roles, delegates, fees, gate and payment,
+    // so both counters must belong to the signing authority
+    if destination_data.authority != source_data.authority {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+    let source_hook_accounts = next_hook_accounts(source_account.key, &source_data, accounts_iter)?;
+    let trigger_programs = next_trigger_programs(&destination_data, accounts_iter)?;
+    let destination_hook_accounts = next_hook_accounts(destination_account.key, &destination_data, accounts_iter)?;
+    validate_authority(
+        program_id,
+        &source_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+
+    // Both sides are checked before anything is written back. Each side follows its own
+    // overflow mode, like a decrement of the source and an increment of the destination.
+    let source_count = source_data.overflow_mode()?.sub(source_data.count(), amount)?;
+    let destination_count = destination_data.overflow_mode()?.add(destination_data.count(), amount)?;
+
+    // Each side runs its hooks like a decrement and an increment by the authority
+    let sides = [
+        (source_account, &source_data, &source_hook_accounts, source_count),
+        (destination_account, &destination_data, &destination_hook_accounts, destination_count),
+    ];
+    for (counter_account, counter_data, hook_accounts, count) in sides {
+        invoke_hook(counter_data, hook_accounts, counter_account, authority_account, HookPhase::Pre, counter_data.count(), count)?;
+    }
+    for (counter_account, counter_data, _, count) in sides {
+        CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(count);
+        record_history(counter_account, authority_account.key, counter_data.count(), count)?;
+    }
+    fire_triggers(
+        destination_account,
+        &destination_data,
+        &trigger_programs,
+        destination_data.count(),
+        destination_count,
+    )?;
+    for (counter_account, counter_data, hook_accounts, count) in sides {
+        invoke_hook(counter_data, hook_accounts, counter_account, authority_account, HookPhase::Post, counter_data.count(), count)?;
+    }
+
+    msg!(
+        "Transfer complete, source: {}, destination: {}",
+        source_count,
+        destination_count
+    );
+    Ok(())
+}
+
+/// Create or update the delegate record granting allowances to a delegate
+fn process_approve_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    increment_allowance: u64,
+    decrement_allowance: u64,
+) -> ProgramResult {
+    msg!(
+        "Approving delegate with increment allowance {} and decrement allowance {}",
+        increment_allowance,
+        decrement_allowance
+    );
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer, writable] Counter authority (pays for the delegate record)
+    // 2. [] Delegate
+    // 3. [writable] Delegate record PDA
+    // 4. [] System program
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let delegate_account = next_account_info(accounts_iter)?;
+    let delegate_record_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    // The authority pays for the record, so it must be a single signer
+    validate_authority(program_id, &counter_data.authority, authority_account, &[])?;
+    check_not_timelocked(&counter_data)?;
+
+    approve_delegate(
+        program_id,
+        counter_account,
+        delegate_account.key,
+        delegate_record_account,
+        authority_account,
+        system_program,
+        increment_allowance,
+        decrement_allowance,
+    )?;
+
+    msg!("Delegate {} approved", delegate_account.key);
+    Ok(())
+}
+
+/// Create or update the delegate record of `delegate_key`, counting newly created records
+#[allow(clippy::too_many_arguments)]
+fn approve_delegate<'a>(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo<'a>,
+    delegate_key: &Pubkey,
+    delegate_record_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    increment_allowance: u64,
+    decrement_allowance: u64,
+) -> ProgramResult {
+    let (delegate_record_key, bump) = find_delegate_address(program_id, counter_account.key, delegate_key);
+    if delegate_record_account.key != &delegate_record_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // Create the record on first approval, later approvals replace the allowances
+    if delegate_record_account.data_is_empty() {
+        create_pda_account(
+            program_id,
+            payer_account,
+            delegate_record_account,
+            system_program,
+            DelegateAccount::LEN,
+            &[
+                DELEGATE_SEED,
+                counter_account.key.as_ref(),
+                delegate_key.as_ref(),
+                &[bump],
+            ],
+        )?;
+        let mut counter_data = counter_account.data.borrow_mut();
+        let counter_data = CounterAccount::load_mut(&mut counter_data)?;
+        counter_data.delegate_count = counter_data
+            .delegate_count
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    } else if delegate_record_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let delegate_data = DelegateAccount {
+        counter: *counter_account.key,
+        delegate: *delegate_key,
+        increment_allowance,
+        decrement_allowance,
+        bump,
+    };
+    delegate_data.serialize(&mut &mut delegate_record_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// Close a delegate record, returning its rent to the counter authority
+fn process_revoke_delegate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Revoking delegate");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer, writable] Counter authority (receives the record rent)
+    // 2. [] Delegate
+    // 3. [writable] Delegate record PDA
+    // 4.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let delegate_account = next_account_info(accounts_iter)?;
+    let delegate_record_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    revoke_delegate(
+        program_id,
+        counter_account,
+        delegate_account.key,
+        delegate_record_account,
+        authority_account,
+    )?;
+
+    msg!("Delegate {} revoked", delegate_account.key);
+    Ok(())
+}
+
+/// Close the delegate record of `delegate_key`, sending its rent to the destination
+fn revoke_delegate(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo,
+    delegate_key: &Pubkey,
+    delegate_record_account: &AccountInfo,
+    destination: &AccountInfo,
+) -> ProgramResult {
+    load_delegate_record(program_id, counter_account.key, delegate_key, delegate_record_account)?;
+    close_account(delegate_record_account, destination)?;
+
+    // Records approved before the count existed were never counted
+    let mut counter_data = counter_account.data.borrow_mut();
+    let counter_data = CounterAccount::load_mut(&mut counter_data)?;
+    counter_data.delegate_count = counter_data.delegate_count.saturating_sub(1);
+    Ok(())
+}
+
+/// Check that the signer may apply `op` to the counter.
+/// The authority may apply any operation, a delegate spends its allowance instead.
+fn authorize_counter_op(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    counter_data: &CounterAccount,
+    signer_account: &AccountInfo,
+    accounts_iter: &mut std::slice::Iter<AccountInfo>,
+    op: &CounterOp,
+) -> ProgramResult {
+    if signer_account.key == &counter_data.authority {
+        return validate_authority(
+            program_id,
+            &counter_data.authority,
+            signer_account,
+            accounts_iter.as_slice(),
+        );
+    }
+
+    if !signer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Anyone else needs a role on the counter or a delegate record
+    let permission_account = next_account_info(accounts_iter)?;
+
+    let (roles_key, _) = find_roles_address(program_id, counter_key);
+    if permission_account.key == &roles_key {
+        let role = match op {
+            CounterOp::Increment { .. } => Role::Incrementer,
+            CounterOp::Decrement { .. } => Role::Decrementer,
+            CounterOp::Set { .. } => return Err(ProgramError::IncorrectAuthority),
+        };
+        return check_role(program_id, counter_key, permission_account, role, signer_account.key);
+    }
+
+    // Otherwise it must be an approved delegate with enough allowance left
+    let delegate_record_account = permission_account;
+    let mut delegate_data = load_delegate_record(
+        program_id,
+        counter_key,
+        signer_account.key,
+        delegate_record_account,
+    )?;
+
+    let (allowance, step) = match op {
+        CounterOp::Increment { step } => (&mut delegate_data.increment_allowance, step),
+        CounterOp::Decrement { step } => (&mut delegate_data.decrement_allowance, step),
+        CounterOp::Set { .. } => return Err(ProgramError::IncorrectAuthority),
+    };
+    *allowance = allowance
+        .checked_sub(step.unwrap_or(1))
+        .ok_or(CounterError::InsufficientAllowance)?;
+
+    delegate_data.serialize(&mut &mut delegate_record_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Delegate allowance left: increment {}, decrement {}",
+        delegate_data.increment_allowance,
+        delegate_data.decrement_allowance
+    );
+    Ok(())
+}
+
+/// Create an empty roles account for a counter
+fn process_initialize_roles(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Initializing counter roles");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer, writable] Counter authority (pays for the roles account)
+    // 2. [writable] Roles PDA
+    // 3. [] System program
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    // The authority pays for the roles account, so it must be a single signer
+    validate_authority(program_id, &counter_data.authority, authority_account, &[])?;
+    check_not_timelocked(&counter_data)?;
+
+    initialize_roles(program_id, counter_account, roles_account, authority_account, system_program)?;
+
+    msg!("Roles initialized for counter {}", counter_account.key);
+    Ok(())
+}
+
+/// Create the empty roles PDA of a counter
+fn initialize_roles<'a>(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo<'a>,
+    roles_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (roles_key, bump) = find_roles_address(program_id, counter_account.key);
+    if roles_account.key != &roles_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !roles_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let roles_data = RolesAccount {
+        counter: *counter_account.key,
+        incrementers: RoleHolders::default(),
+        decrementers: RoleHolders::default(),
+        admins: RoleHolders::default(),
+        bump,
+    };
+    let serialized = borsh::to_vec(&roles_data)?;
+
+    create_pda_account(
+        program_id,
+        payer_account,
+        roles_account,
+        system_program,
+        serialized.len(),
+        &[ROLES_SEED, counter_account.key.as_ref(), &[bump]],
+    )?;
+    roles_account.data.borrow_mut().copy_from_slice(&serialized);
+    Ok(())
+}
+
+/// Grant a role to a key, growing the roles account as needed
+fn process_add_role_holder(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    role: Role,
+    holder: Pubkey,
+) -> ProgramResult {
+    msg!("Adding {} as {:?}", holder, role);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer, writable] Counter authority or admin (pays for the extra space)
+    // 2. [writable] Roles PDA
+    // 3. [] System program
+    // 4.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    let roles_data = load_roles_for_admin(
+        program_id,
+        counter_account,
+        admin_account,
+        roles_account,
+        accounts_iter.as_slice(),
+    )?;
+    add_role_holder(roles_account, roles_data, admin_account, system_program, role, holder)?;
+
+    msg!("{} now holds {:?}", holder, role);
+    Ok(())
+}
+
+/// Grant a role in the loaded roles account, growing it at the payer's expense
+fn add_role_holder<'a>(
+    roles_account: &AccountInfo<'a>,
+    mut roles_data: RolesAccount,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    role: Role,
+    holder: Pubkey,
+) -> ProgramResult {
+    // Adding an existing holder is a no-op
+    let holders = roles_data.holders_mut(role);
+    if holders.holders.contains(&holder) {
+        return Ok(());
+    }
+    holders.holders.push(holder);
+
+    write_resized_account(roles_account, payer_account, system_program, &roles_data)
+}
+
+/// Take a role away from a key, shrinking the roles account
+fn process_remove_role_holder(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    role: Role,
+    holder: Pubkey,
+) -> ProgramResult {
+    msg!("Removing {} from {:?}", holder, role);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer, writable] Counter authority or admin (receives the freed rent)
+    // 2. [writable] Roles PDA
+    // 3. [] System program
+    // 4.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    let roles_data = load_roles_for_admin(
+        program_id,
+        counter_account,
+        admin_account,
+        roles_account,
+        accounts_iter.as_slice(),
+    )?;
+    remove_role_holder(roles_account, roles_data, admin_account, system_program, role, &holder)?;
+
+    msg!("{} no longer holds {:?}", holder, role);
+    Ok(())
+}
+
+/// Take a role away in the loaded roles account, sending the freed rent to the destination
+fn remove_role_holder<'a>(
+    roles_account: &AccountInfo<'a>,
+    mut roles_data: RolesAccount,
+    destination: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    role: Role,
+    holder: &Pubkey,
+) -> ProgramResult {
+    let holders = &mut roles_data.holders_mut(role).holders;
+    let position = holders
+        .iter()
+        .position(|key| key == holder)
+        .ok_or(ProgramError::InvalidArgument)?;
+    holders.remove(position);
+
+    write_resized_account(roles_account, destination, system_program, &roles_data)
+}
+
+/// Open a role to everyone or restrict it to its holders
+fn process_set_role_public(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    role: Role,
+    public: bool,
+) -> ProgramResult {
+    msg!("Setting {:?} public: {}", role, public);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer] Counter authority or admin
+    // 2. [writable] Roles PDA
+    // 3.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+
+    let mut roles_data = load_roles_for_admin(
+        program_id,
+        counter_account,
+        admin_account,
+        roles_account,
+        accounts_iter.as_slice(),
+    )?;
+    roles_data.holders_mut(role).public = public;
+
+    // The flag does not change the serialized size
+    roles_data.serialize(&mut &mut roles_account.data.borrow_mut()[..])?;
+
+    msg!("{:?} public flag updated", role);
+    Ok(())
+}
+
+/// Set the counter back to zero
+fn process_reset_counter(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Resetting counter");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority or admin
+    // 2. [] Roles account (only when not signed by the authority)
+    //    or [signer] multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_frozen(&counter_data)?;
+    check_not_expired(&counter_data)?;
+    check_not_tokenized(&counter_data)?;
+    check_not_timelocked(&counter_data)?;
+    authorize_admin(program_id, counter_account.key, &counter_data, admin_account, accounts_iter)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(0);
+    record_history(counter_account, admin_account.key, counter_data.count(), 0)?;
+
+    msg!("Counter reset to: 0");
+    Ok(())
+}
+
+/// Close the counter account and its PDAs, sending their rent to the destination
+fn process_close_counter(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Closing counter");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority or admin
+    // 2. [writable] Destination for the reclaimed rent
+    // 3. [writable] Counter registry PDA of the authority
+    // 4. [writable] Roles PDA (also checked for the admin role when not signed by the authority)
+    // 5. [writable] Metadata PDA
+    // 6. [writable] Pending change PDA
+    // 7.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+    let destination_account = next_account_info(accounts_iter)?;
+    let registry_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+    let metadata_account = next_account_info(accounts_iter)?;
+    let scheduled_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_frozen(&counter_data)?;
+    check_not_timelocked(&counter_data)?;
+    if admin_account.key == &counter_data.authority {
+        validate_authority(
+            program_id,
+            &counter_data.authority,
+            admin_account,
+            accounts_iter.as_slice(),
+        )?;
+    } else {
+        authorize_admin(
+            program_id,
+            counter_account.key,
+            &counter_data,
+            admin_account,
+            &mut std::slice::from_ref(roles_account).iter(),
+        )?;
+    }
+
+    close_counter(
+        program_id,
+        counter_account,
+        &counter_data,
+        destination_account,
+        registry_account,
+        roles_account,
+        metadata_account,
+        scheduled_account,
+    )?;
+
+    msg!("Counter {} closed", counter_account.key);
+    Ok(())
+}
+
+/// Close a counter without delegates together with its PDAs, sending all rent to the destination
+#[allow(clippy::too_many_arguments)]
+fn close_counter<'a>(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo<'a>,
+    counter_data: &CounterAccount,
+    destination_account: &AccountInfo<'a>,
+    registry_account: &AccountInfo<'a>,
+    roles_account: &AccountInfo<'a>,
+    metadata_account: &AccountInfo<'a>,
+    scheduled_account: &AccountInfo<'a>,
+) -> ProgramResult {
+    if destination_account.key == counter_account.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // Delegate records are not listed anywhere, so they have to be revoked first
+    if counter_data.delegate_count() > 0 {
+        return Err(CounterError::DelegatesOutstanding.into());
+    }
+
+    unregister_counter(
+        program_id,
+        registry_account,
+        &counter_data.authority,
+        counter_account.key,
+        destination_account,
+    )?;
+    close_counter_pdas(
+        program_id,
+        counter_account.key,
+        roles_account,
+        metadata_account,
+        scheduled_account,
+        destination_account,
+    )?;
+    close_account(counter_account, destination_account)?;
+
+    CounterClosed {
+        counter: *counter_account.key,
+        destination: *destination_account.key,
+    }
+    .emit()
+}
+
+/// Close an expired counter on behalf of anyone, sending its rent and the rent of its PDAs
+/// to the recorded rent payer
+fn process_close_expired_counter(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Closing expired counter");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [writable] Rent payer recorded at initialization
+    // 2. [writable] Counter registry PDA of the authority
+    // 3. [writable] Roles PDA
+    // 4. [writable] Metadata PDA
+    // 5. [writable] Pending change PDA
+    // 6.. [writable] Every open delegate record of the counter
+    let counter_account = next_account_info(accounts_iter)?;
+    let rent_payer_account = next_account_info(accounts_iter)?;
+    let registry_account = next_account_info(accounts_iter)?;
+    let roles_account = next_account_info(accounts_iter)?;
+    let metadata_account = next_account_info(accounts_iter)?;
+    let scheduled_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    if !counter_data.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(CounterError::CounterNotExpired.into());
+    }
+    if *rent_payer_account.key != counter_data.rent_payer() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // The authority cannot be asked to revoke delegates, so their records are closed here
+    let mut delegate_count = counter_data.delegate_count();
+    for delegate_record_account in accounts_iter {
+        let delegate_key = DelegateAccount::try_from_slice(&delegate_record_account.data.borrow())
+            .map_err(|_| ProgramError::InvalidAccountData)?
+            .delegate;
+        load_delegate_record(
+            program_id,
+            counter_account.key,
+            &delegate_key,
+            delegate_record_account,
+        )?;
+        close_account(delegate_record_account, rent_payer_account)?;
+        delegate_count = delegate_count.saturating_sub(1);
+    }
+    if delegate_count > 0 {
+        return Err(CounterError::DelegatesOutstanding.into());
+    }
+
+    unregister_counter(
+        program_id,
+        registry_account,
+        &counter_data.authority,
+        counter_account.key,
+        rent_payer_account,
+    )?;
+    close_counter_pdas(
+        program_id,
+        counter_account.key,
+        roles_account,
+        metadata_account,
+        scheduled_account,
+        rent_payer_account,
+    )?;
+    close_account(counter_account, rent_payer_account)?;
+    CounterClosed {
+        counter: *counter_account.key,
+        destination: *rent_payer_account.key,
+    }
+    .emit()?;
+
+    msg!("Expired counter {} closed", counter_account.key);
+    Ok(())
+}
+
+/// Close the roles, metadata and pending change PDAs of a counter being closed, skipping
+/// the ones that were never created
+fn close_counter_pdas<'a>(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    roles_account: &AccountInfo<'a>,
+    metadata_account: &AccountInfo<'a>,
+    scheduled_account: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+) -> ProgramResult {
+    for (account, (expected_key, _)) in [
+        (roles_account, find_roles_address(program_id, counter_key)),
+        (metadata_account, find_metadata_address(program_id, counter_key)),
+        (scheduled_account, find_scheduled_change_address(program_id, counter_key)),
+    ] {
+        if account.key != &expected_key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if account.owner == program_id {
+            close_account(account, destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a multisig account listing the passed signer keys
+fn process_initialize_multisig(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    threshold: u8,
+) -> ProgramResult {
+    msg!("Initializing multisig with threshold: {}", threshold);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [signer, writable] Multisig account (to be created)
+    // 1. [signer, writable] Payer account
+    // 2. [] System program
+    // 3.. [] Keys allowed to sign for the multisig
+    let multisig_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    let signer_accounts = accounts_iter.as_slice();
+
+    if signer_accounts.is_empty() || signer_accounts.len() > MAX_MULTISIG_SIGNERS {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if threshold == 0 || threshold as usize > signer_accounts.len() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // A repeated key would count once per listing towards the threshold
+    let mut signers = [Pubkey::default(); MAX_MULTISIG_SIGNERS];
+    for (index, signer_account) in signer_accounts.iter().enumerate() {
+        if signers[..index].contains(signer_account.key) {
+            return Err(ProgramError::InvalidArgument);
+        }
+        signers[index] = *signer_account.key;
+    }
+
+    let rent = Rent::get()?;
+    invoke(
+        &system_instruction::create_account(
+            payer_account.key,
+            multisig_account.key,
+            rent.minimum_balance(MultisigAccount::LEN),
+            MultisigAccount::LEN as u64,
+            program_id,
+        ),
+        &[
+            payer_account.clone(),
+            multisig_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    let multisig_data = MultisigAccount {
+        discriminator: MultisigAccount::DISCRIMINATOR,
+        threshold,
+        signer_count: signer_accounts.len() as u8,
+        signers,
+    };
+    multisig_data.serialize(&mut &mut multisig_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Multisig initialized: {} of {} signers",
+        threshold,
+        signer_accounts.len()
+    );
+    Ok(())
+}
+
+/// Replace the counter authority
+fn process_set_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_authority: Pubkey,
+) -> ProgramResult {
+    msg!("Setting counter authority to: {}", new_authority);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer, writable] Current counter authority (refunded the rent of its registry entry)
+    // 2. [writable] Counter registry PDA of the current authority
+    // 3. [writable] Counter registry PDA of the new authority
+    // 4. [signer, writable] Payer for the new registry entry
+    // 5. [] System program
+    // 6.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let old_registry_account = next_account_info(accounts_iter)?;
+    let new_registry_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    set_authority(
+        program_id,
+        counter_account,
+        &counter_data,
+        authority_account,
+        old_registry_account,
+        new_registry_account,
+        payer_account,
+        system_program,
+        new_authority,
+    )?;
+
+    msg!("Counter authority updated");
+    Ok(())
+}
+
+/// Hand a counter to a new authority, moving it between their registries. The old
+/// registry entry is refunded to `authority_account`, which must be the current authority.
+#[allow(clippy::too_many_arguments)]
+fn set_authority<'a>(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo<'a>,
+    counter_data: &CounterAccount,
+    authority_account: &AccountInfo<'a>,
+    old_registry_account: &AccountInfo<'a>,
+    new_registry_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    new_authority: Pubkey,
+) -> ProgramResult {
+    if authority_account.key != &counter_data.authority {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+
+    // Move the counter to the registry of its new authority
+    if new_authority != counter_data.authority {
+        unregister_counter(
+            program_id,
+            old_registry_account,
+            &counter_data.authority,
+            counter_account.key,
+            authority_account,
+        )?;
+        register_counter(
+            program_id,
+            new_registry_account,
+            &new_authority,
+            counter_account.key,
+            payer_account,
+            system_program,
+        )?;
+    }
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.authority = new_authority;
+
+    AuthorityChanged {
+        counter: *counter_account.key,
+        old_authority: counter_data.authority,
+        new_authority,
+    }
+    .emit()
+}
+
+/// Freeze or thaw a counter
+fn process_set_frozen(program_id: &Pubkey, accounts: &[AccountInfo], frozen: bool) -> ProgramResult {
+    msg!("Setting counter frozen: {}", frozen);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    // Freezing only restricts the counter, so it stays available as an emergency stop
+    if !frozen {
+        check_not_timelocked(&counter_data)?;
+    }
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_frozen(frozen);
+
+    msg!("Counter frozen: {}", frozen);
+    Ok(())
+}
+
+/// Set the lamport fee charged on every increment and the treasury receiving it
+fn process_set_increment_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_lamports: u64,
+    treasury: Pubkey,
+) -> ProgramResult {
+    msg!("Setting increment fee: {} lamports", fee_lamports);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?
+        .set_increment_fee(fee_lamports, treasury);
+
+    msg!("Increment fee set, treasury: {}", treasury);
+    Ok(())
+}
+
+/// Set or clear the token gate of a counter
+fn process_set_token_gate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    min_balance: u64,
+) -> ProgramResult {
+    msg!("Setting token gate: {} (minimum balance {})", mint, min_balance);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_token_gate(mint, min_balance);
+
+    msg!("Token gate set");
+    Ok(())
+}
+
+/// Set or clear the token payment charged on every increment
+fn process_set_token_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    amount_per_step: u64,
+    mode: TokenPaymentMode,
+    destination: Pubkey,
+) -> ProgramResult {
+    msg!("Setting token payment: {} of {} per step ({:?})", amount_per_step, mint, mode);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?
+        .set_token_payment(mint, amount_per_step, mode, destination);
+
+    msg!("Token payment set");
+    Ok(())
+}
+
+/// Tokenize a counter with a mint controlled by its mint authority PDA, or turn tokenization off
+fn process_set_units_mint(program_id: &Pubkey, accounts: &[AccountInfo], mint: Pubkey) -> ProgramResult {
+    msg!("Setting units mint: {}", mint);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2. [] Units mint (omitted when turning tokenization off)
+    // 3.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    let (mint_authority, bump) = check_units_mint(program_id, counter_account.key, &counter_data, &mint, accounts_iter)?;
+
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_units_mint(mint, bump);
+
+    msg!("Units mint set, mint authority: {}", mint_authority);
+    Ok(())
+}
+
+/// Check that a new units mint is controlled by the counter's mint authority PDA and that
+/// its supply matches the count. The mint account is only taken from the iterator when
+/// tokenization is turned on. Returns the mint authority PDA and its bump.
+fn check_units_mint(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    counter_data: &CounterAccount,
+    mint: &Pubkey,
+    accounts_iter: &mut std::slice::Iter<AccountInfo>,
+) -> Result<(Pubkey, u8), ProgramError> {
+    let (mint_authority, bump) = find_mint_authority_address(program_id, counter_key);
+    if mint != &Pubkey::default() {
+        // The mint must be controlled by the counter and match its value
+        let mint_account = next_account_info(accounts_iter)?;
+        if mint_account.key != mint || *mint_account.owner != spl_token::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let mint_data = spl_token::state::Mint::unpack(&mint_account.data.borrow())?;
+        if mint_data.mint_authority != COption::Some(mint_authority)
+            || mint_data.supply != counter_data.count()
+        {
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    Ok((mint_authority, bump))
+}
+
+/// Store or clear a milestone trigger
+fn process_set_trigger(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    index: u8,
+    threshold: u64,
+    program: Pubkey,
+    data: Vec<u8>,
+) -> ProgramResult {
+    msg!("Setting trigger {} at {}: {}", index, threshold, program);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    set_trigger(
+        CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?,
+        index,
+        threshold,
+        program,
+        &data,
+    )?;
+
+    msg!("Trigger {} set", index);
+    Ok(())
+}
+
+/// Store a trigger in slot `index`, or clear the slot when `program` is the default key
+fn set_trigger(
+    counter_data: &mut CounterAccount,
+    index: u8,
+    threshold: u64,
+    program: Pubkey,
+    data: &[u8],
+) -> ProgramResult {
+    let trigger = if program == Pubkey::default() {
+        Trigger::zeroed()
+    } else {
+        Trigger::new(threshold, program, data)?
+    };
+    let slot = counter_data
+        .triggers_mut()
+        .get_mut(index as usize)
+        .ok_or(ProgramError::InvalidArgument)?;
+    *slot = trigger;
+    Ok(())
+}
+
+/// Set or clear the hook program of a counter
+fn process_set_hook(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    program: Pubkey,
+    pre: bool,
+    post: bool,
+) -> ProgramResult {
+    msg!("Setting hook: {} (pre: {}, post: {})", program, pre, post);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_hook(program, pre, post);
+
+    msg!("Hook set");
+    Ok(())
+}
+
+/// Set or clear the expiry of a counter
+fn process_set_expiry(program_id: &Pubkey, accounts: &[AccountInfo], expires_at: i64) -> ProgramResult {
+    msg!("Setting expiry: {}", expires_at);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_expired(&counter_data)?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_expires_at(expires_at);
+
+    msg!("Expiry set");
+    Ok(())
+}
+
+/// Raise the timelock delay of a counter
+fn process_set_timelock(program_id: &Pubkey, accounts: &[AccountInfo], delay: i64) -> ProgramResult {
+    msg!("Setting timelock delay: {}", delay);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [signer] Counter authority
+    // 2.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+
+    check_timelock_delay(delay)?;
+    // Lowering the delay weakens the timelock, so it has to wait out the current one
+    if delay < counter_data.timelock_delay() {
+        return Err(CounterError::TimelockRequired.into());
+    }
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_timelock_delay(delay);
+
+    msg!("Timelock delay set");
+    Ok(())
+}
+
+/// Record a change to apply once the counter's timelock delay has passed
+fn process_schedule_change(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    action: ScheduledAction,
+) -> ProgramResult {
+    msg!("Scheduling change: {:?}", action);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer] Counter authority
+    // 2. [writable] Pending change PDA
+    // 3. [signer, writable] Payer of the pending change account
+    // 4. [] System program
+    // 5.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let scheduled_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    validate_scheduled_action(counter_account.key, &counter_data, &action)?;
+
+    let (scheduled_key, bump) = find_scheduled_change_address(program_id, counter_account.key);
+    if scheduled_account.key != &scheduled_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // One pending change per counter
+    if !scheduled_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let execute_after = Clock::get()?
+        .unix_timestamp
+        .checked_add(counter_data.timelock_delay())
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let scheduled_data = ScheduledChangeAccount {
+        counter: *counter_account.key,
+        authority: counter_data.authority,
+        rent_payer: *payer_account.key,
+        execute_after,
+        action,
+        bump,
+    };
+    let data = borsh::to_vec(&scheduled_data)?;
+    create_pda_account(
+        program_id,
+        payer_account,
+        scheduled_account,
+        system_program,
+        data.len(),
+        &[SCHEDULED_CHANGE_SEED, counter_account.key.as_ref(), &[bump]],
+    )?;
+    scheduled_account.data.borrow_mut().copy_from_slice(&data);
+
+    msg!("Change executable from {}", execute_after);
+    Ok(())
+}
+
+/// Reject actions whose arguments could never execute, before their rent is paid
+fn validate_scheduled_action(
+    counter_key: &Pubkey,
+    counter_data: &CounterAccount,
+    action: &ScheduledAction,
+) -> ProgramResult {
+    match action {
+        ScheduledAction::SetTimelock { delay } => check_timelock_delay(*delay),
+        ScheduledAction::SetTrigger {
+            index,
+            threshold,
+            program,
+            data,
+        } => {
+            // Apply to a copy to check the slot and the data length
+            let mut counter_copy = *counter_data;
+            set_trigger(&mut counter_copy, *index, *threshold, *program, data)
+        }
+        ScheduledAction::UpdateMetadata { metadata } => metadata.validate(),
+        ScheduledAction::Close { destination } if destination == counter_key => {
+            Err(ProgramError::InvalidArgument)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Apply a pending change whose delay has passed, refunding its rent payer
+fn process_execute_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Executing scheduled change");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Counter account
+    // 1. [writable] Pending change PDA
+    // 2. [writable] Payer of the pending change
+    // 3.. Accounts of the action, as listed on its `ScheduledAction` variant
+    let counter_account = next_account_info(accounts_iter)?;
+    let scheduled_account = next_account_info(accounts_iter)?;
+    let rent_payer_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    let scheduled_data = load_scheduled_change(program_id, scheduled_account, counter_account.key)?;
+    if scheduled_data.authority != counter_data.authority
+        || scheduled_data.rent_payer != *rent_payer_account.key
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if Clock::get()?.unix_timestamp < scheduled_data.execute_after {
+        return Err(CounterError::TimelockPending.into());
+    }
+
+    // Refund the pending change first, a scheduled close also closes its PDAs
+    close_account(scheduled_account, rent_payer_account)?;
+
+    match &scheduled_data.action {
+        ScheduledAction::SetCounter { value } => {
+            let trigger_programs = next_trigger_programs(&counter_data, accounts_iter)?;
+            set_scheduled_count(counter_account, &counter_data, *value)?;
+            fire_triggers(counter_account, &counter_data, &trigger_programs, counter_data.count(), *value)?
+        }
+        ScheduledAction::Reset => set_scheduled_count(counter_account, &counter_data, 0)?,
+        ScheduledAction::SetIncrementFee {
+            fee_lamports,
+            treasury,
+        } => CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?
+            .set_increment_fee(*fee_lamports, *treasury),
+        ScheduledAction::SetTokenGate { mint, min_balance } => {
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_token_gate(*mint, *min_balance)
+        }
+        ScheduledAction::SetTimelock { delay } => {
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_timelock_delay(*delay)
+        }
+        ScheduledAction::Freeze => CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_frozen(true),
+        ScheduledAction::Thaw => CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_frozen(false),
+        ScheduledAction::SetAuthority { new_authority } => {
+            let authority_account = next_account_info(accounts_iter)?;
+            let old_registry_account = next_account_info(accounts_iter)?;
+            let new_registry_account = next_account_info(accounts_iter)?;
+            let payer_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            set_authority(
+                program_id,
+                counter_account,
+                &counter_data,
+                authority_account,
+                old_registry_account,
+                new_registry_account,
+                payer_account,
+                system_program,
+                *new_authority,
+            )?
+        }
+        ScheduledAction::SetTokenPayment {
+            mint,
+            amount_per_step,
+            mode,
+            destination,
+        } => CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?
+            .set_token_payment(*mint, *amount_per_step, *mode, *destination),
+        ScheduledAction::SetUnitsMint { mint } => {
+            let (_, bump) = check_units_mint(program_id, counter_account.key, &counter_data, mint, accounts_iter)?;
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_units_mint(*mint, bump)
+        }
+        ScheduledAction::SetTrigger {
+            index,
+            threshold,
+            program,
+            data,
+        } => set_trigger(
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?,
+            *index,
+            *threshold,
+            *program,
+            data,
+        )?,
+        ScheduledAction::SetHook { program, pre, post } => {
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_hook(*program, *pre, *post)
+        }
+        ScheduledAction::SetExpiry { expires_at } => {
+            check_not_expired(&counter_data)?;
+            CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_expires_at(*expires_at)
+        }
+        ScheduledAction::UpdateMetadata { metadata } => {
+            let metadata_account = next_account_info(accounts_iter)?;
+            let payer_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            write_metadata_account(
+                program_id,
+                counter_account,
+                payer_account,
+                metadata_account,
+                system_program,
+                metadata.clone(),
+            )?
+        }
+        ScheduledAction::ApproveDelegate {
+            delegate,
+            increment_allowance,
+            decrement_allowance,
+        } => {
+            let delegate_record_account = next_account_info(accounts_iter)?;
+            let payer_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            approve_delegate(
+                program_id,
+                counter_account,
+                delegate,
+                delegate_record_account,
+                payer_account,
+                system_program,
+                *increment_allowance,
+                *decrement_allowance,
+            )?
+        }
+        ScheduledAction::RevokeDelegate { delegate } => {
+            let authority_account = next_account_info(accounts_iter)?;
+            let delegate_record_account = next_account_info(accounts_iter)?;
+            if authority_account.key != &counter_data.authority {
+                return Err(ProgramError::IncorrectAuthority);
+            }
+            revoke_delegate(program_id, counter_account, delegate, delegate_record_account, authority_account)?
+        }
+        ScheduledAction::InitializeRoles => {
+            let roles_account = next_account_info(accounts_iter)?;
+            let payer_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            initialize_roles(program_id, counter_account, roles_account, payer_account, system_program)?
+        }
+        ScheduledAction::AddRoleHolder { role, holder } => {
+            let roles_account = next_account_info(accounts_iter)?;
+            let payer_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            let roles_data = load_roles(program_id, counter_account.key, roles_account)?;
+            add_role_holder(roles_account, roles_data, payer_account, system_program, *role, *holder)?
+        }
+        ScheduledAction::RemoveRoleHolder { role, holder } => {
+            let roles_account = next_account_info(accounts_iter)?;
+            let authority_account = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            if authority_account.key != &counter_data.authority {
+                return Err(ProgramError::IncorrectAuthority);
+            }
+            let roles_data = load_roles(program_id, counter_account.key, roles_account)?;
+            remove_role_holder(roles_account, roles_data, authority_account, system_program, *role, holder)?
+        }
+        ScheduledAction::SetRolePublic { role, public } => {
+            let roles_account = next_account_info(accounts_iter)?;
+            let mut roles_data = load_roles(program_id, counter_account.key, roles_account)?;
+            roles_data.holders_mut(*role).public = *public;
+            roles_data.serialize(&mut &mut roles_account.data.borrow_mut()[..])?
+        }
+        ScheduledAction::Close { destination } => {
+            let destination_account = next_account_info(accounts_iter)?;
+            let registry_account = next_account_info(accounts_iter)?;
+            let roles_account = next_account_info(accounts_iter)?;
+            let metadata_account = next_account_info(accounts_iter)?;
+            if destination_account.key != destination {
+                return Err(ProgramError::InvalidArgument);
+            }
+            check_not_frozen(&counter_data)?;
+            close_counter(
+                program_id,
+                counter_account,
+                &counter_data,
+                destination_account,
+                registry_account,
+                roles_account,
+                metadata_account,
+                scheduled_account,
+            )?
+        }
+    }
+
+    msg!("Scheduled change executed: {:?}", scheduled_data.action);
+    Ok(())
+}
+
+/// Overwrite the count of a counter on behalf of a scheduled change
+fn set_scheduled_count(counter_account: &AccountInfo, counter_data: &CounterAccount, value: u64) -> ProgramResult {
+    check_not_frozen(counter_data)?;
+    check_not_expired(counter_data)?;
+    check_not_tokenized(counter_data)?;
+
+    CounterAccount::load_mut(&mut counter_account.data.borrow_mut())?.set_count(value);
+    record_history(counter_account, &counter_data.authority, counter_data.count(), value)
+}
+
+/// Drop a pending change, refunding its rent payer
+fn process_cancel_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Cancelling scheduled change");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer] Counter authority
+    // 2. [writable] Pending change PDA
+    // 3. [writable] Payer of the pending change
+    // 4.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let scheduled_account = next_account_info(accounts_iter)?;
+    let rent_payer_account = next_account_info(accounts_iter)?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    let scheduled_data = load_scheduled_change(program_id, scheduled_account, counter_account.key)?;
+    if scheduled_data.rent_payer != *rent_payer_account.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    close_account(scheduled_account, rent_payer_account)?;
+
+    msg!("Scheduled change cancelled: {:?}", scheduled_data.action);
+    Ok(())
+}
+
+/// Move a baseline counter to the current layout
+fn process_migrate_counter(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Migrating counter");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [signer, writable] Baseline counter account
+    // 1. [] Authority assigned to the counter
+    // 2. [signer, writable] Payer of the extra rent
+    // 3. [] System program
+    // 4. [writable] Counter registry PDA of the assigned authority
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    let registry_account = next_account_info(accounts_iter)?;
+
+    if counter_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if counter_account.data_len() != BASELINE_COUNTER_LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // The baseline layout has no authority, so whoever holds the counter keypair that
+    // created the account decides who becomes its authority
+    if !counter_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let count = u64::from_le_bytes(
+        counter_account.data.borrow()[..]
+            .try_into()
+            .map_err(|_| ProgramError::InvalidAccountData)?,
+    );
+
+    let space = CounterAccount::LEN;
+    let current_lamports = counter_account.lamports();
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    if required_lamports > current_lamports {
+        invoke(
+            &system_instruction::transfer(
+                payer_account.key,
+                counter_account.key,
+                required_lamports - current_lamports,
+            ),
+            &[
+                payer_account.clone(),
+                counter_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    counter_account.realloc(space, true)?;
+
+    // Baseline counters failed on overflow, so they keep checked arithmetic, and the
+    // settings added since then start out off
+    let mut counter_data = CounterAccount::new(count, *authority_account.key, OverflowMode::Checked);
+    counter_data.rent_payer = *payer_account.key;
+    counter_account.data.borrow_mut().copy_from_slice(bytemuck::bytes_of(&counter_data));
+
+    register_counter(
+        program_id,
+        registry_account,
+        authority_account.key,
+        counter_account.key,
+        payer_account,
+        system_program,
+    )?;
+
+    msg!("Baseline counter migrated with count: {}", count);
+    Ok(())
+}
+
+/// Create the program-wide config with the signer as admin
+fn process_initialize_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Initializing program config");
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Config PDA
+    // 1. [signer, writable] Admin (the program upgrade authority, pays for the config account)
+    // 2. [] ProgramData account of this program
+    // 3. [] System program
+    let config_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+    let program_data_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    // Only whoever may upgrade the program may claim its admin role
+    let upgrade_authority = load_upgrade_authority(program_id, program_data_account)?;
+    if upgrade_authority != Some(*admin_account.key) {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+    if !admin_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let (config_key, bump) = find_config_address(program_id);
+    if config_account.key != &config_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if !config_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    create_pda_account(
+        program_id,
+        admin_account,
+        config_account,
+        system_program,
+        ConfigAccount::LEN,
+        &[CONFIG_SEED, &[bump]],
+    )?;
+
+    let config_data = ConfigAccount {
+        admin: *admin_account.key,
+        bump,
+        ..ConfigAccount::default()
+    };
+    config_data.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program config initialized with admin: {}", admin_account.key);
+    Ok(())
+}
+
+/// Read the upgrade authority from the program's ProgramData account
+/// (`None` once the program is immutable)
+fn load_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    if program_data_account.key != &find_program_data_address(program_id).0 {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if program_data_account.owner != &bpf_loader_upgradeable::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // UpgradeableLoaderState::ProgramData: u32 variant 3, u64 slot, Option<Pubkey> upgrade authority
+    let data = program_data_account.data.borrow();
+    if data.len() < PROGRAM_DATA_METADATA_LEN || data[..4] != 3u32.to_le_bytes() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    match data[12] {
+        0 => Ok(None),
+        1 => data
+            .get(13..45)
+            .and_then(|key| Pubkey::try_from(key).ok())
+            .map(Some)
+            .ok_or(ProgramError::InvalidAccountData),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Replace the config admin and defaults
+fn process_update_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    admin: Pubkey,
+    default_step: u64,
+    default_overflow_mode: OverflowMode,
+) -> ProgramResult {
+    msg!(
+        "Updating program config: admin {}, default step {}, default overflow mode {:?}",
+        admin,
+        default_step,
+        default_overflow_mode
+    );
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Config PDA
+    // 1. [signer] Config admin
+    let config_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+
+    if default_step == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut config_data = load_config_for_admin(program_id, config_account, admin_account)?;
+    config_data.admin = admin;
+    config_data.default_step = default_step;
+    config_data.default_overflow_mode = default_overflow_mode;
+    config_data.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program config updated");
+    Ok(())
+}
+
+/// Turn the program-wide pause switch on or off
+fn process_set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    msg!("Setting program paused: {}", paused);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [writable] Config PDA
+    // 1. [signer] Config admin
+    let config_account = next_account_info(accounts_iter)?;
+    let admin_account = next_account_info(accounts_iter)?;
+
+    let mut config_data = load_config_for_admin(program_id, config_account, admin_account)?;
+    config_data.paused = paused;
+    config_data.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program paused: {}", config_data.paused);
+    Ok(())
+}
+
+/// Set the name and description of a counter, creating its metadata PDA if needed
+fn process_update_metadata(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    metadata: CounterMetadata,
+) -> ProgramResult {
+    msg!("Updating counter metadata: {}", metadata.name);
+
+    let accounts_iter = &mut accounts.iter();
+
+    // Accounts expected in order:
+    // 0. [] Counter account
+    // 1. [signer] Counter authority
+    // 2. [writable] Metadata PDA
+    // 3. [signer, writable] Payer of metadata growth
+    // 4. [] System program
+    // 5.. [signer] Multisig signers (only for a multisig authority)
+    let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let metadata_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    metadata.validate()?;
+
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    validate_authority(
+        program_id,
+        &counter_data.authority,
+        authority_account,
+        accounts_iter.as_slice(),
+    )?;
+    check_not_timelocked(&counter_data)?;
+
+    write_metadata_account(
+        program_id,
+        counter_account,
+        payer_account,
+        metadata_account,
+        system_program,
+        metadata,
+    )?;
+
+    msg!("Counter metadata updated");
+    Ok(())
+}
+
+/// Create or resize the metadata PDA of a counter and store `metadata` in it
+fn write_metadata_account<'a>(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    metadata_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    metadata: CounterMetadata,
+) -> ProgramResult {
+    let (metadata_key, bump) = find_metadata_address(program_id, counter_account.key);
+    if metadata_account.key != &metadata_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let metadata_data = MetadataAccount {
+        counter: *counter_account.key,
+        metadata,
+        bump,
+    };
+
+    if metadata_account.data_is_empty() {
+        let space = borsh::to_vec(&metadata_data)?.len();
+        create_pda_account(
+            program_id,
+            payer_account,
+            metadata_account,
+            system_program,
+            space,
+            &[METADATA_SEED, counter_account.key.as_ref(), &[bump]],
+        )?;
+    } else if metadata_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    write_resized_account(metadata_account, payer_account, system_program, &metadata_data)
+}
+
+/// Create a program-owned PDA with `space` bytes, rent exempt and paid by `payer_account`
+///
+/// `create_account` fails once an address holds lamports, so anyone could block a PDA by
+/// sending lamports to it first. A funded address is topped up to the rent-exempt minimum,
+/// then allocated and assigned instead.
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    pda_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    let current_lamports = pda_account.lamports();
+    if current_lamports == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(
+                payer_account.key,
+                pda_account.key,
+                required_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[
+                payer_account.clone(),
+                pda_account.clone(),
+                system_program.clone(),
+            ],
+            &[seeds],
+        );
+    }
+
+    let shortfall = required_lamports.saturating_sub(current_lamports);
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, pda_account.key, shortfall),
+            &[
+                payer_account.clone(),
+                pda_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(pda_account.key, space as u64),
+        &[pda_account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(pda_account.key, program_id),
+        &[pda_account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Load the counter registry of `authority`, or an empty one if it does not exist yet
+fn load_registry(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo,
+    authority: &Pubkey,
+) -> Result<RegistryAccount, ProgramError> {
+    if registry_account.data_is_empty() {
+        let (registry_key, bump) = find_registry_address(program_id, authority);
+        if registry_account.key != &registry_key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        return Ok(RegistryAccount {
+            authority: *authority,
+            next_index: 0,
+            bump,
+            counters: Vec::new(),
+        });
+    }
+
+    if registry_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let registry_data = RegistryAccount::try_from_slice(&registry_account.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let registry_key = Pubkey::create_program_address(
+        &[REGISTRY_SEED, authority.as_ref(), &[registry_data.bump]],
+        program_id,
+    )?;
+    if registry_account.key != &registry_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(registry_data)
+}
+
+/// Append a counter to the registry of `authority`, creating or growing it
+fn register_counter<'a>(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo<'a>,
+    authority: &Pubkey,
+    counter_key: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let mut registry_data = load_registry(program_id, registry_account, authority)?;
+    registry_data.counters.push(*counter_key);
+    registry_data.next_index = registry_data
+        .next_index
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if registry_account.data_is_empty() {
+        let space = borsh::to_vec(&registry_data)?.len();
+        create_pda_account(
+            program_id,
+            payer_account,
+            registry_account,
+            system_program,
+            space,
+            &[REGISTRY_SEED, authority.as_ref(), &[registry_data.bump]],
+        )?;
+    }
+
+    write_resized_account(registry_account, payer_account, system_program, &registry_data)
+}
+
+/// Remove a counter from the registry of `authority`, refunding freed rent to `destination`
+fn unregister_counter(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo,
+    authority: &Pubkey,
+    counter_key: &Pubkey,
+    destination: &AccountInfo,
+) -> ProgramResult {
+    if registry_account.data_is_empty() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let mut registry_data = load_registry(program_id, registry_account, authority)?;
+    let position = registry_data
+        .counters
+        .iter()
+        .position(|counter| counter == counter_key)
+        .ok_or(ProgramError::InvalidArgument)?;
+    registry_data.counters.remove(position);
+
+    write_shrunk_account(registry_account, destination, &registry_data)
+}
+
+/// Load the pending change of a counter, checking the PDA address and owner
+fn load_scheduled_change(
+    program_id: &Pubkey,
+    scheduled_account: &AccountInfo,
+    counter_key: &Pubkey,
+) -> Result<ScheduledChangeAccount, ProgramError> {
+    if scheduled_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let scheduled_data = ScheduledChangeAccount::try_from_slice(&scheduled_account.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let scheduled_key = Pubkey::create_program_address(
+        &[SCHEDULED_CHANGE_SEED, counter_key.as_ref(), &[scheduled_data.bump]],
+        program_id,
+    )?;
+    if scheduled_account.key != &scheduled_key || scheduled_data.counter != *counter_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(scheduled_data)
+}
+
+/// Load the program config, falling back to defaults until it is initialized
+fn load_config(program_id: &Pubkey, config_account: &AccountInfo) -> Result<ConfigAccount, ProgramError> {
+    if config_account.data_is_empty() {
+        let (config_key, _) = find_config_address(program_id);
+        if config_account.key != &config_key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        return Ok(ConfigAccount::default());
+    }
+
+    if config_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let config_data = ConfigAccount::try_from_slice(&config_account.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let config_key = Pubkey::create_program_address(&[CONFIG_SEED, &[config_data.bump]], program_id)?;
+    if config_account.key != &config_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(config_data)
+}
+
+/// Load the initialized program config after checking the admin signed
+fn load_config_for_admin(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+    admin_account: &AccountInfo,
+) -> Result<ConfigAccount, ProgramError> {
+    if config_account.data_is_empty() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let config_data = load_config(program_id, config_account)?;
+    validate_authority(program_id, &config_data.admin, admin_account, &[])?;
+
+    Ok(config_data)
+}
+
+/// Check that the signer is the counter authority or holds the admin role
+fn authorize_admin(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    counter_data: &CounterAccount,
+    signer_account: &AccountInfo,
+    accounts_iter: &mut std::slice::Iter<AccountInfo>,
+) -> ProgramResult {
+    if signer_account.key == &counter_data.authority {
+        return validate_authority(
+            program_id,
+            &counter_data.authority,
+            signer_account,
+            accounts_iter.as_slice(),
+        );
+    }
+
+    if !signer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let roles_account = next_account_info(accounts_iter)?;
+    check_role(program_id, counter_key, roles_account, Role::Admin, signer_account.key)
+}
+
+/// Load the roles account of a counter after checking the signer may manage it
+fn load_roles_for_admin(
+    program_id: &Pubkey,
+    counter_account: &AccountInfo,
+    admin_account: &AccountInfo,
+    roles_account: &AccountInfo,
+    signer_accounts: &[AccountInfo],
+) -> Result<RolesAccount, ProgramError> {
+    validate_counter_account(program_id, counter_account)?;
+    let counter_data = *CounterAccount::load(&counter_account.data.borrow())?;
+    check_not_timelocked(&counter_data)?;
+
+    let roles_data = load_roles(program_id, counter_account.key, roles_account)?;
+    if admin_account.key == &counter_data.authority {
+        validate_authority(
+            program_id,
+            &counter_data.authority,
+            admin_account,
+            signer_accounts,
+        )?;
+    } else {
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !roles_data.admins.contains(admin_account.key) {
+            return Err(CounterError::MissingRole.into());
+        }
+    }
+
+    Ok(roles_data)
+}
+
+/// Load a roles account and verify it belongs to the counter
+fn load_roles(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    roles_account: &AccountInfo,
+) -> Result<RolesAccount, ProgramError> {
+    if roles_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let roles_data = RolesAccount::try_from_slice(&roles_account.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    if &roles_data.counter != counter_key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let roles_key = Pubkey::create_program_address(
+        &[ROLES_SEED, counter_key.as_ref(), &[roles_data.bump]],
+        program_id,
+    )?;
+    if roles_account.key != &roles_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(roles_data)
+}
+
+/// Check that `key` holds `role` in the counter's roles account
+fn check_role(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    roles_account: &AccountInfo,
+    role: Role,
+    key: &Pubkey,
+) -> ProgramResult {
+    let roles_data = load_roles(program_id, counter_key, roles_account)?;
+    if !roles_data.holders(role).contains(key) {
+        return Err(CounterError::MissingRole.into());
+    }
+
+    Ok(())
+}
+
+/// Resize a program account to fit the data, settling rent with the payer
+fn write_resized_account<'a, T: BorshSerialize>(
+    account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    data: &T,
+) -> ProgramResult {
+    let serialized = borsh::to_vec(data)?;
+    let required_lamports = Rent::get()?.minimum_balance(serialized.len());
+    let current_lamports = account.lamports();
+
+    if required_lamports > current_lamports {
+        // Top up rent before growing the account
+        invoke(
+            &system_instruction::transfer(
+                payer_account.key,
+                account.key,
+                required_lamports - current_lamports,
+            ),
+            &[
+                payer_account.clone(),
+                account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    } else if current_lamports > required_lamports {
+        // Return rent freed by shrinking the account
+        let excess = current_lamports - required_lamports;
+        **account.try_borrow_mut_lamports()? = required_lamports;
+        **payer_account.try_borrow_mut_lamports()? = payer_account
+            .lamports()
+            .checked_add(excess)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    account.realloc(serialized.len(), false)?;
+    account.data.borrow_mut().copy_from_slice(&serialized);
+    Ok(())
+}
+
+/// Shrink a program account to fit the data, refunding freed rent to `destination`
+fn write_shrunk_account<T: BorshSerialize>(
+    account: &AccountInfo,
+    destination: &AccountInfo,
+    data: &T,
+) -> ProgramResult {
+    let serialized = borsh::to_vec(data)?;
+    if serialized.len() > account.data_len() {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+
+    let required_lamports = Rent::get()?.minimum_balance(serialized.len());
+    let excess = account.lamports().saturating_sub(required_lamports);
+    **account.try_borrow_mut_lamports()? -= excess;
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(excess)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    account.realloc(serialized.len(), false)?;
+    account.data.borrow_mut().copy_from_slice(&serialized);
+    Ok(())
+}
+
+/// Load a delegate record and verify it belongs to the counter and delegate
+fn load_delegate_record(
+    program_id: &Pubkey,
+    counter_key: &Pubkey,
+    delegate_key: &Pubkey,
+    delegate_record_account: &AccountInfo,
+) -> Result<DelegateAccount, ProgramError> {
+    if delegate_record_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let delegate_data = DelegateAccount::try_from_slice(&delegate_record_account.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    if &delegate_data.counter != counter_key || &delegate_data.delegate != delegate_key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let delegate_record_key = Pubkey::create_program_address(
+        &[
+            DELEGATE_SEED,
+            counter_key.as_ref(),
+            delegate_key.as_ref(),
+            &[delegate_data.bump],
+        ],
+        program_id,
+    )?;
+    if delegate_record_account.key != &delegate_record_key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(delegate_data)
+}
+
+/// Close a program-owned account, moving its lamports to the destination
+fn close_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let lamports = account.lamports();
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+
+    account.realloc(0, false)?;
+    account.assign(&system_program::id());
+    Ok(())
+}
+
+/// Emit a `CounterUpdated` event for a change and append it to the history extension of
+/// the counter, if it has one
+fn record_history(
+    counter_account: &AccountInfo,
+    signer: &Pubkey,
+    previous_count: u64,
+    count: u64,
+) -> ProgramResult {
+    CounterUpdated {
+        counter: *counter_account.key,
+        signer: *signer,
+        old_value: previous_count,
+        new_value: count,
+    }
+    .emit()?;
+
+    let mut data = counter_account.data.borrow_mut();
+    let Some(history) = data.get_mut(CounterAccount::LEN..).filter(|h| !h.is_empty()) else {
+        return Ok(());
+    };
+
+    let (header_data, entries) = history.split_at_mut(HistoryHeader::LEN);
+    let header: &mut HistoryHeader = bytemuck::from_bytes_mut(header_data);
+
+    let clock = Clock::get()?;
+    let entry = HistoryEntry {
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+        signer: *signer,
+        delta: count as i128 - previous_count as i128,
+        resulting_value: count,
+    };
+    let offset = header.next as usize * HistoryEntry::LEN;
+    entries[offset..offset + HistoryEntry::LEN].copy_from_slice(bytemuck::bytes_of(&entry));
+
+    // Overwrite the oldest entry once the buffer is full
+    header.next = (header.next + 1) % header.capacity;
+    header.len = (header.len + 1).min(header.capacity);
+    Ok(())
+}
+
+/// Take one program account per armed trigger of the counter, in slot order
+fn next_trigger_programs<'a, 'b>(
+    counter_data: &CounterAccount,
+    accounts_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> Result<Vec<&'b AccountInfo<'a>>, ProgramError> {
+    counter_data
+        .armed_triggers()
+        .map(|_| next_account_info(accounts_iter))
+        .collect()
+}
+
+/// Fire every armed trigger whose threshold lies in `(previous_count, count]`, marking it
+/// fired before invoking its program. `trigger_programs` holds one account per armed trigger
+/// of `counter_data`, in slot order; triggers fired since `counter_data` was loaded are skipped.
+fn fire_triggers<'a>(
+    counter_account: &AccountInfo<'a>,
+    counter_data: &CounterAccount,
+    trigger_programs: &[&AccountInfo<'a>],
+    previous_count: u64,
+    count: u64,
+) -> ProgramResult {
+    for (index, program_account) in counter_data.armed_triggers().zip(trigger_programs) {
+        let trigger = counter_data.triggers()[index];
+        if *program_account.key != { trigger.program } {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if previous_count >= trigger.threshold || count < trigger.threshold {
+            continue;
+        }
+
+        {
+            let mut data = counter_account.data.borrow_mut();
+            let fired = &mut CounterAccount::load_mut(&mut data)?.triggers_mut()[index];
+            // An earlier operation of the same batch may have fired it already
+            if fired.has_fired() {
+                continue;
+            }
+            fired.fired = 1;
+            fired.fired_slot = Clock::get()?.slot;
+        }
+
+        msg!("Firing trigger {} at {}", index, { trigger.threshold });
+        invoke(
+            &Instruction::new_with_bytes(
+                trigger.program,
+                trigger.data(),
+                vec![AccountMeta::new_readonly(*counter_account.key, false)],
+            ),
+            &[counter_account.clone(), (*program_account).clone()],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Hook program and the accounts forwarded to it for one increment or decrement
+struct HookAccounts<'a> {
+    program: AccountInfo<'a>,
+    /// Extra account list PDA followed by the listed accounts
+    accounts: Vec<AccountInfo<'a>>,
+    /// Metas of `accounts`, with the flags from the list
+    metas: Vec<AccountMeta>,
+}
+
+/// For counters with a hook, take the hook program, its extra account list PDA and the
+/// listed accounts, checking them against the list
+fn next_hook_accounts<'a>(
+    counter_key: &Pubkey,
+    counter_data: &CounterAccount,
+    accounts_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+) -> Result<Option<HookAccounts<'a>>, ProgramError> {
+    if !counter_data.has_hook() {
+        return Ok(None);
+    }
+
+    let hook_program = next_account_info(accounts_iter)?;
+    let extra_account_metas = next_account_info(accounts_iter)?;
+    if *hook_program.key != counter_data.hook_program() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (expected_address, _) = find_extra_account_metas_address(hook_program.key, counter_key);
+    if *extra_account_metas.key != expected_address || extra_account_metas.owner != hook_program.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let list = ExtraAccountMetaList::try_from_slice(&extra_account_metas.data.borrow())
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+
+    let mut hook_accounts = HookAccounts {
+        program: hook_program.clone(),
+        accounts: vec![extra_account_metas.clone()],
+        metas: vec![AccountMeta::new_readonly(*extra_account_metas.key, false)],
+    };
+    for meta in &list.accounts {
+        let account = next_account_info(accounts_iter)?;
+        if *account.key != meta.pubkey {
+            return Err(ProgramError::InvalidArgument);
+        }
+        hook_accounts.accounts.push(account.clone());
+        hook_accounts.metas.push(meta.into());
+    }
+
+    Ok(Some(hook_accounts))
+}
+
+/// Invoke the hook for `phase` if the counter has one that runs in it
+fn invoke_hook<'a>(
+    counter_data: &CounterAccount,
+    hook_accounts: &Option<HookAccounts<'a>>,
+    counter_account: &AccountInfo<'a>,
+    signer_account: &AccountInfo<'a>,
+    phase: HookPhase,
+    old_value: u64,
+    new_value: u64,
+) -> ProgramResult {
+    let Some(hook_accounts) = hook_accounts else {
+        return Ok(());
+    };
+    if !counter_data.hook_runs(phase) {
+        return Ok(());
+    }
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(*counter_account.key, false),
+        AccountMeta::new_readonly(*signer_account.key, false),
+    ];
+    metas.extend(hook_accounts.metas.iter().cloned());
+    let instruction = Instruction::new_with_borsh(
+        *hook_accounts.program.key,
+        &HookInstruction::Execute {
+            phase,
+            old_value,
+            new_value,
+            signer: *signer_account.key,
+        },
+        metas,
+    );
+
+    let mut account_infos = vec![counter_account.clone(), signer_account.clone()];
+    account_infos.extend(hook_accounts.accounts.iter().cloned());
+    account_infos.push(hook_accounts.program.clone());
+
+    msg!("Invoking {:?} hook", phase);
+    invoke(&instruction, &account_infos)
+}
+
+/// Apply a single operation to a counter value following its overflow mode
+fn apply_counter_op(
+    count: u64,
+    op: &CounterOp,
+    overflow_mode: OverflowMode,
+    default_step: u64,
+) -> Result<u64, ProgramError> {
+    match op {
+        CounterOp::Increment { step } => overflow_mode.add(count, step.unwrap_or(default_step)),
+        CounterOp::Decrement { step } => overflow_mode.sub(count, step.unwrap_or(default_step)),
+        CounterOp::Set { value } => Ok(*value),
+    }
+}
+
+/// Verify that the account is an initialized counter owned by this program
+fn validate_counter_account(program_id: &Pubkey, counter_account: &AccountInfo) -> ProgramResult {
+    // Verify account ownership
+    if counter_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Check if account is initialized
+    if counter_account.data.borrow().len() == 0 {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    Ok(())
+}
+
+/// Reject mutations of a frozen counter
+fn check_not_frozen(counter_data: &CounterAccount) -> ProgramResult {
+    if counter_data.is_frozen() {
+        return Err(CounterError::CounterFrozen.into());
+    }
+
+    Ok(())
+}
+
+/// For token-gated counters, take the next account as the signer's token account and
+/// check it holds at least the minimum balance of the gate mint
+fn check_token_gate(
+    counter_data: &CounterAccount,
+    signer_account: &AccountInfo,
+    accounts_iter: &mut std::slice::Iter<AccountInfo>,
+) -> ProgramResult {
+    if !counter_data.is_token_gated() {
+        return Ok(());
+    }
+
+    let token_account = next_account_info(accounts_iter)?;
+    if *token_account.owner != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let token = spl_token::state::Account::unpack(&token_account.data.borrow())?;
+    if token.owner != *signer_account.key
+        || token.mint != counter_data.gate_mint()
+        || token.amount < counter_data.gate_min_balance()
+    {
+        return Err(CounterError::MissingGateToken.into());
+    }
+
+    Ok(())
+}
+
+/// Transfer or burn the tokens owed for an increment of `step` from the signer's
+/// token account through the token program
+fn pay_tokens<'a>(
+    counter_data: &CounterAccount,
+    signer_account: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    step: u64,
+) -> ProgramResult {
+    if *token_program.key != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let amount = counter_data
+        .payment_per_step()
+        .checked_mul(step)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let instruction = match counter_data.payment_mode()? {
+        TokenPaymentMode::Transfer => {
+            if *destination.key != counter_data.payment_destination() {
+                return Err(ProgramError::InvalidArgument);
+            }
+            spl_token::instruction::transfer(
+                token_program.key,
+                source.key,
+                destination.key,
+                signer_account.key,
+                &[],
+                amount,
+            )?
+        }
+        TokenPaymentMode::Burn => {
+            if *destination.key != counter_data.payment_mint() {
+                return Err(ProgramError::InvalidArgument);
+            }
+            spl_token::instruction::burn(
+                token_program.key,
+                source.key,
+                destination.key,
+                signer_account.key,
+                &[],
+                amount,
+            )?
+        }
+    };
+    invoke(
+        &instruction,
+        &[source.clone(), destination.clone(), signer_account.clone(), token_program.clone()],
+    )?;
+
+    msg!("Paid {} tokens of {}", amount, counter_data.payment_mint());
+    Ok(())
+}
+
+/// Reject mutations of a counter past its expiry
+fn check_not_expired(counter_data: &CounterAccount) -> ProgramResult {
+    // Counters without expiry skip the clock sysvar
+    if counter_data.expires_at() != 0 && counter_data.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(CounterError::CounterExpired.into());
+    }
+
+    Ok(())
+}
+
+/// Reject negative timelock delays and delays above `MAX_TIMELOCK_DELAY`
+fn check_timelock_delay(delay: i64) -> ProgramResult {
+    if !(0..=MAX_TIMELOCK_DELAY).contains(&delay) {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Reject authority changes of a timelocked counter that have to go through ScheduleChange
+fn check_not_timelocked(counter_data: &CounterAccount) -> ProgramResult {
+    if counter_data.timelock_delay() > 0 {
+        return Err(CounterError::TimelockRequired.into());
+    }
+
+    Ok(())
+}
+
+/// Reject instructions that would move a tokenized counter away from its token supply
+fn check_not_tokenized(counter_data: &CounterAccount) -> ProgramResult {
+    if counter_data.is_tokenized() {
+        return Err(CounterError::CounterTokenized.into());
+    }
+
+    Ok(())
+}
+
+/// Verify that the authority account is the expected key and signed the transaction.
+/// A multisig authority instead needs `threshold` of its listed keys among `signer_accounts`.
+fn validate_authority(
+    program_id: &Pubkey,
+    expected_authority: &Pubkey,
+    authority_account: &AccountInfo,
+    signer_accounts: &[AccountInfo],
+) -> ProgramResult {
+    if authority_account.key != expected_authority {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+
+    if authority_account.owner == program_id
+        && MultisigAccount::is_multisig(&authority_account.data.borrow())
+    {
+        let multisig_data = MultisigAccount::try_from_slice(&authority_account.data.borrow())?;
+        return check_multisig_signers(&multisig_data, signer_accounts);
+    }
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    Ok(())
+}
+
+/// Check that `threshold` of the keys listed in a multisig signed among `signer_accounts`
+fn check_multisig_signers(multisig_data: &MultisigAccount, signer_accounts: &[AccountInfo]) -> ProgramResult {
+    let listed_signers = &multisig_data.signers[..multisig_data.signer_count as usize];
+
+    // Count each listed key at most once
+    let mut matched = [false; MAX_MULTISIG_SIGNERS];
+    let mut signed = 0u8;
+    for signer_account in signer_accounts {
+        for (position, key) in listed_signers.iter().enumerate() {
+            if key == signer_account.key && !matched[position] {
+                if !signer_account.is_signer {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+                matched[position] = true;
+                signed += 1;
+            }
+        }
+    }
+
+    if signed < multisig_data.threshold {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
@@ -0,0 +1,549 @@
+//! Native test harness that runs `process_instruction` without a compiled `.so`.
+//!
+//! Instructions are serialized into the same input buffer the SBF loader builds,
+//! so `AccountInfo::realloc` and lamport moves behave as they do on chain. System
+//! program CPIs and the `Clock`/`Rent` sysvars are served by syscall stubs.
+//! Every program invocation, including CPIs, is held to the runtime's account
+//! rules: only the owner of an account may change its data, debit its lamports
+//! or hand it to another owner, and no account may be left rent-paying.
+
+#![allow(dead_code)]
+
+use std::{cell::RefCell, collections::HashMap, sync::Once};
+
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader,
+    clock::Clock,
+    entrypoint::{deserialize, ProgramResult, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+/// Entrypoint of a program registered with the harness
+pub type Processor = fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult;
+
+/// Account state held by the harness
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+impl Account {
+    fn system() -> Self {
+        Self {
+            owner: system_program::id(),
+            ..Self::default()
+        }
+    }
+
+    /// Current state of an account handed to a program
+    fn of(info: &AccountInfo) -> Self {
+        Self {
+            lamports: info.lamports(),
+            data: info.data.borrow().to_vec(),
+            owner: *info.owner,
+            executable: info.executable,
+        }
+    }
+
+    /// Holds lamports but fewer than rent exemption requires for its data
+    fn is_rent_paying(&self) -> bool {
+        self.lamports > 0 && self.lamports < Rent::default().minimum_balance(self.data.len())
+    }
+}
+
+/// A running program and the state of its accounts when it last gained control
+struct Frame {
+    program_id: Pubkey,
+    accounts: Vec<(Pubkey, bool, Account)>,
+    /// Lamports across the accounts on entry, which no CPI can change
+    lamports: u128,
+}
+
+impl Frame {
+    fn new(program_id: &Pubkey, infos: &[AccountInfo]) -> Self {
+        let mut accounts: Vec<(Pubkey, bool, Account)> = Vec::with_capacity(infos.len());
+        for info in infos {
+            if !accounts.iter().any(|(key, _, _)| key == info.key) {
+                accounts.push((*info.key, info.is_writable, Account::of(info)));
+            }
+        }
+        let lamports = accounts.iter().map(|(_, _, account)| account.lamports as u128).sum();
+        Self {
+            program_id: *program_id,
+            accounts,
+            lamports,
+        }
+    }
+}
+
+thread_local! {
+    // Tests run on separate threads, so each one gets its own programs and clock
+    static PROGRAMS: RefCell<HashMap<Pubkey, Processor>> = RefCell::new(HashMap::new());
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock::default());
+    static LOGGED_DATA: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The runtime's checks on the changes `program_id` made to one account.
+/// The runtime's own errors have no `ProgramError` equivalent, so every
+/// violation surfaces as `InvalidArgument`.
+fn verify_account_change(
+    program_id: &Pubkey,
+    is_writable: bool,
+    before: &Account,
+    after: &Account,
+) -> ProgramResult {
+    if after == before {
+        return Ok(());
+    }
+    let is_owner = before.owner == *program_id;
+
+    // Executable accounts and read-only accounts never change
+    if before.executable || !is_writable {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // Only the owner may hand an account over, and only with zeroed data
+    if after.owner != before.owner
+        && (!is_owner || after.data.iter().any(|byte| *byte != 0))
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // Only the owner may change the data or its size
+    if after.data != before.data && !is_owner {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // Anyone may credit a writable account, only the owner may debit it
+    if after.lamports < before.lamports && !is_owner {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+struct NativeStubs;
+
+impl SyscallStubs for NativeStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        LOGGED_DATA.with(|logged| logged.borrow_mut().push(fields.concat()));
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = CALL_STACK.with(|stack| stack.borrow().last().expect("CPI outside of a program").program_id);
+        let pda_signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+        // The callee sees the caller's accounts with the privileges of the instruction
+        let mut callee_infos = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let info = account_infos
+                .iter()
+                .find(|info| info.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            if meta.is_signer && !info.is_signer && !pda_signers.contains(&meta.pubkey) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            let mut callee_info = info.clone();
+            callee_info.is_signer = meta.is_signer;
+            callee_info.is_writable = meta.is_writable;
+            callee_infos.push(callee_info);
+        }
+
+        // The caller's changes so far are checked before the callee sees them
+        CALL_STACK.with(|stack| {
+            let stack = stack.borrow();
+            let frame = stack.last().expect("CPI outside of a program");
+            for (key, is_writable, before) in &frame.accounts {
+                if let Some(info) = account_infos.iter().find(|info| info.key == key) {
+                    verify_account_change(&frame.program_id, *is_writable, before, &Account::of(info))?;
+                }
+            }
+            Ok::<_, ProgramError>(())
+        })?;
+
+        run_program(&instruction.program_id, &callee_infos, &instruction.data)?;
+
+        // The callee's changes are its own, so the caller continues from here
+        CALL_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let frame = stack.last_mut().expect("CPI outside of a program");
+            for (key, _, before) in &mut frame.accounts {
+                if let Some(info) = account_infos.iter().find(|info| info.key == key) {
+                    *before = Account::of(info);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = CLOCK.with(|clock| clock.borrow().clone());
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+}
+
+/// Dispatch to the system program stub or a registered processor and check
+/// the account changes it made
+fn run_program(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let processor = if *program_id == system_program::id() {
+        process_system_instruction
+    } else {
+        PROGRAMS
+            .with(|programs| programs.borrow().get(program_id).copied())
+            .ok_or(ProgramError::IncorrectProgramId)?
+    };
+
+    CALL_STACK.with(|stack| stack.borrow_mut().push(Frame::new(program_id, accounts)));
+    let result = processor(program_id, accounts, data);
+    let frame = CALL_STACK.with(|stack| stack.borrow_mut().pop().expect("Call stack underflow"));
+    result?;
+
+    let mut lamports: u128 = 0;
+    for (key, is_writable, before) in &frame.accounts {
+        let info = accounts.iter().find(|info| info.key == key).unwrap();
+        let after = Account::of(info);
+        verify_account_change(program_id, *is_writable, before, &after)?;
+        lamports += after.lamports as u128;
+    }
+    if lamports != frame.lamports {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// The subset of the system program used by the counter program
+fn process_system_instruction(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    match decode_system_instruction(data).ok_or(ProgramError::InvalidInstructionData)? {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if !from.is_signer || !to.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if to.lamports() != 0 || !to.data_is_empty() || to.owner != &system_program::id() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            transfer_lamports(from, to, lamports)?;
+            to.realloc(space as usize, true)?;
+            to.assign(&owner);
+            Ok(())
+        }
+        SystemInstruction::Transfer { lamports } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if !from.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if from.owner != &system_program::id() || !from.data_is_empty() {
+                return Err(ProgramError::InvalidArgument);
+            }
+            transfer_lamports(from, to, lamports)
+        }
+        SystemInstruction::Allocate { space } => {
+            if !accounts[0].is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if !accounts[0].data_is_empty() || accounts[0].owner != &system_program::id() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            accounts[0].realloc(space as usize, true)
+        }
+        SystemInstruction::Assign { owner } => {
+            if !accounts[0].is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            // Only system accounts can be handed to another owner
+            if accounts[0].owner != &owner && accounts[0].owner != &system_program::id() {
+                return Err(ProgramError::IllegalOwner);
+            }
+            accounts[0].assign(&owner);
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    let mut from_lamports = from.try_borrow_mut_lamports()?;
+    **from_lamports = from_lamports
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let mut to_lamports = to.try_borrow_mut_lamports()?;
+    **to_lamports = to_lamports
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+/// Decode the bincode layout of the system instructions the harness supports
+fn decode_system_instruction(data: &[u8]) -> Option<SystemInstruction> {
+    let u64_at = |offset: usize| Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?));
+    let pubkey_at = |offset: usize| Some(Pubkey::new_from_array(data.get(offset..offset + 32)?.try_into().ok()?));
+
+    match u32::from_le_bytes(data.get(..4)?.try_into().ok()?) {
+        0 => Some(SystemInstruction::CreateAccount {
+            lamports: u64_at(4)?,
+            space: u64_at(12)?,
+            owner: pubkey_at(20)?,
+        }),
+        1 => Some(SystemInstruction::Assign { owner: pubkey_at(4)? }),
+        2 => Some(SystemInstruction::Transfer { lamports: u64_at(4)? }),
+        8 => Some(SystemInstruction::Allocate { space: u64_at(4)? }),
+        _ => None,
+    }
+}
+
+/// Offsets of one serialized account inside the program input buffer
+struct AccountLayout {
+    key: Pubkey,
+    is_writable: bool,
+    owner_offset: usize,
+    lamports_offset: usize,
+    data_len_offset: usize,
+}
+
+/// In-memory ledger that executes instructions natively
+pub struct NativeSvm {
+    accounts: HashMap<Pubkey, Account>,
+}
+
+impl Default for NativeSvm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NativeSvm {
+    pub fn new() -> Self {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(NativeStubs));
+        });
+        CLOCK.with(|clock| *clock.borrow_mut() = Clock::default());
+        LOGGED_DATA.with(|logged| logged.borrow_mut().clear());
+
+        Self {
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// Register `processor` as the program deployed at `program_id`
+    pub fn add_program(&mut self, program_id: Pubkey, processor: Processor) {
+        PROGRAMS.with(|programs| programs.borrow_mut().insert(program_id, processor));
+        self.accounts.insert(
+            program_id,
+            Account {
+                lamports: 1,
+                executable: true,
+                owner: bpf_loader::id(),
+                ..Account::default()
+            },
+        );
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        CLOCK.with(|current| *current.borrow_mut() = clock);
+    }
+
+    pub fn airdrop(&mut self, key: &Pubkey, lamports: u64) {
+        self.accounts.entry(*key).or_insert_with(Account::system).lamports += lamports;
+    }
+
+    /// Account stored at `key`, `None` once it holds no lamports
+    pub fn get_account(&self, key: &Pubkey) -> Option<&Account> {
+        self.accounts.get(key).filter(|account| account.lamports > 0)
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, account: Account) {
+        self.accounts.insert(key, account);
+    }
+
+    /// Take the `sol_log_data` entries of the transactions processed since the last call
+    pub fn take_logged_data(&mut self) -> Vec<Vec<u8>> {
+        LOGGED_DATA.with(|logged| logged.take())
+    }
+
+    /// Execute `instructions` atomically, as a transaction signed by `signers`
+    pub fn process(&mut self, instructions: &[Instruction], signers: &[&Keypair]) -> ProgramResult {
+        let signers: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+        let snapshot = self.accounts.clone();
+        let logged_len = LOGGED_DATA.with(|logged| logged.borrow().len());
+
+        let result = instructions
+            .iter()
+            .try_for_each(|instruction| self.process_instruction(instruction, &signers))
+            .and_then(|()| self.check_rent_state(&snapshot, instructions));
+        if result.is_err() {
+            self.accounts = snapshot;
+            LOGGED_DATA.with(|logged| logged.borrow_mut().truncate(logged_len));
+        }
+        result
+    }
+
+    /// No writable account may end the transaction newly rent-paying. One that
+    /// already was may only shrink its balance without changing its size.
+    fn check_rent_state(&self, snapshot: &HashMap<Pubkey, Account>, instructions: &[Instruction]) -> ProgramResult {
+        let writable = instructions
+            .iter()
+            .flat_map(|instruction| &instruction.accounts)
+            .filter(|meta| meta.is_writable);
+        for meta in writable {
+            let after = self.account_or_default(&meta.pubkey);
+            if !after.is_rent_paying() {
+                continue;
+            }
+            let before = snapshot.get(&meta.pubkey).cloned().unwrap_or_else(Account::system);
+            if !before.is_rent_paying()
+                || before.data.len() != after.data.len()
+                || after.lamports > before.lamports
+            {
+                return Err(ProgramError::AccountNotRentExempt);
+            }
+        }
+        Ok(())
+    }
+
+    fn process_instruction(&mut self, instruction: &Instruction, signers: &[Pubkey]) -> ProgramResult {
+        let metas = &instruction.accounts;
+        if metas
+            .iter()
+            .any(|meta| meta.is_signer && !signers.contains(&meta.pubkey))
+        {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // Serialize the accounts in the aligned loader format
+        let mut bytes = Vec::new();
+        let mut layouts = Vec::new();
+        let mut seen: Vec<Pubkey> = Vec::new();
+        bytes.extend_from_slice(&(metas.len() as u64).to_le_bytes());
+        for meta in metas {
+            if let Some(position) = seen.iter().position(|key| key == &meta.pubkey) {
+                bytes.push(position as u8);
+                bytes.extend_from_slice(&[0; 7]);
+                seen.push(meta.pubkey);
+                continue;
+            }
+            seen.push(meta.pubkey);
+
+            // Duplicate metas share the union of their privileges
+            let is_signer = metas.iter().any(|other| other.pubkey == meta.pubkey && other.is_signer);
+            let is_writable = metas.iter().any(|other| other.pubkey == meta.pubkey && other.is_writable);
+            let account = self.account_or_default(&meta.pubkey);
+
+            bytes.push(NON_DUP_MARKER);
+            bytes.push(is_signer as u8);
+            bytes.push(is_writable as u8);
+            bytes.push(account.executable as u8);
+            bytes.extend_from_slice(&[0; 4]);
+            bytes.extend_from_slice(meta.pubkey.as_ref());
+            let owner_offset = bytes.len();
+            bytes.extend_from_slice(account.owner.as_ref());
+            let lamports_offset = bytes.len();
+            bytes.extend_from_slice(&account.lamports.to_le_bytes());
+            let data_len_offset = bytes.len();
+            bytes.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&account.data);
+            bytes.resize(bytes.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+            bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+
+            layouts.push(AccountLayout {
+                key: meta.pubkey,
+                is_writable,
+                owner_offset,
+                lamports_offset,
+                data_len_offset,
+            });
+        }
+        bytes.extend_from_slice(&(instruction.data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&instruction.data);
+        bytes.extend_from_slice(instruction.program_id.as_ref());
+
+        // Backed by u64s so the buffer has the alignment `deserialize` expects
+        let mut input = vec![0u64; bytes.len().div_ceil(8)];
+        let buffer = unsafe {
+            std::slice::from_raw_parts_mut(input.as_mut_ptr() as *mut u8, input.len() * 8)
+        };
+        buffer[..bytes.len()].copy_from_slice(&bytes);
+
+        {
+            let (program_id, accounts, data) = unsafe { deserialize(buffer.as_mut_ptr()) };
+            run_program(program_id, &accounts, data)?;
+        }
+
+        // Read the accounts back; `run_program` already checked the changes
+        let read_u64 = |offset: usize| u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap());
+        let mut lamports_before: u128 = 0;
+        let mut lamports_after: u128 = 0;
+        let mut updates = Vec::new();
+        for layout in &layouts {
+            let before = self.account_or_default(&layout.key);
+            let data_len = read_u64(layout.data_len_offset) as usize;
+            let data_start = layout.data_len_offset + 8;
+            let after = Account {
+                lamports: read_u64(layout.lamports_offset),
+                data: buffer[data_start..data_start + data_len].to_vec(),
+                owner: Pubkey::new_from_array(
+                    buffer[layout.owner_offset..layout.owner_offset + 32].try_into().unwrap(),
+                ),
+                executable: before.executable,
+            };
+
+            lamports_before += before.lamports as u128;
+            lamports_after += after.lamports as u128;
+            if after != before {
+                if !layout.is_writable {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                updates.push((layout.key, after));
+            }
+        }
+        if lamports_before != lamports_after {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        for (key, account) in updates {
+            if account.lamports == 0 {
+                self.accounts.remove(&key);
+            } else {
+                self.accounts.insert(key, account);
+            }
+        }
+        Ok(())
+    }
+
+    fn account_or_default(&self, key: &Pubkey) -> Account {
+        self.accounts.get(key).cloned().unwrap_or_else(Account::system)
+    }
+}
@@ -0,0 +1,930 @@
+//! Compute-unit benchmarks for every `CounterInstruction` variant.
+//!
+//! Each case is executed in LiteSVM and the consumed compute units are compared
+//! against `tests/compute_units_baseline.txt`. A case fails when it uses more than
+//! `CU_THRESHOLD_PERCENT` (default 5) percent above its baseline.
+//!
+//! Cases without a baseline entry fail, so every new case gets one.
+//!
+//! The `borsh_*` cases run the last build that still decoded and re-encoded the whole
+//! counter with Borsh on every increment, so the baseline records the zero-copy layout
+//! against it. `scripts/compute-units.sh` builds that revision from `git archive` into
+//! `target/deploy/solana_counter_program_borsh.so`, builds the current program and runs
+//! this benchmark; record a new baseline with:
+//!
+//!     UPDATE_CU_BASELINE=1 scripts/compute-units.sh
+
+#![cfg(feature = "test-sbf")]
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use borsh::BorshSerialize;
+use litesvm::LiteSVM;
+use solana_counter_program::*;
+use solana_sdk::{
+    account::Account,
+    bpf_loader_upgradeable,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+    transaction::Transaction,
+};
+
+const BASELINE_PATH: &str = "tests/compute_units_baseline.txt";
+const PROGRAM_PATH: &str = "target/deploy/solana_counter_program.so";
+const BORSH_PROGRAM_PATH: &str = "target/deploy/solana_counter_program_borsh.so";
+const DEFAULT_THRESHOLD_PERCENT: u64 = 5;
+
+/// Counters touched by the largest batch case, chosen to fit in one transaction
+const MAX_BATCH_COUNTERS: usize = 16;
+
+/// Multisig approvals in the largest multisig case, chosen to fit in one transaction
+const MAX_MULTISIG_APPROVALS: usize = 8;
+
+struct Bench {
+    svm: LiteSVM,
+    program_id: Pubkey,
+    payer: Keypair,
+    results: BTreeMap<&'static str, u64>,
+}
+
+impl Bench {
+    fn new() -> Self {
+        Self::with_program(PROGRAM_PATH)
+    }
+
+    fn with_program(path: &str) -> Self {
+        let mut svm = LiteSVM::new();
+
+        let payer = Keypair::new();
+        svm.airdrop(&payer.pubkey(), 100_000_000_000)
+            .expect("Failed to airdrop");
+
+        let program_id = Keypair::new().pubkey();
+        svm.add_program_from_file(program_id, path)
+            .unwrap_or_else(|error| panic!("Failed to load {}: {:?}", path, error));
+
+        Self {
+            svm,
+            program_id,
+            payer,
+            results: BTreeMap::new(),
+        }
+    }
+
+    fn config(&self) -> AccountMeta {
+        AccountMeta::new_readonly(find_config_address(&self.program_id).0, false)
+    }
+
+    fn registry(&self, authority: &Pubkey) -> AccountMeta {
+        AccountMeta::new(find_registry_address(&self.program_id, authority).0, false)
+    }
+
+    /// Send a transaction paid by the bench payer and return its compute units
+    fn send(&mut self, instruction: Instruction, signers: &[&Keypair]) -> u64 {
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+
+        let message = Message::new(&[instruction], Some(&self.payer.pubkey()));
+        let transaction = Transaction::new(&all_signers, message, self.svm.latest_blockhash());
+        let metadata = self
+            .svm
+            .send_transaction(transaction)
+            .unwrap_or_else(|failure| panic!("Transaction failed: {:?}", failure.err));
+        self.svm.expire_blockhash();
+
+        metadata.compute_units_consumed
+    }
+
+    /// Send a transaction and record its compute units under `name`
+    fn measure(&mut self, name: &'static str, instruction: Instruction, signers: &[&Keypair]) {
+        let units = self.send(instruction, signers);
+        self.results.insert(name, units);
+    }
+
+    fn initialize_instruction(
+        &self,
+        counter: &Keypair,
+        metadata: Option<CounterMetadata>,
+        history_capacity: u16,
+    ) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(counter.pubkey(), true),
+            AccountMeta::new(self.payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            self.registry(&self.payer.pubkey()),
+        ];
+        if metadata.is_some() {
+            let (metadata_address, _) = find_metadata_address(&self.program_id, &counter.pubkey());
+            accounts.push(AccountMeta::new(metadata_address, false));
+        }
+        accounts.push(self.config());
+
+        counter_instruction(
+            &self.program_id,
+            &CounterInstruction::InitializeCounter {
+                initial_value: 0,
+                metadata,
+                history_capacity,
+            },
+            accounts,
+        )
+    }
+
+    /// Store a packed SPL token account of `mint` without going through the token program
+    fn token_account(&mut self, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+        let token = spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(token, &mut data).expect("Failed to pack token account");
+
+        let address = Pubkey::new_unique();
+        self.svm
+            .set_account(
+                address,
+                Account {
+                    lamports: self.svm.minimum_balance_for_rent_exemption(data.len()),
+                    data,
+                    owner: spl_token::id(),
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            )
+            .expect("Failed to store token account");
+        address
+    }
+
+    /// Store a packed SPL token mint controlled by `mint_authority` with no supply
+    fn mint(&mut self, mint_authority: &Pubkey) -> Pubkey {
+        let mint = spl_token::state::Mint {
+            mint_authority: Some(*mint_authority).into(),
+            is_initialized: true,
+            ..spl_token::state::Mint::default()
+        };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint::pack(mint, &mut data).expect("Failed to pack mint");
+
+        let address = Pubkey::new_unique();
+        self.svm
+            .set_account(
+                address,
+                Account {
+                    lamports: self.svm.minimum_balance_for_rent_exemption(data.len()),
+                    data,
+                    owner: spl_token::id(),
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            )
+            .expect("Failed to store mint");
+        address
+    }
+
+    /// Create a counter owned by the payer without recording its cost
+    fn counter(&mut self, history_capacity: u16) -> Pubkey {
+        let counter = Keypair::new();
+        let instruction = self.initialize_instruction(&counter, None, history_capacity);
+        self.send(instruction, &[&counter]);
+        counter.pubkey()
+    }
+
+    /// Instruction signed by the payer as counter authority
+    fn authority_instruction(&self, instruction: CounterInstruction, counter: Pubkey) -> Instruction {
+        counter_instruction(
+            &self.program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(self.payer.pubkey(), true),
+                self.config(),
+            ],
+        )
+    }
+}
+
+#[test]
+fn compute_units() {
+    let mut bench = Bench::new();
+    let program_id = bench.program_id;
+    let payer = bench.payer.pubkey();
+
+    // Config, with the bench payer recorded as upgrade authority of the program
+    let (config, _) = find_config_address(&program_id);
+    let (program_data, _) = find_program_data_address(&program_id);
+    let mut program_data_bytes = 3u32.to_le_bytes().to_vec();
+    program_data_bytes.extend_from_slice(&0u64.to_le_bytes());
+    program_data_bytes.push(1);
+    program_data_bytes.extend_from_slice(payer.as_ref());
+    bench
+        .svm
+        .set_account(
+            program_data,
+            Account {
+                lamports: bench.svm.minimum_balance_for_rent_exemption(program_data_bytes.len()),
+                data: program_data_bytes,
+                owner: bpf_loader_upgradeable::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        )
+        .expect("Failed to set the program data account");
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeConfig,
+        vec![
+            AccountMeta::new(config, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(program_data, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    bench.measure("initialize_config", instruction, &[]);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::UpdateConfig {
+            admin: payer,
+            default_step: 1,
+            default_overflow_mode: OverflowMode::Checked,
+        },
+        vec![AccountMeta::new(config, false), AccountMeta::new_readonly(payer, true)],
+    );
+    bench.measure("update_config", instruction, &[]);
+
+    for (name, paused) in [("set_paused", true), ("set_unpaused", false)] {
+        let instruction = counter_instruction(
+            &program_id,
+            &CounterInstruction::SetPaused { paused },
+            vec![AccountMeta::new(config, false), AccountMeta::new_readonly(payer, true)],
+        );
+        bench.measure(name, instruction, &[]);
+    }
+
+    // Initialization
+    let counter = Keypair::new();
+    let instruction = bench.initialize_instruction(&counter, None, 0);
+    bench.measure("initialize_counter", instruction, &[&counter]);
+    let counter = counter.pubkey();
+
+    let named_counter = Keypair::new();
+    let metadata = CounterMetadata {
+        name: "n".repeat(MAX_NAME_LEN),
+        description: "d".repeat(MAX_DESCRIPTION_LEN),
+    };
+    let instruction = bench.initialize_instruction(&named_counter, Some(metadata), 0);
+    bench.measure("initialize_counter_max_metadata", instruction, &[&named_counter]);
+
+    let history_counter = Keypair::new();
+    let instruction = bench.initialize_instruction(&history_counter, None, MAX_HISTORY_CAPACITY);
+    bench.measure("initialize_counter_max_history", instruction, &[&history_counter]);
+    let history_counter = history_counter.pubkey();
+
+    // Increment and decrement
+    let instruction = bench.authority_instruction(CounterInstruction::IncrementCounter { step: None }, counter);
+    bench.measure("increment", instruction, &[]);
+    let instruction = bench.authority_instruction(CounterInstruction::DecrementCounter { step: None }, counter);
+    bench.measure("decrement", instruction, &[]);
+    let instruction = increment_counter(&program_id, &counter, &payer, None);
+    bench.measure("increment_compact", instruction, &[]);
+
+    // Fill the history buffer so the measured increment overwrites the oldest entry
+    for _ in 0..MAX_HISTORY_CAPACITY {
+        let instruction = bench.authority_instruction(
+            CounterInstruction::IncrementCounter { step: None },
+            history_counter,
+        );
+        bench.send(instruction, &[]);
+    }
+    let instruction = bench.authority_instruction(
+        CounterInstruction::IncrementCounter { step: None },
+        history_counter,
+    );
+    bench.measure("increment_full_history", instruction, &[]);
+
+    // Increment fee paid to a treasury
+    let fee_counter = bench.counter(0);
+    let treasury = Pubkey::new_unique();
+    bench.svm.airdrop(&treasury, 1_000_000_000).expect("Failed to airdrop");
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetIncrementFee {
+            fee_lamports: 5_000,
+            treasury,
+        },
+        fee_counter,
+    );
+    bench.measure("set_increment_fee", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(fee_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(payer, true),
+            AccountMeta::new(treasury, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_with_fee", instruction, &[]);
+
+    // Increment gated on an SPL token balance
+    let gated_counter = bench.counter(0);
+    let mint = Pubkey::new_unique();
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetTokenGate { mint, min_balance: 1 },
+        gated_counter,
+    );
+    bench.measure("set_token_gate", instruction, &[]);
+    let token_account = bench.token_account(&mint, &payer, 1);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(gated_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new_readonly(token_account, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_token_gated", instruction, &[]);
+
+    // Increment paid for with SPL tokens
+    let paid_counter = bench.counter(0);
+    let source = bench.token_account(&mint, &payer, 1_000);
+    let destination = bench.token_account(&mint, &Pubkey::new_unique(), 0);
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetTokenPayment {
+            mint,
+            amount_per_step: 10,
+            mode: TokenPaymentMode::Transfer,
+            destination,
+        },
+        paid_counter,
+    );
+    bench.measure("set_token_payment", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(paid_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_token_payment", instruction, &[]);
+
+    // Counter units minted on increment and burned on decrement
+    let units_counter = bench.counter(0);
+    let (mint_authority, _) = find_mint_authority_address(&program_id, &units_counter);
+    let units_mint = bench.mint(&mint_authority);
+    let units = bench.token_account(&units_mint, &payer, 0);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetUnitsMint { mint: units_mint },
+        vec![
+            AccountMeta::new(units_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new_readonly(units_mint, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("set_units_mint", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(units_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(units_mint, false),
+            AccountMeta::new(units, false),
+            AccountMeta::new_readonly(mint_authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_mint_units", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::DecrementCounter { step: None },
+        vec![
+            AccountMeta::new(units_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(units, false),
+            AccountMeta::new(units_mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("decrement_burn_units", instruction, &[]);
+
+    // Increment firing a trigger into the memo program shipped with LiteSVM
+    let trigger_counter = bench.counter(0);
+    let memo_program = Pubkey::from_str_const("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetTrigger {
+            index: 0,
+            threshold: 1,
+            program: memo_program,
+            data: vec![b'm'; MAX_TRIGGER_DATA_LEN],
+        },
+        trigger_counter,
+    );
+    bench.measure("set_trigger", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(trigger_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new_readonly(memo_program, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_fire_trigger", instruction, &[]);
+
+    let hook_counter = bench.counter(0);
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetHook {
+            program: Pubkey::new_unique(),
+            pre: true,
+            post: true,
+        },
+        hook_counter,
+    );
+    bench.measure("set_hook", instruction, &[]);
+
+    // Expiry and garbage collection
+    let expiring_counter = bench.counter(0);
+    let mut clock: Clock = bench.svm.get_sysvar();
+    let instruction = bench.authority_instruction(
+        CounterInstruction::SetExpiry {
+            expires_at: clock.unix_timestamp + 1,
+        },
+        expiring_counter,
+    );
+    bench.measure("set_expiry", instruction, &[]);
+    clock.unix_timestamp += 1;
+    bench.svm.set_sysvar(&clock);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseExpiredCounter,
+        vec![
+            AccountMeta::new(expiring_counter, false),
+            AccountMeta::new(payer, false),
+            bench.registry(&payer),
+            AccountMeta::new(find_roles_address(&program_id, &expiring_counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &expiring_counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &expiring_counter).0, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("close_expired_counter", instruction, &[]);
+
+    // Timelocked changes
+    let timelocked_counter = bench.counter(0);
+    let instruction = bench.authority_instruction(CounterInstruction::SetTimelock { delay: 1 }, timelocked_counter);
+    bench.measure("set_timelock", instruction, &[]);
+    let scheduled = find_scheduled_change_address(&program_id, &timelocked_counter).0;
+    let schedule_instruction = |bench: &Bench| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::ScheduleChange {
+                action: ScheduledAction::SetCounter { value: 7 },
+            },
+            vec![
+                AccountMeta::new_readonly(timelocked_counter, false),
+                AccountMeta::new_readonly(payer, true),
+                AccountMeta::new(scheduled, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                bench.config(),
+            ],
+        )
+    };
+    let instruction = schedule_instruction(&bench);
+    bench.measure("schedule_change", instruction, &[]);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CancelChange,
+        vec![
+            AccountMeta::new_readonly(timelocked_counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("cancel_change", instruction, &[]);
+    let instruction = schedule_instruction(&bench);
+    bench.send(instruction, &[]);
+    let mut clock: Clock = bench.svm.get_sysvar();
+    clock.unix_timestamp += 1;
+    bench.svm.set_sysvar(&clock);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ExecuteChange,
+        vec![
+            AccountMeta::new(timelocked_counter, false),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("execute_change", instruction, &[]);
+
+    // Batch
+    let batch_counters: Vec<Pubkey> = (0..MAX_BATCH_COUNTERS).map(|_| bench.counter(0)).collect();
+    let mut accounts: Vec<AccountMeta> = batch_counters
+        .iter()
+        .map(|counter| AccountMeta::new(*counter, false))
+        .collect();
+    accounts.push(AccountMeta::new_readonly(payer, true));
+    accounts.push(bench.config());
+    let ops = (0..MAX_BATCH_COUNTERS as u8)
+        .flat_map(|index| {
+            [
+                (index, CounterOp::Increment { step: Some(2) }),
+                (index, CounterOp::Decrement { step: Some(1) }),
+            ]
+        })
+        .collect();
+    let instruction = counter_instruction(&program_id, &CounterInstruction::Batch { ops }, accounts);
+    bench.measure("batch_max_counters", instruction, &[]);
+
+    // Transfer
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 1 },
+        vec![
+            AccountMeta::new(batch_counters[0], false),
+            AccountMeta::new(batch_counters[1], false),
+            AccountMeta::new_readonly(payer, true),
+            bench.config(),
+        ],
+    );
+    bench.measure("transfer_between_counters", instruction, &[]);
+
+    // Delegates
+    let delegate = Keypair::new();
+    let (delegate_record, _) = find_delegate_address(&program_id, &counter, &delegate.pubkey());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ApproveDelegate {
+            increment_allowance: 10,
+            decrement_allowance: 10,
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("approve_delegate", instruction, &[]);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(delegate.pubkey(), true),
+            AccountMeta::new(delegate_record, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_by_delegate", instruction, &[&delegate]);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::RevokeDelegate,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("revoke_delegate", instruction, &[]);
+
+    // Roles
+    let (roles, _) = find_roles_address(&program_id, &counter);
+    let roles_instruction = |instruction: CounterInstruction, system: bool| {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new(roles, false),
+        ];
+        if system {
+            accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        }
+        accounts.push(AccountMeta::new_readonly(config, false));
+        counter_instruction(&program_id, &instruction, accounts)
+    };
+    bench.measure("initialize_roles", roles_instruction(CounterInstruction::InitializeRoles, true), &[]);
+
+    let holder = Keypair::new();
+    let instruction = roles_instruction(
+        CounterInstruction::AddRoleHolder {
+            role: Role::Incrementer,
+            holder: holder.pubkey(),
+        },
+        true,
+    );
+    bench.measure("add_role_holder", instruction, &[]);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(holder.pubkey(), true),
+            AccountMeta::new_readonly(roles, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("increment_by_role_holder", instruction, &[&holder]);
+
+    let instruction = roles_instruction(
+        CounterInstruction::SetRolePublic {
+            role: Role::Decrementer,
+            public: true,
+        },
+        false,
+    );
+    bench.measure("set_role_public", instruction, &[]);
+
+    let instruction = roles_instruction(
+        CounterInstruction::RemoveRoleHolder {
+            role: Role::Incrementer,
+            holder: holder.pubkey(),
+        },
+        true,
+    );
+    bench.measure("remove_role_holder", instruction, &[]);
+
+    // Metadata
+    let (metadata_address, _) = find_metadata_address(&program_id, &counter);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::UpdateMetadata {
+            metadata: CounterMetadata {
+                name: "n".repeat(MAX_NAME_LEN),
+                description: "d".repeat(MAX_DESCRIPTION_LEN),
+            },
+        },
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(metadata_address, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("update_metadata", instruction, &[]);
+
+    // Freeze, reset and close
+    let instruction = bench.authority_instruction(CounterInstruction::FreezeCounter, counter);
+    bench.measure("freeze_counter", instruction, &[]);
+    let instruction = bench.authority_instruction(CounterInstruction::ThawCounter, counter);
+    bench.measure("thaw_counter", instruction, &[]);
+    let instruction = bench.authority_instruction(CounterInstruction::ResetCounter, counter);
+    bench.measure("reset_counter", instruction, &[]);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new(payer, false),
+            bench.registry(&payer),
+            AccountMeta::new(find_roles_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counter).0, false),
+            bench.config(),
+        ],
+    );
+    bench.measure("close_counter", instruction, &[]);
+
+    // Multisig authority with the maximum number of signers
+    let multisig = Keypair::new();
+    let signers: Vec<Keypair> = (0..MAX_MULTISIG_SIGNERS).map(|_| Keypair::new()).collect();
+    let mut accounts = vec![
+        AccountMeta::new(multisig.pubkey(), true),
+        AccountMeta::new(payer, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    accounts.extend(
+        signers
+            .iter()
+            .map(|signer| AccountMeta::new_readonly(signer.pubkey(), false)),
+    );
+    accounts.push(bench.config());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeMultisig {
+            threshold: MAX_MULTISIG_APPROVALS as u8,
+        },
+        accounts,
+    );
+    bench.measure("initialize_multisig_max_signers", instruction, &[&multisig]);
+
+    let multisig_counter = batch_counters[0];
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: multisig.pubkey(),
+        },
+        vec![
+            AccountMeta::new(multisig_counter, false),
+            AccountMeta::new(payer, true),
+            bench.registry(&payer),
+            bench.registry(&multisig.pubkey()),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            bench.config(),
+        ],
+    );
+    bench.measure("set_authority", instruction, &[]);
+
+    let mut accounts = vec![
+        AccountMeta::new(multisig_counter, false),
+        AccountMeta::new_readonly(multisig.pubkey(), false),
+    ];
+    accounts.extend(
+        signers[..MAX_MULTISIG_APPROVALS]
+            .iter()
+            .map(|signer| AccountMeta::new_readonly(signer.pubkey(), true)),
+    );
+    accounts.push(bench.config());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: None },
+        accounts,
+    );
+    let signer_refs: Vec<&Keypair> = signers[..MAX_MULTISIG_APPROVALS].iter().collect();
+    bench.measure("increment_by_max_multisig", instruction, &signer_refs);
+
+    measure_borsh_layout(&mut bench.results);
+    for case in ["increment", "increment_full_history"] {
+        let borsh_case = format!("borsh_{}", case);
+        assert!(
+            bench.results[case] < bench.results[borsh_case.as_str()],
+            "{} costs {} compute units with the zero-copy layout and {} with Borsh",
+            case,
+            bench.results[case],
+            bench.results[borsh_case.as_str()]
+        );
+    }
+
+    check_baseline(&bench.results);
+}
+
+/// Instructions of the Borsh build, which predates the discriminator encoding
+#[derive(BorshSerialize)]
+enum BorshCounterInstruction {
+    InitializeCounter {
+        initial_value: u64,
+        metadata: Option<CounterMetadata>,
+        history_capacity: u16,
+    },
+    IncrementCounter {
+        step: Option<u64>,
+    },
+}
+
+/// Measure the zero-copy increment cases against the Borsh build
+fn measure_borsh_layout(results: &mut BTreeMap<&'static str, u64>) {
+    let mut bench = Bench::with_program(BORSH_PROGRAM_PATH);
+    let program_id = bench.program_id;
+    let instruction = |instruction: BorshCounterInstruction, accounts: Vec<AccountMeta>| {
+        Instruction::new_with_borsh(program_id, &instruction, accounts)
+    };
+
+    let counter = |bench: &mut Bench, history_capacity: u16| {
+        let counter = Keypair::new();
+        let initialize = instruction(
+            BorshCounterInstruction::InitializeCounter {
+                initial_value: 0,
+                metadata: None,
+                history_capacity,
+            },
+            vec![
+                AccountMeta::new(counter.pubkey(), true),
+                AccountMeta::new(bench.payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                bench.registry(&bench.payer.pubkey()),
+                bench.config(),
+            ],
+        );
+        bench.send(initialize, &[&counter]);
+        counter.pubkey()
+    };
+    let increment = |bench: &Bench, counter: Pubkey| {
+        instruction(
+            BorshCounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(bench.payer.pubkey(), true),
+                bench.config(),
+            ],
+        )
+    };
+
+    let plain_counter = counter(&mut bench, 0);
+    let history_counter = counter(&mut bench, MAX_HISTORY_CAPACITY);
+    let instruction = increment(&bench, plain_counter);
+    bench.measure("borsh_increment", instruction, &[]);
+    for _ in 0..MAX_HISTORY_CAPACITY {
+        let instruction = increment(&bench, history_counter);
+        bench.send(instruction, &[]);
+    }
+    let instruction = increment(&bench, history_counter);
+    bench.measure("borsh_increment_full_history", instruction, &[]);
+
+    results.append(&mut bench.results);
+}
+
+/// Compare measured compute units with the checked-in baseline, or rewrite it
+/// when `UPDATE_CU_BASELINE` is set
+fn check_baseline(results: &BTreeMap<&'static str, u64>) {
+    for (name, units) in results {
+        println!("{:<36} {:>8}", name, units);
+    }
+
+    if std::env::var_os("UPDATE_CU_BASELINE").is_some() {
+        let mut contents = String::from(
+            "# Compute units per benchmark case, regenerate with\n\
+             # UPDATE_CU_BASELINE=1 cargo test --features test-sbf --test compute_units\n",
+        );
+        for (name, units) in results {
+            contents.push_str(&format!("{} {}\n", name, units));
+        }
+        fs::write(BASELINE_PATH, contents).expect("Failed to write baseline");
+        return;
+    }
+
+    let threshold_percent = std::env::var("CU_THRESHOLD_PERCENT")
+        .ok()
+        .map(|value| value.parse().expect("CU_THRESHOLD_PERCENT must be an integer"))
+        .unwrap_or(DEFAULT_THRESHOLD_PERCENT);
+
+    let contents = fs::read_to_string(BASELINE_PATH).expect("Failed to read baseline");
+    let baseline: BTreeMap<&str, u64> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, units) = line
+                .split_once(' ')
+                .unwrap_or_else(|| panic!("Malformed baseline line: {}", line));
+            let units = units
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("Malformed baseline line: {}", line));
+            (name, units)
+        })
+        .collect();
+
+    let mut regressions = Vec::new();
+    for (name, units) in results {
+        let Some(&expected) = baseline.get(name) else {
+            regressions.push(format!(
+                "{}: {} compute units, no baseline (record one with UPDATE_CU_BASELINE=1)",
+                name, units
+            ));
+            continue;
+        };
+        let limit = expected + expected * threshold_percent / 100;
+        if *units > limit {
+            regressions.push(format!(
+                "{}: {} compute units, baseline {} (limit {})",
+                name, units, expected, limit
+            ));
+        }
+    }
+
+    assert!(
+        regressions.is_empty(),
+        "Compute units regressed by more than {}% or have no baseline:\n{}",
+        threshold_percent,
+        regressions.join("\n")
+    );
+}
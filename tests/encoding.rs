@@ -0,0 +1,372 @@
+//! Pins the wire format of every instruction byte for byte.
+//!
+//! Each instruction is encoded as its 8-byte discriminator followed by the Borsh
+//! encoding of its fields. Clients of the first release send a single-byte tag below
+//! `LEGACY_TAG_COUNT` followed by the payload of that release.
+//! Increments, decrements and batches also have a compact opcode and varint encoding.
+
+use borsh::to_vec;
+use solana_counter_program::*;
+use solana_program::{hash::hash, program_error::ProgramError, pubkey::Pubkey};
+
+fn key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Every instruction with its snake_case name, legacy tag and pinned encoding
+fn samples() -> Vec<(&'static str, u8, CounterInstruction, &'static str)> {
+    vec![
+        (
+            "initialize_counter",
+            0,
+            CounterInstruction::InitializeCounter {
+                initial_value: 5,
+                metadata: Some(CounterMetadata {
+                    name: "a".to_string(),
+                    description: String::new(),
+                }),
+                history_capacity: 2,
+            },
+            "43596457e7ac237c0500000000000000010100000061000000000200",
+        ),
+        ("increment_counter", 1, CounterInstruction::IncrementCounter { step: Some(3) }, "107d02ab4918cfe5010300000000000000"),
+        ("decrement_counter", 2, CounterInstruction::DecrementCounter { step: None }, "a925f2e6a97ef52600"),
+        (
+            "batch",
+            3,
+            CounterInstruction::Batch {
+                ops: vec![(1, CounterOp::Set { value: 9 })],
+            },
+            "c6d3f828a52515940100000001020900000000000000",
+        ),
+        ("transfer_between_counters", 4, CounterInstruction::TransferBetweenCounters { amount: 7 }, "2647aba8199772ca0700000000000000"),
+        (
+            "approve_delegate",
+            5,
+            CounterInstruction::ApproveDelegate {
+                increment_allowance: 1,
+                decrement_allowance: 2,
+            },
+            "4406f840c3deb6df01000000000000000200000000000000",
+        ),
+        ("revoke_delegate", 6, CounterInstruction::RevokeDelegate, "8e42627e663c5ca3"),
+        ("initialize_roles", 7, CounterInstruction::InitializeRoles, "a42b5bdbdcf73260"),
+        (
+            "add_role_holder",
+            8,
+            CounterInstruction::AddRoleHolder {
+                role: Role::Decrementer,
+                holder: key(1),
+            },
+            "9ccdb3c288fa695f010101010101010101010101010101010101010101010101010101010101010101",
+        ),
+        (
+            "remove_role_holder",
+            9,
+            CounterInstruction::RemoveRoleHolder {
+                role: Role::Admin,
+                holder: key(2),
+            },
+            "194e7d85931406b0020202020202020202020202020202020202020202020202020202020202020202",
+        ),
+        (
+            "set_role_public",
+            10,
+            CounterInstruction::SetRolePublic {
+                role: Role::Incrementer,
+                public: true,
+            },
+            "0d1d9fdf77134b9c0001",
+        ),
+        ("reset_counter", 11, CounterInstruction::ResetCounter, "f59a3948fb3f834f"),
+        ("close_counter", 12, CounterInstruction::CloseCounter, "04ec34f86b92bb31"),
+        ("initialize_multisig", 13, CounterInstruction::InitializeMultisig { threshold: 2 }, "dc8275151be34ed502"),
+        ("set_authority", 14, CounterInstruction::SetAuthority { new_authority: key(3) }, "85fa25156ea31a790303030303030303030303030303030303030303030303030303030303030303"),
+        ("freeze_counter", 15, CounterInstruction::FreezeCounter, "f6828192465d1af8"),
+        ("thaw_counter", 16, CounterInstruction::ThawCounter, "d4bb5260e4ed1812"),
+        ("initialize_config", 17, CounterInstruction::InitializeConfig, "d07f1501c2bec446"),
+        (
+            "update_config",
+            18,
+            CounterInstruction::UpdateConfig {
+                admin: key(4),
+                default_step: 2,
+                default_overflow_mode: OverflowMode::Wrapping,
+            },
+            "1d9efcbf0a53db630404040404040404040404040404040404040404040404040404040404040404020000000000000002",
+        ),
+        ("set_paused", 19, CounterInstruction::SetPaused { paused: true }, "5b3c7dc0b0e1a6da01"),
+        (
+            "update_metadata",
+            20,
+            CounterInstruction::UpdateMetadata {
+                metadata: CounterMetadata {
+                    name: String::new(),
+                    description: "b".to_string(),
+                },
+            },
+            "aab62bef614ee1ba000000000100000062",
+        ),
+        (
+            "set_increment_fee",
+            21,
+            CounterInstruction::SetIncrementFee {
+                fee_lamports: 100,
+                treasury: key(5),
+            },
+            "af7ecba8d39c05f264000000000000000505050505050505050505050505050505050505050505050505050505050505",
+        ),
+        (
+            "set_token_gate",
+            22,
+            CounterInstruction::SetTokenGate {
+                mint: key(6),
+                min_balance: 1,
+            },
+            "b5f67885ff69967106060606060606060606060606060606060606060606060606060606060606060100000000000000",
+        ),
+        (
+            "set_token_payment",
+            23,
+            CounterInstruction::SetTokenPayment {
+                mint: key(7),
+                amount_per_step: 3,
+                mode: TokenPaymentMode::Burn,
+                destination: key(8),
+            },
+            "c40eb04a0be2994f07070707070707070707070707070707070707070707070707070707070707070300000000000000010808080808080808080808080808080808080808080808080808080808080808",
+        ),
+        ("set_units_mint", 24, CounterInstruction::SetUnitsMint { mint: key(9) }, "a4ae46bf28aeb44f0909090909090909090909090909090909090909090909090909090909090909"),
+        (
+            "set_trigger",
+            25,
+            CounterInstruction::SetTrigger {
+                index: 1,
+                threshold: 10,
+                program: key(10),
+                data: vec![0xab],
+            },
+            "88a37b23f1818f5f010a000000000000000a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a01000000ab",
+        ),
+        (
+            "set_hook",
+            26,
+            CounterInstruction::SetHook {
+                program: key(11),
+                pre: true,
+                post: false,
+            },
+            "af10bbfc13366fdd0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0100",
+        ),
+        ("set_expiry", 27, CounterInstruction::SetExpiry { expires_at: -1 }, "032675214998d5b3ffffffffffffffff"),
+        ("close_expired_counter", 28, CounterInstruction::CloseExpiredCounter, "8752b35ae4878c32"),
+        ("set_timelock", 29, CounterInstruction::SetTimelock { delay: 60 }, "839fde15e1e236d63c00000000000000"),
+        (
+            "schedule_change",
+            30,
+            CounterInstruction::ScheduleChange {
+                action: ScheduledAction::SetCounter { value: 4 },
+            },
+            "3a493d49063388c5000400000000000000",
+        ),
+        ("execute_change", 31, CounterInstruction::ExecuteChange, "683588ee52dec82a"),
+        ("cancel_change", 32, CounterInstruction::CancelChange, "641e049403f4f3a8"),
+        ("migrate_counter", 33, CounterInstruction::MigrateCounter, "7dbba23fe03afad7"),
+    ]
+}
+
+#[test]
+fn discriminators_are_anchor_style_hashes() {
+    assert_eq!(samples().len(), INSTRUCTION_DISCRIMINATORS.len());
+    for (name, tag, _, _) in samples() {
+        let preimage = format!("global:{}", name);
+        assert_eq!(
+            INSTRUCTION_DISCRIMINATORS[tag as usize],
+            hash(preimage.as_bytes()).to_bytes()[..8],
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn encodings_are_pinned() {
+    for (name, tag, instruction, expected) in samples() {
+        let packed = instruction.pack();
+        assert_eq!(hex(&packed), expected, "{}", name);
+
+        // The Borsh encoding is the same fields behind the legacy tag
+        let borsh = to_vec(&instruction).expect("Failed to serialize instruction");
+        assert_eq!(borsh[0], tag, "{}", name);
+        assert_eq!(borsh[1..], packed[8..], "{}", name);
+    }
+}
+
+#[test]
+fn discriminator_encodings_decode() {
+    for (name, _, instruction, _) in samples() {
+        let borsh = to_vec(&instruction).expect("Failed to serialize instruction");
+        let decoded = CounterInstruction::unpack(&instruction.pack()).expect("Failed to decode instruction");
+        assert_eq!(to_vec(&decoded).unwrap(), borsh, "{}", name);
+
+        // The single-byte tag format is reserved for the first release's payloads
+        assert_eq!(
+            CounterInstruction::unpack(&borsh).unwrap_err(),
+            ProgramError::InvalidInstructionData,
+            "{}",
+            name
+        );
+    }
+
+    // Trailing bytes are rejected
+    let mut data = CounterInstruction::ResetCounter.pack();
+    data.push(0);
+    assert_eq!(
+        CounterInstruction::unpack(&data).unwrap_err(),
+        ProgramError::InvalidInstructionData
+    );
+}
+
+#[test]
+fn first_release_encodings_decode() {
+    // Instruction data as built by clients of the first release, whose `CounterInstruction`
+    // was `InitializeCounter { initial_value: u64 }`, `IncrementCounter { step: Option<u64> }`
+    // and `DecrementCounter { step: Option<u64> }` in Borsh
+    let samples = [
+        (
+            "00e803000000000000",
+            LegacyInstruction::InitializeCounter { initial_value: 1_000 },
+        ),
+        ("0100", LegacyInstruction::IncrementCounter { step: None }),
+        (
+            "01010500000000000000",
+            LegacyInstruction::IncrementCounter { step: Some(5) },
+        ),
+        ("0200", LegacyInstruction::DecrementCounter { step: None }),
+        (
+            "0201ffffffffffffffff",
+            LegacyInstruction::DecrementCounter { step: Some(u64::MAX) },
+        ),
+    ];
+    for (encoded, instruction) in samples {
+        let data: Vec<u8> = (0..encoded.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&encoded[index..index + 2], 16).unwrap())
+            .collect();
+        assert_eq!(LegacyInstruction::unpack(&data), Ok(Some(instruction.clone())), "{}", encoded);
+        assert_eq!(hex(&to_vec(&instruction).unwrap()), encoded);
+    }
+
+    // Truncated and padded payloads are rejected, other formats are left to `CounterInstruction`
+    for data in [&[0, 1, 0][..], &[1, 0, 0], &[2, 1, 5]] {
+        assert_eq!(
+            LegacyInstruction::unpack(data),
+            Err(ProgramError::InvalidInstructionData),
+            "{:?}",
+            data
+        );
+    }
+    assert_eq!(LegacyInstruction::unpack(&[]), Ok(None));
+    assert_eq!(LegacyInstruction::unpack(&[COMPACT_INCREMENT_OPCODE]), Ok(None));
+    assert_eq!(LegacyInstruction::unpack(&CounterInstruction::ResetCounter.pack()), Ok(None));
+}
+
+#[test]
+fn legacy_tags_do_not_start_a_discriminator() {
+    for discriminator in INSTRUCTION_DISCRIMINATORS {
+        assert!(discriminator[0] >= LEGACY_TAG_COUNT, "{:?} starts with a legacy tag", discriminator);
+    }
+
+    // CloseCounter's discriminator starts with 4, the legacy tag of TransferBetweenCounters,
+    // and must not fall back to it when followed by a stray byte
+    let mut data = CounterInstruction::CloseCounter.pack();
+    data.push(0);
+    assert_eq!(
+        CounterInstruction::unpack(&data).unwrap_err(),
+        ProgramError::InvalidInstructionData
+    );
+}
+
+#[test]
+fn compact_encodings_are_pinned() {
+    let samples = [
+        (CounterInstruction::IncrementCounter { step: None }, "c0"),
+        (CounterInstruction::IncrementCounter { step: Some(1) }, "c001"),
+        (CounterInstruction::IncrementCounter { step: Some(300) }, "c0ac02"),
+        (CounterInstruction::DecrementCounter { step: None }, "c1"),
+        (
+            CounterInstruction::DecrementCounter { step: Some(u64::MAX) },
+            "c1ffffffffffffffffff01",
+        ),
+        (
+            CounterInstruction::Batch {
+                ops: vec![
+                    (0, CounterOp::Increment { step: None }),
+                    (1, CounterOp::Increment { step: Some(2) }),
+                    (0, CounterOp::Decrement { step: None }),
+                    (1, CounterOp::Decrement { step: Some(128) }),
+                    (2, CounterOp::Set { value: 0 }),
+                ],
+            },
+            "c20000010102000201038001020400",
+        ),
+    ];
+    for (instruction, expected) in samples {
+        let encoded = instruction.encode();
+        assert_eq!(hex(&encoded), expected, "{:?}", instruction);
+        assert!(encoded.len() < instruction.pack().len(), "{:?}", instruction);
+
+        let decoded = CounterInstruction::unpack(&encoded).expect("Failed to decode instruction");
+        assert_eq!(to_vec(&decoded).unwrap(), to_vec(&instruction).unwrap());
+    }
+
+    // Instructions without a compact form fall back to the discriminator format
+    let instruction = CounterInstruction::ResetCounter;
+    assert!(instruction.pack_compact().is_none());
+    assert_eq!(instruction.encode(), instruction.pack());
+}
+
+#[test]
+fn compact_opcodes_are_unambiguous() {
+    for opcode in [COMPACT_INCREMENT_OPCODE, COMPACT_DECREMENT_OPCODE, COMPACT_BATCH_OPCODE] {
+        assert!(opcode as usize >= INSTRUCTION_DISCRIMINATORS.len(), "{:#x} is a legacy tag", opcode);
+        assert!(
+            INSTRUCTION_DISCRIMINATORS
+                .iter()
+                .all(|discriminator| discriminator[0] != opcode),
+            "{:#x} starts a discriminator",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn malformed_compact_data_is_rejected() {
+    for data in [
+        // Overlong varint for 1
+        &[0xc0, 0x81, 0x00][..],
+        // Varint past 64 bits
+        &[0xc0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02],
+        // Truncated varint
+        &[0xc1, 0x80],
+        // Trailing byte after the step
+        &[0xc0, 0x01, 0x01],
+        // Unknown batch op kind
+        &[0xc2, 0x00, 0x05],
+        // Batch op missing its kind
+        &[0xc2, 0x00],
+        // Batch set missing its value
+        &[0xc2, 0x00, 0x04],
+    ] {
+        assert_eq!(
+            CounterInstruction::unpack(data).unwrap_err(),
+            ProgramError::InvalidInstructionData,
+            "{}",
+            hex(data)
+        );
+    }
+}
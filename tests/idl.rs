@@ -0,0 +1,134 @@
+//! Keeps `idl/solana_counter_program.json` in sync with the Rust types.
+//!
+//! The IDL is extracted from the shank annotations in `src/lib.rs`. Shank does not
+//! extract events, so the event structs it lists as types are moved to `events`.
+//! Regenerate it after changing instructions, accounts, errors or events with:
+//!
+//!     UPDATE_IDL=1 cargo test --test idl
+
+use std::fs;
+
+use borsh::to_vec;
+use shank_idl::{
+    extract_idl,
+    idl::{Idl, IdlEvent, IdlEventField},
+    idl_type_definition::IdlTypeDefinitionTy,
+    ParseIdlOpts,
+};
+use solana_counter_program::{
+    AuthorityChanged, CounterClosed, CounterError, CounterInitialized, CounterInstruction, CounterUpdated, Event,
+};
+use solana_program::{hash::hash, pubkey::Pubkey};
+
+const IDL_PATH: &str = "idl/solana_counter_program.json";
+
+/// Types implementing `Event`, with their discriminators
+const EVENTS: [(&str, [u8; 8]); 4] = [
+    ("CounterInitialized", CounterInitialized::DISCRIMINATOR),
+    ("CounterUpdated", CounterUpdated::DISCRIMINATOR),
+    ("AuthorityChanged", AuthorityChanged::DISCRIMINATOR),
+    ("CounterClosed", CounterClosed::DISCRIMINATOR),
+];
+
+fn generate_idl() -> Idl {
+    let opts = ParseIdlOpts {
+        // The program id is chosen at deploy time
+        require_program_address: false,
+        ..ParseIdlOpts::default()
+    };
+    let mut idl = extract_idl("src/lib.rs", opts)
+        .expect("Failed to parse program source")
+        .expect("No IDL found in program source");
+
+    let mut events = Vec::new();
+    for (name, _) in EVENTS {
+        let position = idl
+            .types
+            .iter()
+            .position(|ty| ty.name == name)
+            .unwrap_or_else(|| panic!("{} missing from IDL types", name));
+        let IdlTypeDefinitionTy::Struct { fields } = idl.types.remove(position).ty else {
+            panic!("{} is not a struct", name);
+        };
+        events.push(IdlEvent {
+            name: name.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|field| IdlEventField {
+                    name: field.name,
+                    ty: field.ty,
+                    index: false,
+                })
+                .collect(),
+        });
+    }
+    idl.events = Some(events);
+    idl
+}
+
+#[test]
+fn idl_is_up_to_date() {
+    let json = generate_idl().try_into_json().expect("Failed to render IDL") + "\n";
+
+    if std::env::var_os("UPDATE_IDL").is_some() {
+        fs::create_dir_all("idl").expect("Failed to create idl directory");
+        fs::write(IDL_PATH, json).expect("Failed to write IDL");
+        return;
+    }
+
+    let checked_in = fs::read_to_string(IDL_PATH).expect("Failed to read IDL");
+    assert!(
+        checked_in == json,
+        "{} is out of date, regenerate it with UPDATE_IDL=1 cargo test --test idl",
+        IDL_PATH
+    );
+}
+
+#[test]
+fn idl_matches_borsh_layout() {
+    let idl = generate_idl();
+
+    // Discriminants follow the Borsh variant tags
+    let samples = [
+        ("IncrementCounter", CounterInstruction::IncrementCounter { step: None }),
+        ("RevokeDelegate", CounterInstruction::RevokeDelegate),
+        ("ResetCounter", CounterInstruction::ResetCounter),
+        ("SetAuthority", CounterInstruction::SetAuthority { new_authority: Pubkey::default() }),
+        ("SetPaused", CounterInstruction::SetPaused { paused: false }),
+    ];
+    for (name, instruction) in samples {
+        let idl_instruction = idl
+            .instructions
+            .iter()
+            .find(|idl_instruction| idl_instruction.name == name)
+            .unwrap_or_else(|| panic!("{} missing from IDL", name));
+        let data = to_vec(&instruction).expect("Failed to serialize instruction");
+        assert_eq!(idl_instruction.discriminant.value, data[0], "{}", name);
+    }
+
+    // Error codes match the custom program error codes
+    let errors = idl.errors.expect("IDL lists no errors");
+    for (name, error) in [
+        ("InsufficientAllowance", CounterError::InsufficientAllowance),
+        ("MetadataTooLong", CounterError::MetadataTooLong),
+    ] {
+        let idl_error = errors
+            .iter()
+            .find(|idl_error| idl_error.name == name)
+            .unwrap_or_else(|| panic!("{} missing from IDL", name));
+        assert_eq!(idl_error.code, error as u32, "{}", name);
+    }
+
+    // Event discriminators are Anchor style hashes of the names in the IDL
+    let events = idl.events.expect("IDL lists no events");
+    assert_eq!(events.len(), EVENTS.len());
+    for (name, discriminator) in EVENTS {
+        assert!(events.iter().any(|event| event.name == name), "{} missing from IDL", name);
+        assert_eq!(
+            discriminator,
+            hash(format!("event:{}", name).as_bytes()).to_bytes()[..8],
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,1763 @@
+//! Functional tests that execute the compiled program in LiteSVM.
+//!
+//! tests/native.rs covers the same logic without a compiled program. These tests run
+//! target/deploy/solana_counter_program.so, so build it first:
+//!
+//!     cargo build-sbf
+//!     cargo test --features test-sbf --test litesvm
+
+#![cfg(feature = "test-sbf")]
+
+use borsh::BorshDeserialize;
+use litesvm::LiteSVM;
+use solana_counter_program::*;
+use solana_sdk::{
+    account::{Account, ReadableAccount},
+    bpf_loader_upgradeable,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+
+#[test]
+fn test_counter_program() {
+    let mut svm = LiteSVM::new();
+
+    let payer = Keypair::new();
+
+    svm.airdrop(&payer.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+    
+    let program_keypair = Keypair::new();
+    let program_id = program_keypair.pubkey();
+
+    svm.add_program_from_file(
+        program_id,
+        "target/deploy/solana_counter_program.so"
+    ).expect("Failed to load program");
+
+    // Create new counter account with initial value
+    let counter_keypair = Keypair::new();
+    let initial_value: u64 = 42;
+
+    println!("Testing counter initialization...");
+
+    let init_instruction_data = CounterInstruction::InitializeCounter {
+        initial_value,
+        metadata: None,
+        history_capacity: 0,
+    }.pack();
+
+    let initialize_instruction = Instruction::new_with_bytes(
+        program_id,
+        &init_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer, &counter_keypair],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize transaction should succeed");
+
+    let logs = result.unwrap().logs;
+    println!("Transaction logs:\n{:#?}", logs);  
+
+    // Verify counter was initialized correctly
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+
+    let counter = CounterAccount::load(account.data())
+        .expect("Failed to read counter data");
+
+    assert_eq!(counter.count(), 42);
+    println!("Counter initialized successfully with value: {}", counter.count());   
+        
+    // Test default increment (step = 1)
+    println!("Testing default increment (step = 1)...");
+
+    let increment_instruction_data =
+        CounterInstruction::IncrementCounter { step: None }.pack();
+
+    let increment_instruction = Instruction::new_with_bytes(
+        program_id,
+        &increment_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[increment_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment transaction should succeed");
+
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+
+    let counter = CounterAccount::load(account.data())
+        .expect("Failed to read counter data");
+    assert_eq!(counter.count(), 43);
+    println!("Counter incremented by 1 to: {}", counter.count());
+
+    // Test custom increment (step = 5)
+    println!("Testing custom increment (step = 5)...");
+
+    let increment_by_5_instruction_data =
+        CounterInstruction::IncrementCounter { step: Some(5) }.pack();
+
+    let increment_by_5_instruction = Instruction::new_with_bytes(
+        program_id,
+        &increment_by_5_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[increment_by_5_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment by 5 transaction should succeed");
+
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+
+    let counter = CounterAccount::load(account.data())
+        .expect("Failed to read counter data");
+    assert_eq!(counter.count(), 48);
+    println!("Counter incremented by 5 to: {}", counter.count());
+
+    // Test default decrement (step = 1)
+    println!("Testing default decrement (step = 1)...");
+
+    let decrement_instruction_data =
+        CounterInstruction::DecrementCounter { step: None }.pack();
+
+    let decrement_instruction = Instruction::new_with_bytes(
+        program_id,
+        &decrement_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[decrement_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Decrement transaction should succeed");
+
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+
+    let counter = CounterAccount::load(account.data())
+        .expect("Failed to read counter data");
+    assert_eq!(counter.count(), 47);
+    println!("Counter decremented by 1 to: {}", counter.count());
+
+    // Test custom decrement (step = 3)
+    println!("Testing custom decrement (step = 3)...");
+
+    let decrement_by_3_instruction_data =
+        CounterInstruction::DecrementCounter { step: Some(3) }.pack();
+
+    let decrement_by_3_instruction = Instruction::new_with_bytes(
+        program_id,
+        &decrement_by_3_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[decrement_by_3_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Decrement by 3 transaction should succeed");
+
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+
+    let counter = CounterAccount::load(account.data())
+        .expect("Failed to read counter data");
+    assert_eq!(counter.count(), 44);
+    println!("Counter decremented by 3 to: {}", counter.count());
+
+    // Test underflow protection
+    println!("Testing underflow protection...");
+
+    // Reset counter to 0
+    let reset_instruction_data =
+        CounterInstruction::DecrementCounter { step: Some(44) }.pack();
+
+    let reset_instruction = Instruction::new_with_bytes(
+        program_id,
+        &reset_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[reset_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Reset transaction should succeed");
+
+    // Attempt to decrement below 0 should fail
+    let decrement_below_zero_instruction_data =
+        CounterInstruction::DecrementCounter { step: Some(1) }.pack();
+
+    let decrement_below_zero_instruction = Instruction::new_with_bytes(
+        program_id,
+        &decrement_below_zero_instruction_data,
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[decrement_below_zero_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer],
+        message,
+        svm.latest_blockhash()
+    );
+
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Decrement below zero should fail");
+    println!("Underflow protection test passed!");
+}
+
+/// Load the compiled program into a fresh LiteSVM instance with a funded payer
+fn setup() -> (LiteSVM, Pubkey, Keypair) {
+    let mut svm = LiteSVM::new();
+
+    let payer = Keypair::new();
+    svm.airdrop(&payer.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+
+    let program_id = Keypair::new().pubkey();
+    svm.add_program_from_file(
+        program_id,
+        "target/deploy/solana_counter_program.so"
+    ).expect("Failed to load program");
+
+    (svm, program_id, payer)
+}
+
+/// Create a counter account holding `initial_value`
+fn initialize_counter(
+    svm: &mut LiteSVM,
+    program_id: Pubkey,
+    payer: &Keypair,
+    initial_value: u64,
+) -> Pubkey {
+    let counter_keypair = Keypair::new();
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeCounter {
+            initial_value,
+            metadata: None,
+            history_capacity: 0,
+        },
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[payer, &counter_keypair],
+        message,
+        svm.latest_blockhash()
+    );
+    svm.send_transaction(transaction).expect("Initialize transaction should succeed");
+
+    counter_keypair.pubkey()
+}
+
+/// Read the current value of a counter account
+fn read_count(svm: &LiteSVM, counter: &Pubkey) -> u64 {
+    let account = svm
+        .get_account(counter)
+        .expect("Failed to get counter account");
+
+    CounterAccount::load(account.data())
+        .expect("Failed to read counter data")
+        .count()
+}
+
+/// Create an SPL token mint controlled by `mint_authority`
+fn create_mint(svm: &mut LiteSVM, payer: &Keypair, mint_authority: &Pubkey) -> Pubkey {
+    let mint = Keypair::new();
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            svm.minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(&spl_token::id(), &mint.pubkey(), mint_authority, None, 0)
+            .expect("Failed to build initialize mint instruction"),
+    ];
+
+    let message = Message::new(&instructions, Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[payer, &mint], message, svm.latest_blockhash());
+    svm.send_transaction(transaction).expect("Create mint transaction should succeed");
+
+    mint.pubkey()
+}
+
+/// Create a token account of `mint` for `owner` holding `amount` tokens minted by `payer`
+fn create_token_account(
+    svm: &mut LiteSVM,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let token_account = Keypair::new();
+    let mut instructions = vec![
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &token_account.pubkey(),
+            svm.minimum_balance_for_rent_exemption(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account3(&spl_token::id(), &token_account.pubkey(), mint, owner)
+            .expect("Failed to build initialize account instruction"),
+    ];
+    if amount > 0 {
+        instructions.push(
+            spl_token::instruction::mint_to(&spl_token::id(), mint, &token_account.pubkey(), &payer.pubkey(), &[], amount)
+                .expect("Failed to build mint instruction"),
+        );
+    }
+
+    let message = Message::new(&instructions, Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[payer, &token_account], message, svm.latest_blockhash());
+    svm.send_transaction(transaction).expect("Create token account transaction should succeed");
+
+    token_account.pubkey()
+}
+
+#[test]
+fn test_batch() {
+    let (mut svm, program_id, payer) = setup();
+
+    let first = initialize_counter(&mut svm, program_id, &payer, 10);
+    let second = initialize_counter(&mut svm, program_id, &payer, 20);
+
+    // Apply several operations to both counters in one instruction
+    let batch_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::Batch {
+            ops: vec![
+                (0, CounterOp::Increment { step: None }),
+                (1, CounterOp::Decrement { step: Some(5) }),
+                (0, CounterOp::Increment { step: Some(4) }),
+                (1, CounterOp::Set { value: 100 }),
+            ],
+        },
+        vec![
+            AccountMeta::new(first, false),
+            AccountMeta::new(second, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[batch_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Batch transaction should succeed");
+
+    assert_eq!(read_count(&svm, &first), 15);
+    assert_eq!(read_count(&svm, &second), 100);
+
+    // A failing operation must leave every counter untouched
+    let failing_batch_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::Batch {
+            ops: vec![
+                (0, CounterOp::Increment { step: Some(1) }),
+                (1, CounterOp::Decrement { step: Some(101) }),
+            ],
+        },
+        vec![
+            AccountMeta::new(first, false),
+            AccountMeta::new(second, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[failing_batch_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Batch with underflow should fail");
+
+    assert_eq!(read_count(&svm, &first), 15);
+    assert_eq!(read_count(&svm, &second), 100);
+
+    // Operations may not reference accounts that were not passed
+    let out_of_range_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::Batch {
+            ops: vec![(2, CounterOp::Increment { step: None })],
+        },
+        vec![
+            AccountMeta::new(first, false),
+            AccountMeta::new(second, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[out_of_range_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Batch with unknown account index should fail");
+}
+
+#[test]
+fn test_transfer_between_counters() {
+    let (mut svm, program_id, payer) = setup();
+
+    let source = initialize_counter(&mut svm, program_id, &payer, 50);
+    let destination = initialize_counter(&mut svm, program_id, &payer, 5);
+
+    // Move 20 units from source to destination signed by the source authority
+    let transfer_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 20 },
+        vec![
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[transfer_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Transfer transaction should succeed");
+
+    assert_eq!(read_count(&svm, &source), 30);
+    assert_eq!(read_count(&svm, &destination), 25);
+
+    // Transferring more than the source holds must fail without touching either side
+    let overdraw_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 31 },
+        vec![
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[overdraw_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Transfer exceeding the source value should fail");
+
+    assert_eq!(read_count(&svm, &source), 30);
+    assert_eq!(read_count(&svm, &destination), 25);
+
+    // Someone other than the source authority cannot move value
+    let stranger = Keypair::new();
+    svm.airdrop(&stranger.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+
+    let stranger_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 1 },
+        vec![
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(stranger.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[stranger_instruction], Some(&stranger.pubkey()));
+    let transaction = Transaction::new(&[&stranger], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Transfer signed by another key should fail");
+
+    assert_eq!(read_count(&svm, &source), 30);
+}
+
+#[test]
+fn test_delegate_allowances() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+
+    let delegate = Keypair::new();
+    svm.airdrop(&delegate.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+    let (delegate_record, _) = find_delegate_address(&program_id, &counter, &delegate.pubkey());
+
+    // Increments signed by the delegate fail before approval
+    let delegate_increment = |step: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: Some(step) },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(delegate.pubkey(), true),
+                AccountMeta::new(delegate_record, false),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        )
+    };
+
+    let message = Message::new(&[delegate_increment(1)], Some(&delegate.pubkey()));
+    let transaction = Transaction::new(&[&delegate], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment by an unapproved delegate should fail");
+
+    // Grant an increment allowance of 5 and no decrement allowance
+    let approve_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ApproveDelegate {
+            increment_allowance: 5,
+            decrement_allowance: 0,
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[approve_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Approve transaction should succeed");
+
+    // The delegate spends 3 of its 5 units
+    let message = Message::new(&[delegate_increment(3)], Some(&delegate.pubkey()));
+    let transaction = Transaction::new(&[&delegate], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment within allowance should succeed");
+    assert_eq!(read_count(&svm, &counter), 13);
+
+    let record = svm
+        .get_account(&delegate_record)
+        .expect("Failed to get delegate record");
+    let record = DelegateAccount::try_from_slice(record.data())
+        .expect("Failed to deserialize delegate record");
+    assert_eq!(record.increment_allowance, 2);
+
+    // Exceeding the remaining allowance fails
+    let message = Message::new(&[delegate_increment(3)], Some(&delegate.pubkey()));
+    let transaction = Transaction::new(&[&delegate], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment beyond allowance should fail");
+
+    // No decrement allowance was granted
+    let delegate_decrement = counter_instruction(
+        &program_id,
+        &CounterInstruction::DecrementCounter { step: None },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(delegate.pubkey(), true),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[delegate_decrement], Some(&delegate.pubkey()));
+    let transaction = Transaction::new(&[&delegate], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Decrement without allowance should fail");
+
+    // Revoking closes the record and blocks the delegate again
+    let revoke_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::RevokeDelegate,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[revoke_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Revoke transaction should succeed");
+
+    let message = Message::new(&[delegate_increment(1)], Some(&delegate.pubkey()));
+    let transaction = Transaction::new(&[&delegate], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment after revoke should fail");
+    assert_eq!(read_count(&svm, &counter), 13);
+}
+
+#[test]
+fn test_roles() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let (roles, _) = find_roles_address(&program_id, &counter);
+
+    let visitor = Keypair::new();
+    let moderator = Keypair::new();
+    for keypair in [&visitor, &moderator] {
+        svm.airdrop(&keypair.pubkey(), 1_000_000_000)
+            .expect("Failed to airdrop");
+    }
+
+    let send = |svm: &mut LiteSVM, instruction: Instruction, signer: &Keypair| {
+        let message = Message::new(&[instruction], Some(&signer.pubkey()));
+        let transaction = Transaction::new(&[signer], message, svm.latest_blockhash());
+        svm.send_transaction(transaction).is_ok()
+    };
+    let mutation = |instruction: CounterInstruction, signer: &Keypair| {
+        counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(roles, false),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        )
+    };
+
+    // Create the roles account, open incrementing to everyone and add a moderator
+    let initialize_roles_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeRoles,
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(roles, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+    let succeeded = send(&mut svm, initialize_roles_instruction, &payer);
+    assert!(succeeded, "Initialize roles transaction should succeed");
+
+    let public_increment_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetRolePublic {
+            role: Role::Incrementer,
+            public: true,
+        },
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(roles, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+    let succeeded = send(&mut svm, public_increment_instruction, &payer);
+    assert!(succeeded, "Set role public transaction should succeed");
+
+    let add_moderator_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::AddRoleHolder {
+            role: Role::Decrementer,
+            holder: moderator.pubkey(),
+        },
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(roles, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+    let succeeded = send(&mut svm, add_moderator_instruction, &payer);
+    assert!(succeeded, "Add role holder transaction should succeed");
+
+    // Anyone may increment, only the moderator may decrement
+    let instruction = mutation(CounterInstruction::IncrementCounter { step: None }, &visitor);
+    let succeeded = send(&mut svm, instruction, &visitor);
+    assert!(succeeded, "Public increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+
+    let instruction = mutation(CounterInstruction::DecrementCounter { step: None }, &visitor);
+    let succeeded = send(&mut svm, instruction, &visitor);
+    assert!(!succeeded, "Decrement without the role should fail");
+
+    let instruction = mutation(CounterInstruction::DecrementCounter { step: None }, &moderator);
+    let succeeded = send(&mut svm, instruction, &moderator);
+    assert!(succeeded, "Moderator decrement should succeed");
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    // Reset is reserved for admins
+    let instruction = mutation(CounterInstruction::ResetCounter, &moderator);
+    let succeeded = send(&mut svm, instruction, &moderator);
+    assert!(!succeeded, "Reset by a non-admin should fail");
+
+    let reset_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ResetCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+    let succeeded = send(&mut svm, reset_instruction, &payer);
+    assert!(succeeded, "Reset by the authority should succeed");
+    assert_eq!(read_count(&svm, &counter), 0);
+
+    // Closing returns the rent and removes the counter
+    let close_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(roles, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counter).0, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+    let succeeded = send(&mut svm, close_instruction, &payer);
+    assert!(succeeded, "Close by the authority should succeed");
+
+    let closed = svm.get_account(&counter);
+    assert!(
+        closed.is_none() || closed.unwrap().lamports == 0,
+        "Counter account should be closed"
+    );
+}
+
+#[test]
+fn test_multisig_authority() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+
+    // Create a 2-of-3 multisig
+    let multisig = Keypair::new();
+    let signers = [Keypair::new(), Keypair::new(), Keypair::new()];
+
+    let mut multisig_accounts = vec![
+        AccountMeta::new(multisig.pubkey(), true),
+        AccountMeta::new(payer.pubkey(), true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    multisig_accounts.extend(
+        signers
+            .iter()
+            .map(|signer| AccountMeta::new_readonly(signer.pubkey(), false)),
+    );
+    multisig_accounts.push(AccountMeta::new_readonly(find_config_address(&program_id).0, false));
+
+    let multisig_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeMultisig { threshold: 2 },
+        multisig_accounts,
+    );
+
+    let message = Message::new(&[multisig_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer, &multisig], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize multisig transaction should succeed");
+
+    // Hand the counter over to the multisig
+    let set_authority_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: multisig.pubkey(),
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_registry_address(&program_id, &multisig.pubkey()).0, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[set_authority_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set authority transaction should succeed");
+
+    // A reset approved by one listed signer is not enough
+    let reset_instruction = |approvers: &[&Keypair]| {
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(multisig.pubkey(), false),
+        ];
+        accounts.extend(
+            approvers
+                .iter()
+                .map(|approver| AccountMeta::new_readonly(approver.pubkey(), true)),
+        );
+        accounts.push(AccountMeta::new_readonly(find_config_address(&program_id).0, false));
+        counter_instruction(&program_id, &CounterInstruction::ResetCounter, accounts)
+    };
+
+    let message = Message::new(&[reset_instruction(&[&signers[0]])], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer, &signers[0]],
+        message,
+        svm.latest_blockhash()
+    );
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Reset with 1 of 2 required signatures should fail");
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    // Two listed signers meet the threshold
+    let message = Message::new(
+        &[reset_instruction(&[&signers[0], &signers[2]])],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(
+        &[&payer, &signers[0], &signers[2]],
+        message,
+        svm.latest_blockhash()
+    );
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Reset with 2 of 2 required signatures should succeed");
+    assert_eq!(read_count(&svm, &counter), 0);
+
+    // The previous single-key authority lost control
+    let old_authority_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ResetCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[old_authority_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Reset by the previous authority should fail");
+}
+
+#[test]
+fn test_freeze_and_thaw() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+
+    let authority_instruction = |instruction: CounterInstruction| {
+        counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        )
+    };
+
+    let message = Message::new(
+        &[authority_instruction(CounterInstruction::FreezeCounter)],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Freeze transaction should succeed");
+
+    // Mutations are rejected while the value stays readable
+    for instruction in [
+        CounterInstruction::IncrementCounter { step: None },
+        CounterInstruction::DecrementCounter { step: None },
+        CounterInstruction::ResetCounter,
+    ] {
+        let message = Message::new(&[authority_instruction(instruction)], Some(&payer.pubkey()));
+        let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+        let result = svm.send_transaction(transaction);
+        assert!(result.is_err(), "Mutation of a frozen counter should fail");
+    }
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    let message = Message::new(
+        &[authority_instruction(CounterInstruction::ThawCounter)],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Thaw transaction should succeed");
+
+    let message = Message::new(
+        &[authority_instruction(CounterInstruction::IncrementCounter { step: None })],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment after thaw should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+}
+
+#[test]
+fn test_metadata() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter_keypair = Keypair::new();
+    let (metadata_address, _) = find_metadata_address(&program_id, &counter_keypair.pubkey());
+    let metadata = CounterMetadata {
+        name: String::from("page-views"),
+        description: String::from("Landing page opens"),
+    };
+
+    let initialize_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeCounter {
+            initial_value: 0,
+            metadata: Some(metadata.clone()),
+            history_capacity: 0,
+        },
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(metadata_address, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer, &counter_keypair],
+        message,
+        svm.latest_blockhash()
+    );
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize with metadata transaction should succeed");
+
+    let read_metadata = |svm: &LiteSVM| {
+        let account = svm
+            .get_account(&metadata_address)
+            .expect("Failed to get metadata account");
+        MetadataAccount::try_from_slice(account.data())
+            .expect("Failed to deserialize metadata")
+            .metadata
+    };
+    assert_eq!(read_metadata(&svm), metadata);
+
+    let update_instruction = |metadata: CounterMetadata| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::UpdateMetadata { metadata },
+            vec![
+                AccountMeta::new_readonly(counter_keypair.pubkey(), false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(metadata_address, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        )
+    };
+
+    // Longer descriptions grow the account
+    let updated = CounterMetadata {
+        name: String::from("page-views"),
+        description: String::from("Number of times the landing page was opened"),
+    };
+    let message = Message::new(&[update_instruction(updated.clone())], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Update metadata transaction should succeed");
+    assert_eq!(read_metadata(&svm), updated);
+
+    // Names over the length limit are rejected
+    let too_long = CounterMetadata {
+        name: "x".repeat(MAX_NAME_LEN + 1),
+        description: String::new(),
+    };
+    let message = Message::new(&[update_instruction(too_long)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Overlong name should be rejected");
+    assert_eq!(read_metadata(&svm), updated);
+}
+
+#[test]
+fn test_registry() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counters: Vec<Pubkey> = (0..3)
+        .map(|value| initialize_counter(&mut svm, program_id, &payer, value))
+        .collect();
+    let (registry, _) = find_registry_address(&program_id, &payer.pubkey());
+
+    let read_registry = |svm: &LiteSVM| {
+        let account = svm
+            .get_account(&registry)
+            .expect("Failed to get registry account");
+        RegistryAccount::try_from_slice(account.data())
+            .expect("Failed to deserialize registry")
+    };
+
+    let registry_data = read_registry(&svm);
+    assert_eq!(registry_data.counters, counters);
+    assert_eq!(registry_data.next_index, 3);
+    assert_eq!(registry_data.page(0, 2), &counters[..2]);
+    assert_eq!(registry_data.page(1, 2), &counters[2..]);
+    assert!(registry_data.page(2, 2).is_empty());
+
+    // Closing a counter removes it from the registry
+    let close_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseCounter,
+        vec![
+            AccountMeta::new(counters[1], false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new(registry, false),
+            AccountMeta::new(find_roles_address(&program_id, &counters[1]).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counters[1]).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counters[1]).0, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[close_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Close transaction should succeed");
+
+    let registry_data = read_registry(&svm);
+    assert_eq!(registry_data.counters, vec![counters[0], counters[2]]);
+    assert_eq!(registry_data.next_index, 3);
+
+    // Pages can also be read straight from the raw account bytes
+    let account = svm.get_account(&registry).expect("Failed to get registry account");
+    let entries = RegistryAccount::decode_entries(&account.data()[RegistryAccount::entry_range(1, 1)]);
+    assert_eq!(entries, vec![counters[2]]);
+}
+
+#[test]
+fn test_history() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter_keypair = Keypair::new();
+    let initialize_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeCounter {
+            initial_value: 10,
+            metadata: None,
+            history_capacity: 2,
+        },
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+        ],
+    );
+
+    let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(
+        &[&payer, &counter_keypair],
+        message,
+        svm.latest_blockhash()
+    );
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize with history transaction should succeed");
+
+    // Three changes overflow a two-entry buffer, dropping the oldest
+    for instruction in [
+        CounterInstruction::IncrementCounter { step: Some(5) },
+        CounterInstruction::DecrementCounter { step: Some(3) },
+        CounterInstruction::IncrementCounter { step: Some(1) },
+    ] {
+        let instruction = counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter_keypair.pubkey(), false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        );
+        let message = Message::new(&[instruction], Some(&payer.pubkey()));
+        let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+        let result = svm.send_transaction(transaction);
+        assert!(result.is_ok(), "Counter mutation should succeed");
+        svm.expire_blockhash();
+    }
+
+    let account = svm
+        .get_account(&counter_keypair.pubkey())
+        .expect("Failed to get counter account");
+    let counter = CounterAccount::load(account.data()).expect("Failed to read counter data");
+    assert_eq!(counter.count(), 13);
+
+    let history = decode_history(account.data()).expect("Failed to decode history");
+    assert_eq!(history.len(), 2);
+    // Entry fields are unaligned, so they are copied out before comparing
+    let changes: Vec<(i128, u64)> = history
+        .iter()
+        .map(|entry| (entry.delta, entry.resulting_value))
+        .collect();
+    assert_eq!(changes, vec![(-3, 12), (1, 13)]);
+    assert!(history.iter().all(|entry| entry.signer == payer.pubkey()));
+}
+
+#[test]
+fn test_zero_copy_compute_units() {
+    let (mut svm, program_id, payer) = setup();
+
+    // Compute units of one increment of a counter created with `history_capacity`
+    let mut increment_cost = |history_capacity: u16| {
+        let counter_keypair = Keypair::new();
+        let initialize_instruction = counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeCounter {
+                initial_value: 0,
+                metadata: None,
+                history_capacity,
+            },
+            vec![
+                AccountMeta::new(counter_keypair.pubkey(), true),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        );
+        let increment_instruction = counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter_keypair.pubkey(), false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+            ],
+        );
+
+        let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
+        let transaction = Transaction::new(
+            &[&payer, &counter_keypair],
+            message,
+            svm.latest_blockhash()
+        );
+        svm.send_transaction(transaction).expect("Initialize transaction should succeed");
+
+        let message = Message::new(&[increment_instruction], Some(&payer.pubkey()));
+        let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+        svm.send_transaction(transaction)
+            .expect("Increment transaction should succeed")
+            .compute_units_consumed
+    };
+
+    let plain = increment_cost(0);
+    let small_history = increment_cost(1);
+    let full_history = increment_cost(MAX_HISTORY_CAPACITY);
+    println!(
+        "Increment compute units: plain {}, 1-entry history {}, {}-entry history {}",
+        plain, small_history, MAX_HISTORY_CAPACITY, full_history
+    );
+
+    // Fields are cast in place, so the cost does not grow with the account size
+    assert_eq!(small_history, full_history);
+}
+
+#[test]
+fn test_global_pause() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let (config, _) = find_config_address(&program_id);
+
+    // Only the upgrade authority recorded in the ProgramData account may initialize the config
+    let (program_data, _) = find_program_data_address(&program_id);
+    let mut program_data_bytes = 3u32.to_le_bytes().to_vec();
+    program_data_bytes.extend_from_slice(&0u64.to_le_bytes());
+    program_data_bytes.push(1);
+    program_data_bytes.extend_from_slice(payer.pubkey().as_ref());
+    svm.set_account(
+        program_data,
+        Account {
+            lamports: svm.minimum_balance_for_rent_exemption(program_data_bytes.len()),
+            data: program_data_bytes,
+            owner: bpf_loader_upgradeable::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    )
+    .expect("Failed to set the ProgramData account");
+
+    let initialize_config_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeConfig,
+        vec![
+            AccountMeta::new(config, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(program_data, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+
+    let message = Message::new(&[initialize_config_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize config transaction should succeed");
+
+    let set_paused_instruction = |paused: bool| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::SetPaused { paused },
+            vec![
+                AccountMeta::new(config, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+            ],
+        )
+    };
+    let increment_instruction = || {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(config, false),
+            ],
+        )
+    };
+
+    let message = Message::new(&[set_paused_instruction(true)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Pause transaction should succeed");
+
+    // Every counter instruction is refused while paused
+    let message = Message::new(&[increment_instruction()], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment while paused should fail");
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    let message = Message::new(&[set_paused_instruction(false)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Unpause transaction should succeed");
+
+    let message = Message::new(&[increment_instruction()], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment after unpause should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+}
+#[test]
+fn test_token_gate() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let mint = create_mint(&mut svm, &payer, &payer.pubkey());
+    let config = find_config_address(&program_id).0;
+
+    let set_gate_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetTokenGate { mint, min_balance: 1 },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[set_gate_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set token gate transaction should succeed");
+
+    let increment_instruction = |token_account: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(token_account, false),
+                AccountMeta::new_readonly(config, false),
+            ],
+        )
+    };
+
+    // An empty token account does not pass the gate
+    let empty_account = create_token_account(&mut svm, &payer, &mint, &payer.pubkey(), 0);
+    let message = Message::new(&[increment_instruction(empty_account)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment without gate tokens should fail");
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    let holder_account = create_token_account(&mut svm, &payer, &mint, &payer.pubkey(), 1);
+    let message = Message::new(&[increment_instruction(holder_account)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Increment by a token holder should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+}
+#[test]
+fn test_token_payment() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let mint = create_mint(&mut svm, &payer, &payer.pubkey());
+    let source = create_token_account(&mut svm, &payer, &mint, &payer.pubkey(), 10);
+    let destination = create_token_account(&mut svm, &payer, &mint, &Pubkey::new_unique(), 0);
+    let config = find_config_address(&program_id).0;
+
+    let set_payment_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetTokenPayment {
+            mint,
+            amount_per_step: 2,
+            mode: TokenPaymentMode::Transfer,
+            destination,
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[set_payment_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set token payment transaction should succeed");
+
+    let increment_instruction = |step: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: Some(step) },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(source, false),
+                AccountMeta::new(destination, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(config, false),
+            ],
+        )
+    };
+    let token_balance = |svm: &LiteSVM, token_account: &Pubkey| {
+        let account = svm.get_account(token_account).expect("Failed to get token account");
+        spl_token::state::Account::unpack(account.data())
+            .expect("Failed to read token account")
+            .amount
+    };
+
+    let message = Message::new(&[increment_instruction(3)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Paid increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 3);
+    assert_eq!(token_balance(&svm, &source), 4);
+    assert_eq!(token_balance(&svm, &destination), 6);
+
+    // The remaining balance does not cover another three steps
+    let message = Message::new(&[increment_instruction(3)], Some(&payer.pubkey()));
+    svm.expire_blockhash();
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Increment without enough tokens should fail");
+    assert_eq!(read_count(&svm, &counter), 3);
+}
+#[test]
+fn test_tokenized_units() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let (mint_authority, _) = find_mint_authority_address(&program_id, &counter);
+    let mint = create_mint(&mut svm, &payer, &mint_authority);
+    let units = create_token_account(&mut svm, &payer, &mint, &payer.pubkey(), 0);
+    let config = find_config_address(&program_id).0;
+
+    let set_units_mint_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetUnitsMint { mint },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[set_units_mint_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set units mint transaction should succeed");
+
+    let increment_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: Some(4) },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(mint, false),
+            AccountMeta::new(units, false),
+            AccountMeta::new_readonly(mint_authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[increment_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Minting increment should succeed");
+
+    let decrement_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::DecrementCounter { step: Some(1) },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(units, false),
+            AccountMeta::new(mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[decrement_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Burning decrement should succeed");
+
+    // Counter value and token supply move together
+    let mint_account = svm.get_account(&mint).expect("Failed to get mint");
+    let supply = spl_token::state::Mint::unpack(mint_account.data())
+        .expect("Failed to read mint")
+        .supply;
+    assert_eq!(read_count(&svm, &counter), 3);
+    assert_eq!(supply, 3);
+}
+#[test]
+fn test_triggers() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let config = find_config_address(&program_id).0;
+    // SPL memo v1, shipped with LiteSVM, logs its data and ignores its accounts
+    let memo_program = Pubkey::from_str_const("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+
+    let set_trigger_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetTrigger {
+            index: 0,
+            threshold: 2,
+            program: memo_program,
+            data: b"milestone".to_vec(),
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[set_trigger_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set trigger transaction should succeed");
+
+    let increment_instruction = |step: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: Some(step) },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(memo_program, false),
+                AccountMeta::new_readonly(config, false),
+            ],
+        )
+    };
+
+    let message = Message::new(&[increment_instruction(1)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let metadata = svm.send_transaction(transaction).expect("Increment below the threshold should succeed");
+    assert!(!metadata.logs.iter().any(|log| log.contains("milestone")));
+
+    let message = Message::new(&[increment_instruction(2)], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let metadata = svm.send_transaction(transaction).expect("Increment across the threshold should succeed");
+    assert!(
+        metadata.logs.iter().any(|log| log.contains("milestone")),
+        "Trigger should invoke the memo program"
+    );
+
+    let account = svm.get_account(&counter).expect("Failed to get counter account");
+    let counter_data = CounterAccount::load(account.data()).expect("Failed to read counter data");
+    assert!(counter_data.triggers()[0].has_fired());
+}
+#[test]
+fn test_expiry() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let config = find_config_address(&program_id).0;
+    let mut clock: Clock = svm.get_sysvar();
+    let expires_at = clock.unix_timestamp + 60;
+
+    let set_expiry_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetExpiry { expires_at },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(&[set_expiry_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set expiry transaction should succeed");
+
+    // A stranger pays for the close, the rent goes back to the original payer
+    let collector = Keypair::new();
+    svm.airdrop(&collector.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+    let close_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseExpiredCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_roles_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counter).0, false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+
+    let message = Message::new(std::slice::from_ref(&close_instruction), Some(&collector.pubkey()));
+    let transaction = Transaction::new(&[&collector], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Closing before expiry should fail");
+
+    clock.unix_timestamp = expires_at;
+    svm.set_sysvar(&clock);
+    svm.expire_blockhash();
+
+    let payer_lamports = svm.get_balance(&payer.pubkey()).unwrap_or(0);
+    let message = Message::new(&[close_instruction], Some(&collector.pubkey()));
+    let transaction = Transaction::new(&[&collector], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Closing after expiry should succeed");
+    let closed = svm.get_account(&counter);
+    assert!(
+        closed.is_none() || closed.unwrap().lamports == 0,
+        "Counter account should be closed"
+    );
+    assert!(svm.get_balance(&payer.pubkey()).unwrap_or(0) > payer_lamports);
+}
+
+#[test]
+fn test_timelock() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let config = find_config_address(&program_id).0;
+    let scheduled = find_scheduled_change_address(&program_id, &counter).0;
+
+    let set_timelock_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetTimelock { delay: 60 },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let schedule_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ScheduleChange {
+            action: ScheduledAction::Reset,
+        },
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+    let message = Message::new(
+        &[set_timelock_instruction, schedule_instruction],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Set timelock and schedule transaction should succeed");
+
+    // Anyone may execute, but only after the delay
+    let executor = Keypair::new();
+    svm.airdrop(&executor.pubkey(), 1_000_000_000)
+        .expect("Failed to airdrop");
+    let execute_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ExecuteChange,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new_readonly(config, false),
+        ],
+    );
+
+    let message = Message::new(std::slice::from_ref(&execute_instruction), Some(&executor.pubkey()));
+    let transaction = Transaction::new(&[&executor], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Executing before the delay should fail");
+
+    let mut clock: Clock = svm.get_sysvar();
+    clock.unix_timestamp += 60;
+    svm.set_sysvar(&clock);
+    svm.expire_blockhash();
+
+    let message = Message::new(&[execute_instruction], Some(&executor.pubkey()));
+    let transaction = Transaction::new(&[&executor], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Executing after the delay should succeed");
+
+    let counter_account = svm.get_account(&counter).expect("Failed to get counter account");
+    let counter_data = CounterAccount::load(&counter_account.data).expect("Failed to read counter data");
+    assert_eq!(counter_data.count(), 0);
+}
+
+#[test]
+fn test_first_release_clients() {
+    let (mut svm, program_id, payer) = setup();
+
+    // Instruction data and account lists exactly as clients of the first release build them
+    let counter_keypair = Keypair::new();
+    let initialize_instruction = Instruction::new_with_bytes(
+        program_id,
+        &[0, 10, 0, 0, 0, 0, 0, 0, 0],
+        vec![
+            AccountMeta::new(counter_keypair.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let message = Message::new(&[initialize_instruction], Some(&payer.pubkey()));
+    let transaction = Transaction::new(&[&payer, &counter_keypair], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Initialize transaction should succeed");
+
+    let step_instruction = |data: &[u8]| {
+        Instruction::new_with_bytes(
+            program_id,
+            data,
+            vec![AccountMeta::new(counter_keypair.pubkey(), false)],
+        )
+    };
+    let read_baseline = |svm: &LiteSVM| {
+        let account = svm
+            .get_account(&counter_keypair.pubkey())
+            .expect("Failed to get counter account");
+        u64::from_le_bytes(account.data().try_into().expect("Baseline counters hold 8 bytes"))
+    };
+
+    let message = Message::new(
+        &[
+            step_instruction(&[1, 0]),
+            step_instruction(&[1, 1, 5, 0, 0, 0, 0, 0, 0, 0]),
+            step_instruction(&[2, 0]),
+        ],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_ok(), "Baseline steps should succeed");
+    assert_eq!(read_baseline(&svm), 15);
+
+    let message = Message::new(
+        &[step_instruction(&[2, 1, 16, 0, 0, 0, 0, 0, 0, 0])],
+        Some(&payer.pubkey()),
+    );
+    let transaction = Transaction::new(&[&payer], message, svm.latest_blockhash());
+    let result = svm.send_transaction(transaction);
+    assert!(result.is_err(), "Decrement below zero should fail");
+    assert_eq!(read_baseline(&svm), 15);
+}
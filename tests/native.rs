@@ -0,0 +1,2561 @@
+//! Program logic tests that run `process_instruction` natively, without `cargo build-sbf`
+
+mod common;
+
+use borsh::BorshDeserialize;
+use common::{Account, NativeSvm};
+use solana_counter_program::*;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    bpf_loader_upgradeable,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+/// Register the program in a fresh native ledger with a funded payer
+fn setup() -> (NativeSvm, Pubkey, Keypair) {
+    let mut svm = NativeSvm::new();
+
+    let payer = Keypair::new();
+    svm.airdrop(&payer.pubkey(), 1_000_000_000);
+
+    let program_id = Pubkey::new_unique();
+    svm.add_program(program_id, process_instruction);
+
+    (svm, program_id, payer)
+}
+
+fn config_meta(program_id: &Pubkey) -> AccountMeta {
+    AccountMeta::new_readonly(find_config_address(program_id).0, false)
+}
+
+fn initialize_instruction(
+    program_id: Pubkey,
+    payer: &Keypair,
+    counter: &Keypair,
+    initial_value: u64,
+    history_capacity: u16,
+) -> Instruction {
+    counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeCounter {
+            initial_value,
+            metadata: None,
+            history_capacity,
+        },
+        vec![
+            AccountMeta::new(counter.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            config_meta(&program_id),
+        ],
+    )
+}
+
+/// Create a counter account holding `initial_value`
+fn initialize_counter(svm: &mut NativeSvm, program_id: Pubkey, payer: &Keypair, initial_value: u64) -> Pubkey {
+    let counter = Keypair::new();
+    let instruction = initialize_instruction(program_id, payer, &counter, initial_value, 0);
+    svm.process(&[instruction], &[payer, &counter])
+        .expect("Initialize should succeed");
+
+    counter.pubkey()
+}
+
+/// Counter instruction signed by a single-key authority
+fn authority_instruction(
+    program_id: Pubkey,
+    instruction: CounterInstruction,
+    counter: Pubkey,
+    authority: &Keypair,
+) -> Instruction {
+    counter_instruction(
+        &program_id,
+        &instruction,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(authority.pubkey(), true),
+            config_meta(&program_id),
+        ],
+    )
+}
+
+/// Close a counter signed by its single-key authority, refunding the authority
+fn close_instruction(program_id: Pubkey, counter: Pubkey, authority: &Keypair) -> Instruction {
+    counter_instruction(
+        &program_id,
+        &CounterInstruction::CloseCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(authority.pubkey(), true),
+            AccountMeta::new(authority.pubkey(), false),
+            AccountMeta::new(find_registry_address(&program_id, &authority.pubkey()).0, false),
+            AccountMeta::new(find_roles_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counter).0, false),
+            config_meta(&program_id),
+        ],
+    )
+}
+
+/// Record `authority` as upgrade authority in the ProgramData account of the program
+fn set_upgrade_authority(svm: &mut NativeSvm, program_id: Pubkey, authority: &Pubkey) -> Pubkey {
+    let (program_data, _) = find_program_data_address(&program_id);
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(authority.as_ref());
+    svm.set_account(
+        program_data,
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::id(),
+            executable: false,
+        },
+    );
+    program_data
+}
+
+/// Initialize the config with `admin` and make new counters use `overflow_mode`
+fn configure(svm: &mut NativeSvm, program_id: Pubkey, admin: &Keypair, overflow_mode: OverflowMode) {
+    let program_data = set_upgrade_authority(svm, program_id, &admin.pubkey());
+    let (config, _) = find_config_address(&program_id);
+    let initialize = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeConfig,
+        vec![
+            AccountMeta::new(config, false),
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new_readonly(program_data, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    svm.process(&[initialize], &[admin]).expect("Initialize config should succeed");
+    set_overflow_mode(svm, program_id, admin, overflow_mode);
+}
+
+/// Make counters initialized from now on use `overflow_mode`
+fn set_overflow_mode(svm: &mut NativeSvm, program_id: Pubkey, admin: &Keypair, overflow_mode: OverflowMode) {
+    let update = counter_instruction(
+        &program_id,
+        &CounterInstruction::UpdateConfig {
+            admin: admin.pubkey(),
+            default_step: 1,
+            default_overflow_mode: overflow_mode,
+        },
+        vec![
+            AccountMeta::new(find_config_address(&program_id).0, false),
+            AccountMeta::new_readonly(admin.pubkey(), true),
+        ],
+    );
+    svm.process(&[update], &[admin]).expect("Update config should succeed");
+}
+
+fn read_counter(svm: &NativeSvm, counter: &Pubkey) -> CounterAccount {
+    let account = svm.get_account(counter).expect("Failed to get counter account");
+    *CounterAccount::load(&account.data).expect("Failed to read counter data")
+}
+
+fn read_count(svm: &NativeSvm, counter: &Pubkey) -> u64 {
+    read_counter(svm, counter).count()
+}
+
+#[test]
+fn test_counter_program() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 42);
+    assert_eq!(read_count(&svm, &counter), 42);
+    assert_eq!(read_counter(&svm, &counter).authority, payer.pubkey());
+
+    let counter_keypair = Keypair::new();
+    let instruction = initialize_instruction(program_id, &payer, &counter_keypair, 0, 0);
+    svm.process(std::slice::from_ref(&instruction), &[&payer, &counter_keypair])
+        .expect("Initialize should succeed");
+    assert!(
+        svm.process(&[instruction], &[&payer, &counter_keypair]).is_err(),
+        "Initializing twice should fail"
+    );
+
+    let steps = [
+        (CounterInstruction::IncrementCounter { step: None }, 43),
+        (CounterInstruction::IncrementCounter { step: Some(5) }, 48),
+        (CounterInstruction::DecrementCounter { step: None }, 47),
+        (CounterInstruction::DecrementCounter { step: Some(3) }, 44),
+        (CounterInstruction::ResetCounter, 0),
+    ];
+    for (instruction, expected) in steps {
+        let instruction = authority_instruction(program_id, instruction, counter, &payer);
+        svm.process(&[instruction], &[&payer]).expect("Counter update should succeed");
+        assert_eq!(read_count(&svm, &counter), expected);
+    }
+
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::DecrementCounter { step: None },
+        counter,
+        &payer,
+    );
+    assert_eq!(
+        svm.process(&[instruction], &[&payer]),
+        Err(ProgramError::InvalidAccountData),
+        "Decrement below zero should fail"
+    );
+
+    // Only the authority may change the counter
+    let stranger = Keypair::new();
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        counter,
+        &stranger,
+    );
+    assert!(svm.process(&[instruction], &[&stranger]).is_err());
+    assert_eq!(read_count(&svm, &counter), 0);
+}
+
+#[test]
+fn test_batch() {
+    let (mut svm, program_id, payer) = setup();
+
+    let first = initialize_counter(&mut svm, program_id, &payer, 10);
+    let second = initialize_counter(&mut svm, program_id, &payer, 20);
+
+    let batch_instruction = |ops: Vec<(u8, CounterOp)>| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::Batch { ops },
+            vec![
+                AccountMeta::new(first, false),
+                AccountMeta::new(second, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    let instruction = batch_instruction(vec![
+        (0, CounterOp::Increment { step: None }),
+        (1, CounterOp::Decrement { step: Some(5) }),
+        (0, CounterOp::Increment { step: Some(4) }),
+        (1, CounterOp::Set { value: 100 }),
+    ]);
+    svm.process(&[instruction], &[&payer]).expect("Batch should succeed");
+    assert_eq!(read_count(&svm, &first), 15);
+    assert_eq!(read_count(&svm, &second), 100);
+
+    // A failing operation must leave every counter untouched
+    let instruction = batch_instruction(vec![
+        (0, CounterOp::Increment { step: Some(1) }),
+        (1, CounterOp::Decrement { step: Some(101) }),
+    ]);
+    assert!(svm.process(&[instruction], &[&payer]).is_err());
+    assert_eq!(read_count(&svm, &first), 15);
+    assert_eq!(read_count(&svm, &second), 100);
+
+    let instruction = batch_instruction(vec![(2, CounterOp::Increment { step: None })]);
+    assert!(
+        svm.process(&[instruction], &[&payer]).is_err(),
+        "Batch with unknown account index should fail"
+    );
+}
+
+#[test]
+fn test_transfer_between_counters() {
+    let (mut svm, program_id, payer) = setup();
+
+    let source = initialize_counter(&mut svm, program_id, &payer, 50);
+    let destination = initialize_counter(&mut svm, program_id, &payer, 5);
+
+    let transfer_instruction = |amount: u64, authority: &Keypair| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::TransferBetweenCounters { amount },
+            vec![
+                AccountMeta::new(source, false),
+                AccountMeta::new(destination, false),
+                AccountMeta::new_readonly(authority.pubkey(), true),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    svm.process(&[transfer_instruction(20, &payer)], &[&payer])
+        .expect("Transfer should succeed");
+    assert_eq!(read_count(&svm, &source), 30);
+    assert_eq!(read_count(&svm, &destination), 25);
+
+    assert!(
+        svm.process(&[transfer_instruction(31, &payer)], &[&payer]).is_err(),
+        "Transfer exceeding the source value should fail"
+    );
+
+    let stranger = Keypair::new();
+    assert!(
+        svm.process(&[transfer_instruction(1, &stranger)], &[&stranger]).is_err(),
+        "Transfer signed by another key should fail"
+    );
+    assert_eq!(read_count(&svm, &source), 30);
+    assert_eq!(read_count(&svm, &destination), 25);
+
+    // A counter of another authority cannot be raised through a transfer
+    svm.airdrop(&stranger.pubkey(), 1_000_000_000);
+    let own = initialize_counter(&mut svm, program_id, &stranger, u64::MAX);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 1 },
+        vec![
+            AccountMeta::new(own, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(stranger.pubkey(), true),
+            config_meta(&program_id),
+        ],
+    );
+    assert_eq!(
+        svm.process(&[instruction], &[&stranger]),
+        Err(ProgramError::IncorrectAuthority)
+    );
+    assert_eq!(read_count(&svm, &destination), 25);
+
+    // Each side follows its own overflow mode
+    let admin = Keypair::new();
+    svm.airdrop(&admin.pubkey(), 1_000_000_000);
+    configure(&mut svm, program_id, &admin, OverflowMode::Saturating);
+    let saturating_source = initialize_counter(&mut svm, program_id, &payer, 5);
+    let saturating_destination = initialize_counter(&mut svm, program_id, &payer, u64::MAX - 2);
+    set_overflow_mode(&mut svm, program_id, &admin, OverflowMode::Wrapping);
+    let wrapping_source = initialize_counter(&mut svm, program_id, &payer, 5);
+    let wrapping_destination = initialize_counter(&mut svm, program_id, &payer, u64::MAX - 2);
+    let transfer = |source: Pubkey, destination: Pubkey, amount: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::TransferBetweenCounters { amount },
+            vec![
+                AccountMeta::new(source, false),
+                AccountMeta::new(destination, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    svm.process(&[transfer(saturating_source, saturating_destination, 10)], &[&payer])
+        .expect("Saturating transfer should succeed");
+    assert_eq!(read_count(&svm, &saturating_source), 0);
+    assert_eq!(read_count(&svm, &saturating_destination), u64::MAX);
+
+    svm.process(&[transfer(wrapping_source, wrapping_destination, 10)], &[&payer])
+        .expect("Wrapping transfer should succeed");
+    assert_eq!(read_count(&svm, &wrapping_source), u64::MAX - 4);
+    assert_eq!(read_count(&svm, &wrapping_destination), 7);
+
+    // A checked side still refuses to go out of range
+    assert_eq!(
+        svm.process(&[transfer(wrapping_source, destination, u64::MAX - 10)], &[&payer]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    assert_eq!(
+        svm.process(&[transfer(source, saturating_destination, 31)], &[&payer]),
+        Err(ProgramError::InvalidAccountData)
+    );
+}
+
+#[test]
+fn test_delegate_allowances() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let delegate = Keypair::new();
+    let (delegate_record, _) = find_delegate_address(&program_id, &counter, &delegate.pubkey());
+
+    let delegate_instruction = |instruction: CounterInstruction| {
+        counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(delegate.pubkey(), true),
+                AccountMeta::new(delegate_record, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    let increment = |step: u64| delegate_instruction(CounterInstruction::IncrementCounter { step: Some(step) });
+
+    assert!(
+        svm.process(&[increment(1)], &[&delegate]).is_err(),
+        "Increment by an unapproved delegate should fail"
+    );
+
+    let approve_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ApproveDelegate {
+            increment_allowance: 5,
+            decrement_allowance: 0,
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(std::slice::from_ref(&approve_instruction), &[&payer])
+        .expect("Approve should succeed");
+    assert_eq!(read_counter(&svm, &counter).delegate_count(), 1);
+
+    svm.process(&[increment(3)], &[&delegate])
+        .expect("Increment within allowance should succeed");
+    assert_eq!(read_count(&svm, &counter), 13);
+
+    let record = svm.get_account(&delegate_record).expect("Failed to get delegate record");
+    let record = DelegateAccount::try_from_slice(&record.data).expect("Failed to deserialize delegate record");
+    assert_eq!(record.increment_allowance, 2);
+
+    assert_eq!(
+        svm.process(&[increment(3)], &[&delegate]),
+        Err(CounterError::InsufficientAllowance.into())
+    );
+    assert_eq!(
+        svm.process(
+            &[delegate_instruction(CounterInstruction::DecrementCounter { step: None })],
+            &[&delegate]
+        ),
+        Err(CounterError::InsufficientAllowance.into())
+    );
+
+    // Approving again replaces the allowances without counting the delegate twice
+    svm.process(&[approve_instruction], &[&payer]).expect("Approve should succeed");
+    assert_eq!(read_counter(&svm, &counter).delegate_count(), 1);
+
+    // The counter cannot be closed while the record is open
+    assert_eq!(
+        svm.process(&[close_instruction(program_id, counter, &payer)], &[&payer]),
+        Err(CounterError::DelegatesOutstanding.into())
+    );
+
+    // Revoking closes the record and refunds its rent
+    let payer_lamports = svm.get_account(&payer.pubkey()).unwrap().lamports;
+    let revoke_instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::RevokeDelegate,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(delegate.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[revoke_instruction], &[&payer]).expect("Revoke should succeed");
+    assert!(svm.get_account(&delegate_record).is_none());
+    assert!(svm.get_account(&payer.pubkey()).unwrap().lamports > payer_lamports);
+    assert_eq!(read_counter(&svm, &counter).delegate_count(), 0);
+
+    assert!(
+        svm.process(&[increment(1)], &[&delegate]).is_err(),
+        "Increment after revoke should fail"
+    );
+    assert_eq!(read_count(&svm, &counter), 13);
+}
+
+#[test]
+fn test_roles() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let (roles, _) = find_roles_address(&program_id, &counter);
+    let visitor = Keypair::new();
+    let moderator = Keypair::new();
+
+    let admin_instruction = |instruction: CounterInstruction, system: bool| {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(roles, false),
+        ];
+        if system {
+            accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        }
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &instruction, accounts)
+    };
+    let mutation = |instruction: CounterInstruction, signer: &Keypair| {
+        counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(roles, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    let instructions = [
+        admin_instruction(CounterInstruction::InitializeRoles, true),
+        admin_instruction(
+            CounterInstruction::SetRolePublic {
+                role: Role::Incrementer,
+                public: true,
+            },
+            false,
+        ),
+        admin_instruction(
+            CounterInstruction::AddRoleHolder {
+                role: Role::Decrementer,
+                holder: moderator.pubkey(),
+            },
+            true,
+        ),
+    ];
+    svm.process(&instructions, &[&payer]).expect("Role setup should succeed");
+
+    // Anyone may increment, only the moderator may decrement
+    svm.process(&[mutation(CounterInstruction::IncrementCounter { step: None }, &visitor)], &[&visitor])
+        .expect("Public increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+
+    assert_eq!(
+        svm.process(&[mutation(CounterInstruction::DecrementCounter { step: None }, &visitor)], &[&visitor]),
+        Err(CounterError::MissingRole.into())
+    );
+    svm.process(&[mutation(CounterInstruction::DecrementCounter { step: None }, &moderator)], &[&moderator])
+        .expect("Moderator decrement should succeed");
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    assert!(
+        svm.process(&[mutation(CounterInstruction::ResetCounter, &moderator)], &[&moderator]).is_err(),
+        "Reset by a non-admin should fail"
+    );
+
+    // Removing the role takes the permission away again
+    let instruction = admin_instruction(
+        CounterInstruction::RemoveRoleHolder {
+            role: Role::Decrementer,
+            holder: moderator.pubkey(),
+        },
+        true,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Remove role holder should succeed");
+    assert!(svm
+        .process(&[mutation(CounterInstruction::DecrementCounter { step: None }, &moderator)], &[&moderator])
+        .is_err());
+    assert_eq!(read_count(&svm, &counter), 10);
+}
+
+#[test]
+fn test_multisig_authority() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let multisig = Keypair::new();
+    let signers = [Keypair::new(), Keypair::new(), Keypair::new()];
+
+    let initialize_multisig = |keys: &[Pubkey]| {
+        let mut accounts = vec![
+            AccountMeta::new(multisig.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ];
+        accounts.extend(keys.iter().map(|key| AccountMeta::new_readonly(*key, false)));
+        accounts.push(config_meta(&program_id));
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeMultisig { threshold: 2 },
+            accounts,
+        )
+    };
+
+    // A key listed twice would meet the threshold on its own
+    let repeated = [signers[0].pubkey(), signers[0].pubkey(), signers[1].pubkey()];
+    assert_eq!(
+        svm.process(&[initialize_multisig(&repeated)], &[&payer, &multisig]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    let keys: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+    svm.process(&[initialize_multisig(&keys)], &[&payer, &multisig])
+        .expect("Initialize multisig should succeed");
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: multisig.pubkey(),
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_registry_address(&program_id, &multisig.pubkey()).0, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Set authority should succeed");
+    assert_eq!(read_counter(&svm, &counter).authority, multisig.pubkey());
+
+    let reset_instruction = |approvers: &[&Keypair]| {
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(multisig.pubkey(), false),
+        ];
+        accounts.extend(
+            approvers
+                .iter()
+                .map(|approver| AccountMeta::new_readonly(approver.pubkey(), true)),
+        );
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &CounterInstruction::ResetCounter, accounts)
+    };
+
+    assert!(
+        svm.process(&[reset_instruction(&[&signers[0]])], &[&signers[0]]).is_err(),
+        "Reset with 1 of 2 required signatures should fail"
+    );
+    assert!(
+        svm.process(&[reset_instruction(&[&signers[0], &signers[0]])], &[&signers[0]]).is_err(),
+        "The same signer listed twice should count once"
+    );
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    svm.process(&[reset_instruction(&[&signers[0], &signers[2]])], &[&signers[0], &signers[2]])
+        .expect("Reset with 2 of 2 required signatures should succeed");
+    assert_eq!(read_count(&svm, &counter), 0);
+
+    let instruction = authority_instruction(program_id, CounterInstruction::ResetCounter, counter, &payer);
+    assert!(
+        svm.process(&[instruction], &[&payer]).is_err(),
+        "Reset by the previous authority should fail"
+    );
+}
+
+#[test]
+fn test_freeze_and_thaw() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let instruction = |instruction: CounterInstruction| authority_instruction(program_id, instruction, counter, &payer);
+
+    svm.process(&[instruction(CounterInstruction::FreezeCounter)], &[&payer])
+        .expect("Freeze should succeed");
+    assert!(read_counter(&svm, &counter).is_frozen());
+
+    for mutation in [
+        CounterInstruction::IncrementCounter { step: None },
+        CounterInstruction::DecrementCounter { step: None },
+        CounterInstruction::ResetCounter,
+    ] {
+        assert_eq!(
+            svm.process(&[instruction(mutation)], &[&payer]),
+            Err(CounterError::CounterFrozen.into())
+        );
+    }
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    svm.process(&[instruction(CounterInstruction::ThawCounter)], &[&payer])
+        .expect("Thaw should succeed");
+    svm.process(&[instruction(CounterInstruction::IncrementCounter { step: None })], &[&payer])
+        .expect("Increment after thaw should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+}
+
+#[test]
+fn test_metadata() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = Keypair::new();
+    let (metadata_address, _) = find_metadata_address(&program_id, &counter.pubkey());
+    let metadata = CounterMetadata {
+        name: String::from("page-views"),
+        description: String::from("Landing page opens"),
+    };
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeCounter {
+            initial_value: 0,
+            metadata: Some(metadata.clone()),
+            history_capacity: 0,
+        },
+        vec![
+            AccountMeta::new(counter.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(metadata_address, false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer, &counter])
+        .expect("Initialize with metadata should succeed");
+
+    let read_metadata = |svm: &NativeSvm| {
+        let account = svm.get_account(&metadata_address).expect("Failed to get metadata account");
+        MetadataAccount::try_from_slice(&account.data)
+            .expect("Failed to deserialize metadata")
+            .metadata
+    };
+    assert_eq!(read_metadata(&svm), metadata);
+
+    // A separate payer funds growth, so the authority does not have to hold lamports
+    let metadata_payer = Keypair::new();
+    svm.airdrop(&metadata_payer.pubkey(), 1_000_000_000);
+    let update_instruction = |metadata: CounterMetadata| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::UpdateMetadata { metadata },
+            vec![
+                AccountMeta::new_readonly(counter.pubkey(), false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(metadata_address, false),
+                AccountMeta::new(metadata_payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    // The account grows and shrinks with the metadata
+    let longer = CounterMetadata {
+        name: String::from("page-views"),
+        description: String::from("Number of times the landing page was opened"),
+    };
+    let authority_lamports = svm.get_account(&payer.pubkey()).unwrap().lamports;
+    svm.process(&[update_instruction(longer.clone())], &[&payer, &metadata_payer])
+        .expect("Update metadata should succeed");
+    assert_eq!(read_metadata(&svm), longer);
+    assert_eq!(svm.get_account(&payer.pubkey()).unwrap().lamports, authority_lamports);
+    assert!(svm.get_account(&metadata_payer.pubkey()).unwrap().lamports < 1_000_000_000);
+    let grown_len = svm.get_account(&metadata_address).unwrap().data.len();
+
+    let shorter = CounterMetadata {
+        name: String::from("views"),
+        description: String::new(),
+    };
+    svm.process(&[update_instruction(shorter.clone())], &[&payer, &metadata_payer])
+        .expect("Update metadata should succeed");
+    assert_eq!(read_metadata(&svm), shorter);
+    assert!(svm.get_account(&metadata_address).unwrap().data.len() < grown_len);
+
+    let too_long = CounterMetadata {
+        name: "x".repeat(MAX_NAME_LEN + 1),
+        description: String::new(),
+    };
+    assert_eq!(
+        svm.process(&[update_instruction(too_long)], &[&payer, &metadata_payer]),
+        Err(CounterError::MetadataTooLong.into())
+    );
+    assert_eq!(read_metadata(&svm), shorter);
+
+    // Closing the counter closes its metadata too
+    svm.process(&[close_instruction(program_id, counter.pubkey(), &payer)], &[&payer])
+        .expect("Close should succeed");
+    assert!(svm.get_account(&counter.pubkey()).is_none());
+    assert!(svm.get_account(&metadata_address).is_none());
+}
+
+#[test]
+fn test_registry() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counters: Vec<Pubkey> = (0..3)
+        .map(|value| initialize_counter(&mut svm, program_id, &payer, value))
+        .collect();
+    let (registry, _) = find_registry_address(&program_id, &payer.pubkey());
+
+    let read_registry = |svm: &NativeSvm| {
+        let account = svm.get_account(&registry).expect("Failed to get registry account");
+        RegistryAccount::try_from_slice(&account.data).expect("Failed to deserialize registry")
+    };
+
+    let registry_data = read_registry(&svm);
+    assert_eq!(registry_data.counters, counters);
+    assert_eq!(registry_data.next_index, 3);
+    assert_eq!(registry_data.page(1, 2), &counters[2..]);
+
+    svm.process(&[close_instruction(program_id, counters[1], &payer)], &[&payer])
+        .expect("Close should succeed");
+    assert!(svm.get_account(&counters[1]).is_none());
+
+    let registry_data = read_registry(&svm);
+    assert_eq!(registry_data.counters, vec![counters[0], counters[2]]);
+    assert_eq!(registry_data.next_index, 3);
+
+    let account = svm.get_account(&registry).unwrap();
+    let entries = RegistryAccount::decode_entries(&account.data[RegistryAccount::entry_range(1, 1)]);
+    assert_eq!(entries, vec![counters[2]]);
+
+    // Handing a counter over refunds the old registry entry to the old authority,
+    // while a separate payer funds the entry in the new registry
+    let new_authority = Pubkey::new_unique();
+    let new_payer = Keypair::new();
+    svm.airdrop(&new_payer.pubkey(), 1_000_000_000);
+    let registry_lamports = svm.get_account(&registry).unwrap().lamports;
+    let authority_lamports = svm.get_account(&payer.pubkey()).unwrap().lamports;
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetAuthority { new_authority },
+        vec![
+            AccountMeta::new(counters[2], false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(registry, false),
+            AccountMeta::new(find_registry_address(&program_id, &new_authority).0, false),
+            AccountMeta::new(new_payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer, &new_payer]).expect("Set authority should succeed");
+    let refund = registry_lamports - svm.get_account(&registry).unwrap().lamports;
+    assert!(refund > 0);
+    assert_eq!(svm.get_account(&payer.pubkey()).unwrap().lamports, authority_lamports + refund);
+    assert_eq!(read_registry(&svm).counters, vec![counters[0]]);
+}
+
+#[test]
+fn test_migrate_counter() {
+    let (mut svm, program_id, payer) = setup();
+
+    // A counter created by the first release holds only its count
+    let counter = Keypair::new();
+    svm.set_account(
+        counter.pubkey(),
+        Account {
+            lamports: Rent::default().minimum_balance(BASELINE_COUNTER_LEN),
+            data: 41u64.to_le_bytes().to_vec(),
+            owner: program_id,
+            executable: false,
+        },
+    );
+    let authority = Keypair::new();
+    let registry = find_registry_address(&program_id, &authority.pubkey()).0;
+
+    let increment = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        counter.pubkey(),
+        &authority,
+    );
+    assert_eq!(
+        svm.process(std::slice::from_ref(&increment), &[&authority]),
+        Err(ProgramError::InvalidAccountData),
+        "Baseline counters have to be migrated first"
+    );
+
+    let migrate = |counter_signs: bool| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::MigrateCounter,
+            vec![
+                AccountMeta::new(counter.pubkey(), counter_signs),
+                AccountMeta::new_readonly(authority.pubkey(), false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(registry, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    // Only the holder of the counter keypair may assign the authority
+    assert_eq!(
+        svm.process(&[migrate(false)], &[&payer]),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    svm.process(&[migrate(true)], &[&payer, &counter])
+        .expect("Migrate should succeed");
+
+    let account = svm.get_account(&counter.pubkey()).unwrap().clone();
+    assert_eq!(account.data.len(), CounterAccount::LEN);
+    assert_eq!(account.lamports, Rent::default().minimum_balance(CounterAccount::LEN));
+    let counter_data = read_counter(&svm, &counter.pubkey());
+    assert_eq!(counter_data.version(), CounterAccount::VERSION);
+    assert_eq!(counter_data.count(), 41);
+    assert_eq!(counter_data.authority, authority.pubkey());
+    assert_eq!(counter_data.rent_payer(), payer.pubkey());
+    assert_eq!(counter_data.overflow_mode(), Ok(OverflowMode::Checked));
+    assert_eq!(counter_data.timelock_delay(), 0);
+    let registry_data = RegistryAccount::try_from_slice(&svm.get_account(&registry).unwrap().data)
+        .expect("Failed to read registry");
+    assert_eq!(registry_data.counters, vec![counter.pubkey()]);
+
+    // Migrated counters work as usual and cannot be migrated twice
+    svm.process(&[increment], &[&authority]).expect("Increment should succeed");
+    assert_eq!(read_count(&svm, &counter.pubkey()), 42);
+    assert_eq!(
+        svm.process(&[migrate(true)], &[&payer, &counter]),
+        Err(ProgramError::InvalidAccountData)
+    );
+}
+
+#[test]
+fn test_prefunded_pdas() {
+    let (mut svm, program_id, payer) = setup();
+
+    // Lamports sent to a PDA address in advance must not block its creation,
+    // whether they fall short of the rent-exempt minimum or exceed it
+    svm.airdrop(&find_registry_address(&program_id, &payer.pubkey()).0, 1);
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let (roles, _) = find_roles_address(&program_id, &counter);
+    svm.airdrop(&roles, 10_000_000);
+
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::InitializeRoles,
+        vec![
+            AccountMeta::new_readonly(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(roles, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Initialize roles should succeed");
+
+    let roles_account = svm.get_account(&roles).expect("Failed to get roles account");
+    assert_eq!(roles_account.owner, program_id);
+    assert_eq!(roles_account.lamports, 10_000_000);
+    let registry = svm.get_account(&find_registry_address(&program_id, &payer.pubkey()).0).unwrap();
+    assert_eq!(registry.owner, program_id);
+    assert_eq!(
+        RegistryAccount::try_from_slice(&registry.data).unwrap().counters,
+        vec![counter]
+    );
+}
+
+#[test]
+fn test_history() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = Keypair::new();
+    let instruction = initialize_instruction(program_id, &payer, &counter, 10, 2);
+    svm.process(&[instruction], &[&payer, &counter])
+        .expect("Initialize with history should succeed");
+
+    // Three changes overflow a two-entry buffer, dropping the oldest
+    for (slot, instruction) in [
+        CounterInstruction::IncrementCounter { step: Some(5) },
+        CounterInstruction::DecrementCounter { step: Some(3) },
+        CounterInstruction::IncrementCounter { step: Some(1) },
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        svm.set_clock(Clock {
+            slot: slot as u64,
+            ..Clock::default()
+        });
+        let instruction = authority_instruction(program_id, instruction, counter.pubkey(), &payer);
+        svm.process(&[instruction], &[&payer]).expect("Counter update should succeed");
+    }
+
+    let account = svm.get_account(&counter.pubkey()).unwrap();
+    assert_eq!(account.data.len(), CounterAccount::LEN + history_space(2));
+    assert_eq!(read_count(&svm, &counter.pubkey()), 13);
+
+    let history = decode_history(&account.data).expect("Failed to decode history");
+    // Entry fields are unaligned, so they are copied out before comparing
+    let changes: Vec<(u64, i128, u64)> = history
+        .iter()
+        .map(|entry| (entry.slot, entry.delta, entry.resulting_value))
+        .collect();
+    assert_eq!(changes, vec![(1, -3, 12), (2, 1, 13)]);
+
+    let counter = Keypair::new();
+    let instruction = initialize_instruction(program_id, &payer, &counter, 0, MAX_HISTORY_CAPACITY + 1);
+    assert_eq!(
+        svm.process(&[instruction], &[&payer, &counter]),
+        Err(ProgramError::InvalidArgument)
+    );
+}
+
+#[test]
+fn test_config_and_pause() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let (config, _) = find_config_address(&program_id);
+    let admin = Keypair::new();
+    svm.airdrop(&admin.pubkey(), 1_000_000_000);
+
+    let config_instruction = |instruction: CounterInstruction, signer: &Keypair| {
+        counter_instruction(
+            &program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(config, false),
+                AccountMeta::new_readonly(signer.pubkey(), true),
+            ],
+        )
+    };
+    let increment = |step: Option<u64>| {
+        authority_instruction(program_id, CounterInstruction::IncrementCounter { step }, counter, &payer)
+    };
+
+    // A counter instruction with the wrong config account is rejected
+    let mut instruction = increment(None);
+    instruction.accounts.last_mut().unwrap().pubkey = Pubkey::new_unique();
+    assert_eq!(svm.process(&[instruction], &[&payer]), Err(ProgramError::InvalidSeeds));
+
+    // Only the upgrade authority recorded in the ProgramData account may initialize the config
+    let program_data = set_upgrade_authority(&mut svm, program_id, &admin.pubkey());
+    let initialize_config = |signer: &Keypair, program_data: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeConfig,
+            vec![
+                AccountMeta::new(config, false),
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new_readonly(program_data, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    };
+    assert_eq!(
+        svm.process(&[initialize_config(&payer, program_data)], &[&payer]),
+        Err(ProgramError::IncorrectAuthority)
+    );
+    assert_eq!(
+        svm.process(&[initialize_config(&admin, Pubkey::new_unique())], &[&admin]),
+        Err(ProgramError::InvalidSeeds)
+    );
+
+    // A truncated ProgramData account is rejected instead of read past its end
+    let program_data_account = svm.get_account(&program_data).unwrap().clone();
+    let mut truncated = program_data_account.clone();
+    truncated.data.truncate(20);
+    svm.set_account(program_data, truncated);
+    assert_eq!(
+        svm.process(&[initialize_config(&admin, program_data)], &[&admin]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    svm.set_account(program_data, program_data_account);
+    svm.process(&[initialize_config(&admin, program_data)], &[&admin])
+        .expect("Initialize config should succeed");
+
+    let update = |admin: &Keypair, default_step: u64| {
+        config_instruction(
+            CounterInstruction::UpdateConfig {
+                admin: admin.pubkey(),
+                default_step,
+                default_overflow_mode: OverflowMode::Saturating,
+            },
+            admin,
+        )
+    };
+    assert!(svm.process(&[update(&payer, 2)], &[&payer]).is_err(), "Only the admin may update");
+    assert!(svm.process(&[update(&admin, 0)], &[&admin]).is_err(), "A zero step is rejected");
+    svm.process(&[update(&admin, 2)], &[&admin]).expect("Update config should succeed");
+
+    // Existing counters keep their overflow mode but use the new default step
+    svm.process(&[increment(None)], &[&payer]).expect("Increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 12);
+    let saturating = initialize_counter(&mut svm, program_id, &payer, u64::MAX - 1);
+    assert_eq!(read_counter(&svm, &saturating).overflow_mode(), Ok(OverflowMode::Saturating));
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        saturating,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Saturating increment should succeed");
+    assert_eq!(read_count(&svm, &saturating), u64::MAX);
+
+    // Every counter instruction is refused while paused
+    let set_paused = |paused: bool| config_instruction(CounterInstruction::SetPaused { paused }, &admin);
+    svm.process(&[set_paused(true)], &[&admin]).expect("Pause should succeed");
+    assert_eq!(
+        svm.process(&[increment(None)], &[&payer]),
+        Err(CounterError::ProgramPaused.into())
+    );
+    let counter_keypair = Keypair::new();
+    let instruction = initialize_instruction(program_id, &payer, &counter_keypair, 0, 0);
+    assert_eq!(
+        svm.process(&[instruction], &[&payer, &counter_keypair]),
+        Err(CounterError::ProgramPaused.into())
+    );
+
+    svm.process(&[set_paused(false)], &[&admin]).expect("Unpause should succeed");
+    svm.process(&[increment(Some(1))], &[&payer]).expect("Increment after unpause should succeed");
+    assert_eq!(read_count(&svm, &counter), 13);
+}
+
+#[test]
+fn test_increment_fee() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let treasury = Pubkey::new_unique();
+    svm.airdrop(&treasury, 1_000_000);
+
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetIncrementFee {
+            fee_lamports: 5_000,
+            treasury,
+        },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Setting the fee should succeed");
+    let counter_data = read_counter(&svm, &counter);
+    assert_eq!(counter_data.fee_lamports(), 5_000);
+    assert_eq!(counter_data.treasury(), treasury);
+
+    let fee_payer = Keypair::new();
+    svm.airdrop(&fee_payer.pubkey(), 1_000_000);
+    let increment = |treasury: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(fee_payer.pubkey(), true),
+                AccountMeta::new(treasury, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    svm.process(&[increment(treasury)], &[&payer, &fee_payer])
+        .expect("Increment with fee should succeed");
+    assert_eq!(read_count(&svm, &counter), 1);
+    assert_eq!(svm.get_account(&treasury).unwrap().lamports, 1_005_000);
+    assert_eq!(svm.get_account(&fee_payer.pubkey()).unwrap().lamports, 995_000);
+
+    // The fee must go to the configured treasury
+    assert_eq!(
+        svm.process(&[increment(Pubkey::new_unique())], &[&payer, &fee_payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    // The fee accounts are required once a fee is set
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        counter,
+        &payer,
+    );
+    assert!(svm.process(&[instruction], &[&payer]).is_err());
+    assert_eq!(read_count(&svm, &counter), 1);
+
+    // Clearing the fee restores the plain account list
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetIncrementFee {
+            fee_lamports: 0,
+            treasury: Pubkey::default(),
+        },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Clearing the fee should succeed");
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Free increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 2);
+}
+
+/// Store an initialized SPL token account of `mint` owned by `owner`
+fn token_account(svm: &mut NativeSvm, owner: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+    let token = spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: spl_token::state::AccountState::Initialized,
+        ..spl_token::state::Account::default()
+    };
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account::pack(token, &mut data).expect("Failed to pack token account");
+
+    let address = Pubkey::new_unique();
+    svm.set_account(
+        address,
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        },
+    );
+    address
+}
+
+#[test]
+fn test_token_gate() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let (roles, _) = find_roles_address(&program_id, &counter);
+    let mint = Pubkey::new_unique();
+
+    let instructions = [
+        authority_instruction(
+            program_id,
+            CounterInstruction::SetTokenGate { mint, min_balance: 2 },
+            counter,
+            &payer,
+        ),
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeRoles,
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(roles, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                config_meta(&program_id),
+            ],
+        ),
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::SetRolePublic {
+                role: Role::Incrementer,
+                public: true,
+            },
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(roles, false),
+                config_meta(&program_id),
+            ],
+        ),
+    ];
+    svm.process(&instructions, &[&payer]).expect("Gate setup should succeed");
+    let counter_data = read_counter(&svm, &counter);
+    assert_eq!(counter_data.gate_mint(), mint);
+    assert_eq!(counter_data.gate_min_balance(), 2);
+
+    let holder = Keypair::new();
+    let increment = |signer: &Keypair, token_account: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: None },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(token_account, false),
+                AccountMeta::new_readonly(roles, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    let holder_tokens = token_account(&mut svm, &holder.pubkey(), &mint, 2);
+    svm.process(&[increment(&holder, holder_tokens)], &[&holder])
+        .expect("Holder increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+
+    // Too small a balance, another mint or someone else's tokens are all rejected
+    let visitor = Keypair::new();
+    for token_account in [
+        token_account(&mut svm, &visitor.pubkey(), &mint, 1),
+        token_account(&mut svm, &visitor.pubkey(), &Pubkey::new_unique(), 5),
+        holder_tokens,
+    ] {
+        assert_eq!(
+            svm.process(&[increment(&visitor, token_account)], &[&visitor]),
+            Err(CounterError::MissingGateToken.into())
+        );
+    }
+
+    // The token account must belong to the token program
+    let forged = Pubkey::new_unique();
+    let mut account = svm.get_account(&holder_tokens).unwrap().clone();
+    account.owner = program_id;
+    svm.set_account(forged, account);
+    assert_eq!(
+        svm.process(&[increment(&holder, forged)], &[&holder]),
+        Err(ProgramError::IncorrectProgramId)
+    );
+
+    // The authority is gated as well
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::DecrementCounter { step: None },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(token_account(&mut svm, &payer.pubkey(), &mint, 0), false),
+            config_meta(&program_id),
+        ],
+    );
+    assert_eq!(
+        svm.process(&[instruction], &[&payer]),
+        Err(CounterError::MissingGateToken.into())
+    );
+    assert_eq!(read_count(&svm, &counter), 11);
+}
+
+/// Store an initialized SPL token mint with `supply` tokens outstanding
+fn token_mint(svm: &mut NativeSvm, mint_authority: Option<Pubkey>, supply: u64) -> Pubkey {
+    let mint = spl_token::state::Mint {
+        mint_authority: mint_authority.into(),
+        supply,
+        is_initialized: true,
+        ..spl_token::state::Mint::default()
+    };
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut data).expect("Failed to pack mint");
+
+    let address = Pubkey::new_unique();
+    svm.set_account(
+        address,
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        },
+    );
+    address
+}
+
+fn token_balance(svm: &NativeSvm, token_account: &Pubkey) -> u64 {
+    let account = svm.get_account(token_account).expect("Failed to get token account");
+    spl_token::state::Account::unpack(&account.data)
+        .expect("Failed to read token account")
+        .amount
+}
+
+#[test]
+fn test_token_payment() {
+    let (mut svm, program_id, payer) = setup();
+    svm.add_program(spl_token::id(), spl_token::processor::Processor::process);
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let mint = token_mint(&mut svm, None, 100);
+    let source = token_account(&mut svm, &payer.pubkey(), &mint, 100);
+    let treasury = token_account(&mut svm, &Pubkey::new_unique(), &mint, 0);
+
+    let set_payment = |mode: TokenPaymentMode| {
+        authority_instruction(
+            program_id,
+            CounterInstruction::SetTokenPayment {
+                mint,
+                amount_per_step: 3,
+                mode,
+                destination: treasury,
+            },
+            counter,
+            &payer,
+        )
+    };
+    let increment = |step: u64, destination: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::IncrementCounter { step: Some(step) },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(source, false),
+                AccountMeta::new(destination, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    // Each unit of step moves the configured amount to the destination
+    svm.process(&[set_payment(TokenPaymentMode::Transfer)], &[&payer])
+        .expect("Setting the payment should succeed");
+    svm.process(&[increment(4, treasury)], &[&payer])
+        .expect("Paid increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 4);
+    assert_eq!(token_balance(&svm, &source), 88);
+    assert_eq!(token_balance(&svm, &treasury), 12);
+
+    let other_account = token_account(&mut svm, &payer.pubkey(), &mint, 0);
+    assert_eq!(
+        svm.process(&[increment(1, other_account)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    // An increment the caller cannot pay for is rejected as a whole
+    assert!(svm.process(&[increment(30, treasury)], &[&payer]).is_err());
+    assert_eq!(read_count(&svm, &counter), 4);
+    assert_eq!(token_balance(&svm, &source), 88);
+
+    // Burning takes the tokens out of the supply
+    svm.process(&[set_payment(TokenPaymentMode::Burn)], &[&payer])
+        .expect("Switching to burn should succeed");
+    svm.process(&[increment(2, mint)], &[&payer])
+        .expect("Burning increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 6);
+    assert_eq!(token_balance(&svm, &source), 82);
+    let mint_account = svm.get_account(&mint).expect("Failed to get mint");
+    let supply = spl_token::state::Mint::unpack(&mint_account.data)
+        .expect("Failed to read mint")
+        .supply;
+    assert_eq!(supply, 94);
+    assert_eq!(token_balance(&svm, &treasury), 12);
+}
+
+#[test]
+fn test_tokenized_units() {
+    let (mut svm, program_id, payer) = setup();
+    svm.add_program(spl_token::id(), spl_token::processor::Processor::process);
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let (mint_authority, _) = find_mint_authority_address(&program_id, &counter);
+    let mint = token_mint(&mut svm, Some(mint_authority), 0);
+    let holder = Keypair::new();
+    let holder_units = token_account(&mut svm, &holder.pubkey(), &mint, 0);
+
+    let set_units_mint = |mint_account: Pubkey| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::SetUnitsMint { mint: mint_account },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(mint_account, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+
+    // The mint has to be controlled by the counter's mint authority PDA
+    let foreign_mint = token_mint(&mut svm, Some(payer.pubkey()), 0);
+    assert_eq!(
+        svm.process(&[set_units_mint(foreign_mint)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    svm.process(&[set_units_mint(mint)], &[&payer])
+        .expect("Tokenizing the counter should succeed");
+    assert_eq!(read_counter(&svm, &counter).units_mint(), mint);
+
+    let mint_supply = |svm: &NativeSvm| {
+        let account = svm.get_account(&mint).expect("Failed to get mint");
+        spl_token::state::Mint::unpack(&account.data)
+            .expect("Failed to read mint")
+            .supply
+    };
+
+    // Increments mint units to any destination
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::IncrementCounter { step: Some(5) },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(mint, false),
+            AccountMeta::new(holder_units, false),
+            AccountMeta::new_readonly(mint_authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Minting increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 5);
+    assert_eq!(token_balance(&svm, &holder_units), 5);
+    assert_eq!(mint_supply(&svm), 5);
+
+    // Decrements burn units from the signer, who needs the decrementer role
+    let (roles, _) = find_roles_address(&program_id, &counter);
+    let instructions = [
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::InitializeRoles,
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(roles, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                config_meta(&program_id),
+            ],
+        ),
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::SetRolePublic {
+                role: Role::Decrementer,
+                public: true,
+            },
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(roles, false),
+                config_meta(&program_id),
+            ],
+        ),
+    ];
+    svm.process(&instructions, &[&payer]).expect("Role setup should succeed");
+
+    let decrement = |step: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::DecrementCounter { step: Some(step) },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(holder.pubkey(), true),
+                AccountMeta::new(holder_units, false),
+                AccountMeta::new(mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(roles, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    svm.process(&[decrement(2)], &[&holder]).expect("Burning decrement should succeed");
+    assert_eq!(read_count(&svm, &counter), 3);
+    assert_eq!(token_balance(&svm, &holder_units), 3);
+    assert_eq!(mint_supply(&svm), 3);
+
+    assert!(
+        svm.process(&[decrement(4)], &[&holder]).is_err(),
+        "Burning more units than held should fail"
+    );
+    assert_eq!(read_count(&svm, &counter), 3);
+
+    // Instructions that would bypass the mint are refused
+    let instruction = authority_instruction(program_id, CounterInstruction::ResetCounter, counter, &payer);
+    assert_eq!(
+        svm.process(&[instruction], &[&payer]),
+        Err(CounterError::CounterTokenized.into())
+    );
+    assert_eq!(mint_supply(&svm), read_count(&svm, &counter));
+}
+
+thread_local! {
+    /// Count seen and instruction data received by `record_trigger`
+    static FIRED_TRIGGERS: std::cell::RefCell<Vec<(u64, Vec<u8>)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Trigger target that records the counter value it was invoked with
+fn record_trigger(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let count = CounterAccount::load(&accounts[0].data.borrow())?.count();
+    FIRED_TRIGGERS.with(|fired| fired.borrow_mut().push((count, data.to_vec())));
+    Ok(())
+}
+
+#[test]
+fn test_triggers() {
+    let (mut svm, program_id, payer) = setup();
+    let target = Pubkey::new_unique();
+    svm.add_program(target, record_trigger);
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let set_trigger = |index: u8, threshold: u64, data: Vec<u8>| {
+        authority_instruction(
+            program_id,
+            CounterInstruction::SetTrigger {
+                index,
+                threshold,
+                program: target,
+                data,
+            },
+            counter,
+            &payer,
+        )
+    };
+    svm.process(&[set_trigger(0, 10, vec![1]), set_trigger(2, 12, vec![2, 2])], &[&payer])
+        .expect("Setting triggers should succeed");
+    assert_eq!(
+        svm.process(&[set_trigger(0, 10, vec![0; MAX_TRIGGER_DATA_LEN + 1])], &[&payer]),
+        Err(ProgramError::InvalidInstructionData)
+    );
+    assert_eq!(
+        svm.process(&[set_trigger(MAX_TRIGGERS as u8, 10, vec![])], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    let increment = |step: u64, trigger_programs: usize| {
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+        ];
+        accounts.extend((0..trigger_programs).map(|_| AccountMeta::new_readonly(target, false)));
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &CounterInstruction::IncrementCounter { step: Some(step) }, accounts)
+    };
+
+    // Every armed trigger needs its program account, even when it does not fire
+    assert!(svm.process(&[increment(5, 1)], &[&payer]).is_err());
+    svm.process(&[increment(5, 2)], &[&payer]).expect("Increment below the thresholds should succeed");
+    assert!(FIRED_TRIGGERS.with(|fired| fired.borrow().is_empty()));
+
+    // Crossing both thresholds at once fires both, after the count is updated
+    svm.set_clock(Clock {
+        slot: 7,
+        ..Clock::default()
+    });
+    svm.process(&[increment(10, 2)], &[&payer]).expect("Increment across the thresholds should succeed");
+    assert_eq!(
+        FIRED_TRIGGERS.with(|fired| fired.borrow().clone()),
+        vec![(15, vec![1]), (15, vec![2, 2])]
+    );
+    let triggers = *read_counter(&svm, &counter).triggers();
+    assert!(triggers[0].has_fired() && triggers[2].has_fired());
+    assert_eq!({ triggers[0].fired_slot }, 7);
+
+    // Fired triggers are not armed anymore and never fire again
+    svm.process(&[increment(1, 0)], &[&payer]).expect("Increment after firing should succeed");
+    assert_eq!(FIRED_TRIGGERS.with(|fired| fired.borrow().len()), 2);
+
+    // Batches, transfers and scheduled sets fire triggers like increments
+    let armed_counter = |svm: &mut NativeSvm, data: u8| {
+        let counter = initialize_counter(svm, program_id, &payer, 0);
+        let instruction = authority_instruction(
+            program_id,
+            CounterInstruction::SetTrigger {
+                index: 0,
+                threshold: 3,
+                program: target,
+                data: vec![data],
+            },
+            counter,
+            &payer,
+        );
+        svm.process(&[instruction], &[&payer]).expect("Setting the trigger should succeed");
+        counter
+    };
+    let fired_since = |start: usize| FIRED_TRIGGERS.with(|fired| fired.borrow()[start..].to_vec());
+
+    // Crossing the threshold twice in one batch fires once
+    let batched = armed_counter(&mut svm, 3);
+    let start = FIRED_TRIGGERS.with(|fired| fired.borrow().len());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::Batch {
+            ops: vec![
+                (0, CounterOp::Increment { step: Some(5) }),
+                (0, CounterOp::Decrement { step: Some(4) }),
+                (0, CounterOp::Set { value: 9 }),
+            ],
+        },
+        vec![
+            AccountMeta::new(batched, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(target, false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Batch across the threshold should succeed");
+    assert_eq!(fired_since(start), vec![(5, vec![3])]);
+
+    let transferred = armed_counter(&mut svm, 4);
+    let start = FIRED_TRIGGERS.with(|fired| fired.borrow().len());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::TransferBetweenCounters { amount: 3 },
+        vec![
+            AccountMeta::new(batched, false),
+            AccountMeta::new(transferred, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(target, false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Transfer across the threshold should succeed");
+    assert_eq!(fired_since(start), vec![(3, vec![4])]);
+
+    let scheduled_counter = armed_counter(&mut svm, 5);
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetTimelock { delay: 1 },
+        scheduled_counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Setting the timelock should succeed");
+    let scheduled = find_scheduled_change_address(&program_id, &scheduled_counter).0;
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ScheduleChange {
+            action: ScheduledAction::SetCounter { value: 30 },
+        },
+        vec![
+            AccountMeta::new_readonly(scheduled_counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Scheduling a change should succeed");
+    svm.set_clock(Clock {
+        slot: 8,
+        unix_timestamp: 1,
+        ..Clock::default()
+    });
+    let start = FIRED_TRIGGERS.with(|fired| fired.borrow().len());
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ExecuteChange,
+        vec![
+            AccountMeta::new(scheduled_counter, false),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new_readonly(target, false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[]).expect("Executing the change should succeed");
+    assert_eq!(fired_since(start), vec![(30, vec![5])]);
+}
+
+thread_local! {
+    /// Phase, values and counter value seen by `limit_hook`
+    static HOOK_CALLS: std::cell::RefCell<Vec<(HookPhase, u64, u64, u64)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Hook that refuses values above the little-endian limit stored in its first extra account
+fn limit_hook(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let HookInstruction::Execute {
+        phase,
+        old_value,
+        new_value,
+        ..
+    } = HookInstruction::try_from_slice(data)?;
+    let count = CounterAccount::load(&accounts[0].data.borrow())?.count();
+    HOOK_CALLS.with(|calls| calls.borrow_mut().push((phase, old_value, new_value, count)));
+
+    let limit = u64::from_le_bytes(accounts[3].data.borrow()[..8].try_into().unwrap());
+    if new_value > limit {
+        return Err(ProgramError::Custom(42));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hooks() {
+    let (mut svm, program_id, payer) = setup();
+    let hook = Pubkey::new_unique();
+    svm.add_program(hook, limit_hook);
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let limit = Pubkey::new_unique();
+    svm.set_account(
+        limit,
+        Account {
+            lamports: 1_000_000,
+            data: 5u64.to_le_bytes().to_vec(),
+            owner: hook,
+            ..Account::default()
+        },
+    );
+    let (extra_account_metas, _) = find_extra_account_metas_address(&hook, &counter);
+    let list = ExtraAccountMetaList {
+        accounts: vec![ExtraAccountMeta {
+            pubkey: limit,
+            is_signer: false,
+            is_writable: false,
+        }],
+    };
+    svm.set_account(
+        extra_account_metas,
+        Account {
+            lamports: 1_000_000,
+            data: borsh::to_vec(&list).unwrap(),
+            owner: hook,
+            ..Account::default()
+        },
+    );
+
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetHook {
+            program: hook,
+            pre: true,
+            post: true,
+        },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Setting the hook should succeed");
+
+    // Clients resolve the hook accounts from the on-chain list
+    let mutation = |svm: &NativeSvm, instruction: CounterInstruction| {
+        let counter_data = read_counter(svm, &counter);
+        let hook_program = counter_data.hook_program();
+        let (list_address, _) = find_extra_account_metas_address(&hook_program, &counter);
+        let list_account = svm.get_account(&list_address).expect("Failed to get extra account list");
+        let list = ExtraAccountMetaList::try_from_slice(&list_account.data).expect("Failed to read extra account list");
+
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+            AccountMeta::new_readonly(hook_program, false),
+            AccountMeta::new_readonly(list_address, false),
+        ];
+        accounts.extend(list.accounts.iter().map(AccountMeta::from));
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &instruction, accounts)
+    };
+
+    svm.process(&[mutation(&svm, CounterInstruction::IncrementCounter { step: Some(3) })], &[&payer])
+        .expect("Increment within the limit should succeed");
+    svm.process(&[mutation(&svm, CounterInstruction::DecrementCounter { step: Some(1) })], &[&payer])
+        .expect("Decrement within the limit should succeed");
+    assert_eq!(read_count(&svm, &counter), 2);
+    assert_eq!(
+        HOOK_CALLS.with(|calls| calls.borrow().clone()),
+        vec![
+            (HookPhase::Pre, 0, 3, 0),
+            (HookPhase::Post, 0, 3, 3),
+            (HookPhase::Pre, 3, 2, 3),
+            (HookPhase::Post, 3, 2, 2),
+        ]
+    );
+
+    // A hook error aborts the change
+    assert_eq!(
+        svm.process(&[mutation(&svm, CounterInstruction::IncrementCounter { step: Some(4) })], &[&payer]),
+        Err(ProgramError::Custom(42))
+    );
+    assert_eq!(read_count(&svm, &counter), 2);
+
+    // The extra accounts must match the list
+    let mut instruction = mutation(&svm, CounterInstruction::IncrementCounter { step: None });
+    instruction.accounts[4] = AccountMeta::new_readonly(Pubkey::new_unique(), false);
+    assert_eq!(svm.process(&[instruction], &[&payer]), Err(ProgramError::InvalidArgument));
+
+    // Batches run the hooks around every operation
+    HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+    let batch = |ops: Vec<(u8, CounterOp)>| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::Batch { ops },
+            vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(hook, false),
+                AccountMeta::new_readonly(extra_account_metas, false),
+                AccountMeta::new_readonly(limit, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    svm.process(
+        &[batch(vec![
+            (0, CounterOp::Increment { step: Some(1) }),
+            (0, CounterOp::Decrement { step: Some(2) }),
+        ])],
+        &[&payer],
+    )
+    .expect("Batch within the limit should succeed");
+    assert_eq!(read_count(&svm, &counter), 1);
+    assert_eq!(
+        HOOK_CALLS.with(|calls| calls.borrow().clone()),
+        vec![
+            (HookPhase::Pre, 2, 3, 2),
+            (HookPhase::Post, 2, 3, 3),
+            (HookPhase::Pre, 3, 1, 3),
+            (HookPhase::Post, 3, 1, 1),
+        ]
+    );
+    assert_eq!(
+        svm.process(&[batch(vec![(0, CounterOp::Set { value: 6 })])], &[&payer]),
+        Err(ProgramError::Custom(42))
+    );
+
+    // Transfers run the hook of the destination like an increment
+    HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+    let source = initialize_counter(&mut svm, program_id, &payer, 10);
+    let transfer = |amount: u64| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::TransferBetweenCounters { amount },
+            vec![
+                AccountMeta::new(source, false),
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new_readonly(hook, false),
+                AccountMeta::new_readonly(extra_account_metas, false),
+                AccountMeta::new_readonly(limit, false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    svm.process(&[transfer(2)], &[&payer]).expect("Transfer within the limit should succeed");
+    assert_eq!(
+        HOOK_CALLS.with(|calls| calls.borrow().clone()),
+        vec![(HookPhase::Pre, 1, 3, 1), (HookPhase::Post, 1, 3, 3)]
+    );
+    assert_eq!(
+        svm.process(&[transfer(3)], &[&payer]),
+        Err(ProgramError::Custom(42))
+    );
+    assert_eq!(read_count(&svm, &source), 8);
+}
+
+#[test]
+fn test_expiry() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    assert_eq!(read_counter(&svm, &counter).rent_payer(), payer.pubkey());
+    let counter_rent = svm.get_account(&counter).unwrap().lamports;
+
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetExpiry { expires_at: 1_000 },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Setting the expiry should succeed");
+    assert_eq!(read_counter(&svm, &counter).expires_at(), 1_000);
+
+    // An outstanding delegate record is closed along with the counter
+    let delegate = Pubkey::new_unique();
+    let (delegate_record, _) = find_delegate_address(&program_id, &counter, &delegate);
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::ApproveDelegate {
+            increment_allowance: 1,
+            decrement_allowance: 0,
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(delegate, false),
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Approve should succeed");
+
+    // Anyone may close, but only after expiry and only to the rent payer
+    let close_with = |rent_payer: Pubkey, delegate_records: &[Pubkey]| {
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(rent_payer, false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_roles_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_scheduled_change_address(&program_id, &counter).0, false),
+        ];
+        accounts.extend(delegate_records.iter().map(|record| AccountMeta::new(*record, false)));
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &CounterInstruction::CloseExpiredCounter, accounts)
+    };
+    let close = |rent_payer: Pubkey| close_with(rent_payer, &[delegate_record]);
+    let increment = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: None },
+        counter,
+        &payer,
+    );
+    svm.set_clock(Clock {
+        unix_timestamp: 999,
+        ..Clock::default()
+    });
+    assert_eq!(
+        svm.process(&[close(payer.pubkey())], &[]),
+        Err(CounterError::CounterNotExpired.into())
+    );
+    svm.process(std::slice::from_ref(&increment), &[&payer])
+        .expect("Increment before expiry should succeed");
+
+    svm.set_clock(Clock {
+        unix_timestamp: 1_000,
+        ..Clock::default()
+    });
+    assert_eq!(
+        svm.process(&[increment], &[&payer]),
+        Err(CounterError::CounterExpired.into())
+    );
+    assert_eq!(read_count(&svm, &counter), 11);
+    assert_eq!(
+        svm.process(&[close(Pubkey::new_unique())], &[]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    assert_eq!(
+        svm.process(&[close_with(payer.pubkey(), &[])], &[]),
+        Err(CounterError::DelegatesOutstanding.into())
+    );
+
+    let payer_lamports = svm.get_account(&payer.pubkey()).unwrap().lamports;
+    svm.process(&[close(payer.pubkey())], &[]).expect("Closing an expired counter should succeed");
+    assert!(svm.get_account(&counter).is_none());
+    assert!(svm.get_account(&delegate_record).is_none());
+    assert!(svm.get_account(&payer.pubkey()).unwrap().lamports >= payer_lamports + counter_rent);
+
+    let registry = svm
+        .get_account(&find_registry_address(&program_id, &payer.pubkey()).0)
+        .expect("Failed to get registry");
+    let registry = RegistryAccount::try_from_slice(&registry.data).expect("Failed to read registry");
+    assert!(!registry.counters.contains(&counter));
+}
+
+#[test]
+fn test_timelock() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 10);
+    let set_timelock = |delay: i64| {
+        authority_instruction(program_id, CounterInstruction::SetTimelock { delay }, counter, &payer)
+    };
+    // Delays above the maximum would overflow every schedule and are rejected
+    assert_eq!(
+        svm.process(&[set_timelock(MAX_TIMELOCK_DELAY + 1)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        svm.process(&[set_timelock(i64::MAX)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    svm.process(&[set_timelock(100)], &[&payer]).expect("Setting the timelock should succeed");
+    assert_eq!(read_counter(&svm, &counter).timelock_delay(), 100);
+
+    // Direct resets, authority decrements and batches, settings changes, closing and
+    // delay decreases now have to be scheduled
+    for instruction in [
+        CounterInstruction::ResetCounter,
+        CounterInstruction::DecrementCounter { step: Some(10) },
+        CounterInstruction::Batch {
+            ops: vec![(0, CounterOp::Increment { step: Some(1) })],
+        },
+        CounterInstruction::ThawCounter,
+        CounterInstruction::SetExpiry { expires_at: 1 },
+        CounterInstruction::SetHook {
+            program: Pubkey::new_unique(),
+            pre: true,
+            post: false,
+        },
+        CounterInstruction::SetTrigger {
+            index: 0,
+            threshold: 20,
+            program: Pubkey::new_unique(),
+            data: vec![],
+        },
+        CounterInstruction::SetTimelock { delay: 50 },
+    ] {
+        assert_eq!(
+            svm.process(&[authority_instruction(program_id, instruction, counter, &payer)], &[&payer]),
+            Err(CounterError::TimelockRequired.into())
+        );
+    }
+    assert_eq!(
+        svm.process(&[close_instruction(program_id, counter, &payer)], &[&payer]),
+        Err(CounterError::TimelockRequired.into())
+    );
+    assert_eq!(read_count(&svm, &counter), 10);
+
+    // Checked increments cannot land on an arbitrary value and stay direct
+    let increment = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: Some(1) },
+        counter,
+        &payer,
+    );
+    svm.process(&[increment], &[&payer]).expect("Increment should succeed");
+    assert_eq!(read_count(&svm, &counter), 11);
+
+    let scheduled = find_scheduled_change_address(&program_id, &counter).0;
+    let schedule = |action: ScheduledAction| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::ScheduleChange { action },
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new_readonly(payer.pubkey(), true),
+                AccountMeta::new(scheduled, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    let execute_with = |action_accounts: Vec<AccountMeta>| {
+        let mut accounts = vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(scheduled, false),
+            AccountMeta::new(payer.pubkey(), false),
+        ];
+        accounts.extend(action_accounts);
+        accounts.push(config_meta(&program_id));
+        counter_instruction(&program_id, &CounterInstruction::ExecuteChange, accounts)
+    };
+    let execute = execute_with(vec![]);
+
+    svm.set_clock(Clock {
+        unix_timestamp: 1_000,
+        ..Clock::default()
+    });
+    svm.process(&[schedule(ScheduledAction::SetCounter { value: 77 })], &[&payer])
+        .expect("Scheduling a change should succeed");
+    let scheduled_data = ScheduledChangeAccount::try_from_slice(&svm.get_account(&scheduled).unwrap().data)
+        .expect("Failed to read scheduled change");
+    assert_eq!(scheduled_data.execute_after, 1_100);
+    assert!(
+        svm.process(&[schedule(ScheduledAction::Reset)], &[&payer]).is_err(),
+        "Only one change may be pending"
+    );
+
+    svm.set_clock(Clock {
+        unix_timestamp: 1_099,
+        ..Clock::default()
+    });
+    assert_eq!(
+        svm.process(std::slice::from_ref(&execute), &[]),
+        Err(CounterError::TimelockPending.into())
+    );
+    assert_eq!(read_count(&svm, &counter), 11);
+
+    // Anyone may execute once the delay has passed
+    svm.set_clock(Clock {
+        unix_timestamp: 1_100,
+        ..Clock::default()
+    });
+    svm.process(std::slice::from_ref(&execute), &[]).expect("Executing the change should succeed");
+    assert_eq!(read_count(&svm, &counter), 77);
+    assert!(svm.get_account(&scheduled).is_none());
+
+    // The authority may cancel a pending change
+    svm.process(&[schedule(ScheduledAction::SetTimelock { delay: 0 })], &[&payer])
+        .expect("Scheduling a change should succeed");
+    let cancel = |authority: &Keypair| {
+        counter_instruction(
+            &program_id,
+            &CounterInstruction::CancelChange,
+            vec![
+                AccountMeta::new_readonly(counter, false),
+                AccountMeta::new_readonly(authority.pubkey(), true),
+                AccountMeta::new(scheduled, false),
+                AccountMeta::new(payer.pubkey(), false),
+                config_meta(&program_id),
+            ],
+        )
+    };
+    let stranger = Keypair::new();
+    assert!(svm.process(&[cancel(&stranger)], &[&stranger]).is_err());
+    svm.process(&[cancel(&payer)], &[&payer]).expect("Cancelling the change should succeed");
+    assert!(svm.get_account(&scheduled).is_none());
+    assert!(svm.process(&[execute], &[]).is_err());
+    assert_eq!(read_counter(&svm, &counter).timelock_delay(), 100);
+
+    // Actions that could never execute are rejected when scheduled
+    let invalid_trigger = ScheduledAction::SetTrigger {
+        index: MAX_TRIGGERS as u8,
+        threshold: 20,
+        program: Pubkey::new_unique(),
+        data: vec![],
+    };
+    assert_eq!(
+        svm.process(&[schedule(invalid_trigger)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    // Actions that create accounts take a payer from the executor
+    let executor = Keypair::new();
+    svm.airdrop(&executor.pubkey(), 1_000_000_000);
+    let delegate = Pubkey::new_unique();
+    let (delegate_record, _) = find_delegate_address(&program_id, &counter, &delegate);
+    let mut now = 1_100;
+    let mut run_scheduled = |svm: &mut NativeSvm, action: ScheduledAction, accounts: Vec<AccountMeta>| {
+        svm.process(&[schedule(action)], &[&payer]).expect("Scheduling a change should succeed");
+        now += 100;
+        svm.set_clock(Clock {
+            unix_timestamp: now,
+            ..Clock::default()
+        });
+        svm.process(&[execute_with(accounts)], &[&executor])
+    };
+    run_scheduled(
+        &mut svm,
+        ScheduledAction::ApproveDelegate {
+            delegate,
+            increment_allowance: 5,
+            decrement_allowance: 0,
+        },
+        vec![
+            AccountMeta::new(delegate_record, false),
+            AccountMeta::new(executor.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+    .expect("Executing the approval should succeed");
+    assert!(svm.get_account(&delegate_record).is_some());
+    assert_eq!(read_counter(&svm, &counter).delegate_count(), 1);
+
+    run_scheduled(
+        &mut svm,
+        ScheduledAction::RevokeDelegate { delegate },
+        vec![
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new(delegate_record, false),
+        ],
+    )
+    .expect("Executing the revocation should succeed");
+    assert!(svm.get_account(&delegate_record).is_none());
+    assert_eq!(read_counter(&svm, &counter).delegate_count(), 0);
+
+    // Freezing only restricts the counter and works at once, thawing waits out the delay
+    svm.process(
+        &[authority_instruction(program_id, CounterInstruction::FreezeCounter, counter, &payer)],
+        &[&payer],
+    )
+    .expect("Freezing a timelocked counter should succeed");
+    assert!(read_counter(&svm, &counter).is_frozen());
+    assert_eq!(
+        svm.process(
+            &[authority_instruction(program_id, CounterInstruction::ThawCounter, counter, &payer)],
+            &[&payer],
+        ),
+        Err(CounterError::TimelockRequired.into())
+    );
+    run_scheduled(&mut svm, ScheduledAction::Thaw, vec![]).expect("Executing the thaw should succeed");
+    assert!(!read_counter(&svm, &counter).is_frozen());
+
+    run_scheduled(
+        &mut svm,
+        ScheduledAction::Close {
+            destination: payer.pubkey(),
+        },
+        vec![
+            AccountMeta::new(payer.pubkey(), false),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_roles_address(&program_id, &counter).0, false),
+            AccountMeta::new(find_metadata_address(&program_id, &counter).0, false),
+        ],
+    )
+    .expect("Executing the close should succeed");
+    assert!(svm.get_account(&counter).is_none());
+    assert!(svm.get_account(&scheduled).is_none());
+}
+
+#[test]
+fn test_instruction_encodings() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let accounts = vec![
+        AccountMeta::new(counter, false),
+        AccountMeta::new_readonly(payer.pubkey(), true),
+        config_meta(&program_id),
+    ];
+
+    // Discriminator and legacy encodings are both accepted
+    let increment = CounterInstruction::IncrementCounter { step: Some(2) };
+    let legacy = borsh::to_vec(&increment).expect("Failed to serialize instruction");
+    for data in [increment.pack(), legacy] {
+        let instruction = Instruction::new_with_bytes(program_id, &data, accounts.clone());
+        svm.process(&[instruction], &[&payer]).expect("Increment should succeed");
+    }
+    assert_eq!(read_count(&svm, &counter), 4);
+
+    let mut data = increment.pack();
+    data[7] ^= 1;
+    let instruction = Instruction::new_with_bytes(program_id, &data, accounts);
+    assert_eq!(
+        svm.process(&[instruction], &[&payer]),
+        Err(ProgramError::InvalidInstructionData)
+    );
+}
+
+#[test]
+fn test_first_release_clients() {
+    let (mut svm, program_id, payer) = setup();
+
+    // Instruction data and account lists exactly as clients of the first release build them
+    let counter = Keypair::new();
+    let initialize = Instruction::new_with_bytes(
+        program_id,
+        &[0, 10, 0, 0, 0, 0, 0, 0, 0],
+        vec![
+            AccountMeta::new(counter.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    svm.process(&[initialize], &[&payer, &counter])
+        .expect("Initialize should succeed");
+    let account = svm.get_account(&counter.pubkey()).unwrap();
+    assert_eq!(account.data, 10u64.to_le_bytes());
+    assert_eq!(account.owner, program_id);
+
+    let step = |data: &[u8]| {
+        Instruction::new_with_bytes(program_id, data, vec![AccountMeta::new(counter.pubkey(), false)])
+    };
+    let read_baseline = |svm: &NativeSvm| {
+        u64::from_le_bytes(svm.get_account(&counter.pubkey()).unwrap().data[..].try_into().unwrap())
+    };
+
+    // Anyone may step a baseline counter, by 1 unless a step is given
+    svm.process(&[step(&[1, 0])], &[&payer]).expect("Increment should succeed");
+    assert_eq!(read_baseline(&svm), 11);
+    svm.process(&[step(&[1, 1, 5, 0, 0, 0, 0, 0, 0, 0])], &[&payer])
+        .expect("Increment should succeed");
+    assert_eq!(read_baseline(&svm), 16);
+    svm.process(&[step(&[2, 0])], &[&payer]).expect("Decrement should succeed");
+    assert_eq!(read_baseline(&svm), 15);
+    svm.process(&[step(&[2, 1, 15, 0, 0, 0, 0, 0, 0, 0])], &[&payer])
+        .expect("Decrement should succeed");
+    assert_eq!(read_baseline(&svm), 0);
+
+    // Overflow fails with the error of the first release
+    assert_eq!(
+        svm.process(&[step(&[2, 0])], &[&payer]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    assert_eq!(
+        svm.process(&[step(&[1, 1, 255, 255, 255, 255, 255, 255, 255, 255]), step(&[1, 0])], &[&payer]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    assert_eq!(read_baseline(&svm), 0);
+
+    // Counters in the current layout keep requiring their authority
+    let current = initialize_counter(&mut svm, program_id, &payer, 0);
+    let instruction = Instruction::new_with_bytes(program_id, &[1, 0], vec![AccountMeta::new(current, false)]);
+    assert!(svm.process(&[instruction], &[&payer]).is_err());
+    assert_eq!(read_count(&svm, &current), 0);
+
+    // A config PDA passed after the original accounts subjects them to the pause switch
+    let admin = Keypair::new();
+    svm.airdrop(&admin.pubkey(), 1_000_000_000);
+    configure(&mut svm, program_id, &admin, OverflowMode::Checked);
+    let mut paused_step = step(&[1, 0]);
+    paused_step.accounts.push(config_meta(&program_id));
+    svm.process(std::slice::from_ref(&paused_step), &[&payer])
+        .expect("Increment should succeed");
+    let set_paused = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetPaused { paused: true },
+        vec![
+            AccountMeta::new(find_config_address(&program_id).0, false),
+            AccountMeta::new_readonly(admin.pubkey(), true),
+        ],
+    );
+    svm.process(&[set_paused], &[&admin]).expect("Pause should succeed");
+    assert_eq!(
+        svm.process(&[paused_step], &[&payer]),
+        Err(CounterError::ProgramPaused.into())
+    );
+    let mut wrong_config = step(&[1, 0]);
+    wrong_config.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+    assert_eq!(svm.process(&[wrong_config], &[&payer]), Err(ProgramError::InvalidSeeds));
+    assert_eq!(read_baseline(&svm), 1);
+}
+
+#[test]
+fn test_compact_builders() {
+    let (mut svm, program_id, payer) = setup();
+
+    let first = initialize_counter(&mut svm, program_id, &payer, 10);
+    let second = initialize_counter(&mut svm, program_id, &payer, 20);
+
+    let instructions = [
+        increment_counter(&program_id, &first, &payer.pubkey(), None),
+        increment_counter(&program_id, &first, &payer.pubkey(), Some(300)),
+        decrement_counter(&program_id, &second, &payer.pubkey(), Some(5)),
+        batch(
+            &program_id,
+            &[first, second],
+            &payer.pubkey(),
+            vec![
+                (0, CounterOp::Decrement { step: None }),
+                (1, CounterOp::Set { value: 7 }),
+            ],
+        ),
+    ];
+    assert_eq!(instructions[0].data, [COMPACT_INCREMENT_OPCODE]);
+    svm.process(&instructions, &[&payer]).expect("Compact instructions should succeed");
+    assert_eq!(read_count(&svm, &first), 310);
+    assert_eq!(read_count(&svm, &second), 7);
+}
+
+/// Program breaking one of the runtime's account rules, picked by the instruction data
+fn rogue_program(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    match data[0] {
+        // Write to an account it does not own
+        0 => accounts[0].data.borrow_mut()[0] ^= 1,
+        // Debit an account it does not own
+        1 => {
+            **accounts[0].lamports.borrow_mut() -= 1;
+            **accounts[1].lamports.borrow_mut() += 1;
+        }
+        // Hand over an account it owns without zeroing the data
+        2 => accounts[0].assign(&system_program::id()),
+        // Leave an account it owns below rent exemption
+        3 => {
+            **accounts[0].lamports.borrow_mut() -= 1;
+            **accounts[1].lamports.borrow_mut() += 1;
+        }
+        // Hand over an account it owns after zeroing the data
+        _ => {
+            accounts[0].data.borrow_mut().fill(0);
+            accounts[0].assign(&system_program::id());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_runtime_account_rules() {
+    let (mut svm, program_id, payer) = setup();
+    let rogue = Pubkey::new_unique();
+    svm.add_program(rogue, rogue_program);
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 0);
+    let owned = Pubkey::new_unique();
+    svm.set_account(
+        owned,
+        Account {
+            lamports: Rent::default().minimum_balance(8),
+            data: vec![1; 8],
+            owner: rogue,
+            executable: false,
+        },
+    );
+    let rogue_instruction = |rule: u8, account: Pubkey| Instruction {
+        program_id: rogue,
+        accounts: vec![AccountMeta::new(account, false), AccountMeta::new(payer.pubkey(), true)],
+        data: vec![rule],
+    };
+
+    // Only the owner may change data or debit lamports
+    let counter_before = svm.get_account(&counter).cloned();
+    assert_eq!(
+        svm.process(&[rogue_instruction(0, counter)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        svm.process(&[rogue_instruction(1, counter)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(svm.get_account(&counter).cloned(), counter_before);
+
+    // Only zeroed accounts change owner, and none is left rent-paying
+    assert_eq!(
+        svm.process(&[rogue_instruction(2, owned)], &[&payer]),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        svm.process(&[rogue_instruction(3, owned)], &[&payer]),
+        Err(ProgramError::AccountNotRentExempt)
+    );
+    svm.process(&[rogue_instruction(4, owned)], &[&payer])
+        .expect("Reassigning zeroed data should succeed");
+    assert_eq!(svm.get_account(&owned).unwrap().owner, system_program::id());
+
+    // The same rules hold for programs the counter invokes
+    let instruction = authority_instruction(
+        program_id,
+        CounterInstruction::SetTrigger {
+            index: 0,
+            threshold: 1,
+            program: rogue,
+            data: vec![0],
+        },
+        counter,
+        &payer,
+    );
+    svm.process(&[instruction], &[&payer]).expect("Setting the trigger should succeed");
+    let mut instruction =
+        authority_instruction(program_id, CounterInstruction::IncrementCounter { step: None }, counter, &payer);
+    let config = instruction.accounts.pop().unwrap();
+    instruction.accounts.push(AccountMeta::new_readonly(rogue, false));
+    instruction.accounts.push(config);
+    assert_eq!(svm.process(&[instruction], &[&payer]), Err(ProgramError::InvalidArgument));
+    assert_eq!(read_count(&svm, &counter), 0);
+}
+
+#[test]
+fn test_events() {
+    let (mut svm, program_id, payer) = setup();
+
+    let counter = initialize_counter(&mut svm, program_id, &payer, 5);
+    let logged = svm.take_logged_data();
+    assert_eq!(
+        logged.iter().find_map(|data| CounterInitialized::decode(data)),
+        Some(CounterInitialized {
+            counter,
+            authority: payer.pubkey(),
+            initial_value: 5,
+        })
+    );
+
+    // Every count change is logged, failed transactions log nothing
+    let increment = authority_instruction(
+        program_id,
+        CounterInstruction::IncrementCounter { step: Some(3) },
+        counter,
+        &payer,
+    );
+    svm.process(&[increment], &[&payer]).expect("Increment should succeed");
+    let decrement = authority_instruction(
+        program_id,
+        CounterInstruction::DecrementCounter { step: Some(100) },
+        counter,
+        &payer,
+    );
+    assert!(svm.process(&[decrement], &[&payer]).is_err());
+    let updates: Vec<_> = svm
+        .take_logged_data()
+        .iter()
+        .filter_map(|data| CounterUpdated::decode(data))
+        .collect();
+    assert_eq!(
+        updates,
+        vec![CounterUpdated {
+            counter,
+            signer: payer.pubkey(),
+            old_value: 5,
+            new_value: 8,
+        }]
+    );
+
+    let new_authority = Keypair::new();
+    let instruction = counter_instruction(
+        &program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: new_authority.pubkey(),
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(find_registry_address(&program_id, &payer.pubkey()).0, false),
+            AccountMeta::new(find_registry_address(&program_id, &new_authority.pubkey()).0, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            config_meta(&program_id),
+        ],
+    );
+    svm.process(&[instruction], &[&payer]).expect("Set authority should succeed");
+    assert_eq!(
+        svm.take_logged_data()
+            .iter()
+            .find_map(|data| AuthorityChanged::decode(data)),
+        Some(AuthorityChanged {
+            counter,
+            old_authority: payer.pubkey(),
+            new_authority: new_authority.pubkey(),
+        })
+    );
+
+    svm.airdrop(&new_authority.pubkey(), 1_000_000_000);
+    let instruction = close_instruction(program_id, counter, &new_authority);
+    svm.process(&[instruction], &[&new_authority]).expect("Close should succeed");
+    assert_eq!(
+        svm.take_logged_data()
+            .iter()
+            .find_map(|data| CounterClosed::decode(data)),
+        Some(CounterClosed {
+            counter,
+            destination: new_authority.pubkey(),
+        })
+    );
+}
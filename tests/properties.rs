@@ -0,0 +1,293 @@
+//! Property tests that drive the native processor with random instruction sequences
+//! and compare the counters against a simple model
+
+mod common;
+
+use common::NativeSvm;
+use proptest::prelude::*;
+use solana_counter_program::*;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+/// History capacity of the first counter, small enough to wrap during a run
+const HISTORY_CAPACITY: u16 = 3;
+
+/// Counter instruction applied to one of two counters owned by the same authority
+#[derive(Clone, Debug)]
+enum Action {
+    Increment { counter: usize, step: Option<u64> },
+    Decrement { counter: usize, step: Option<u64> },
+    Reset { counter: usize },
+    Freeze { counter: usize },
+    Thaw { counter: usize },
+    Transfer { source: usize, amount: u64 },
+    Batch { ops: Vec<(u8, CounterOp)> },
+}
+
+/// Expected state of both counters
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Model {
+    counts: [u64; 2],
+    frozen: [bool; 2],
+}
+
+impl Model {
+    /// State after `action`, or `None` when the program must reject it
+    fn apply(&self, action: &Action) -> Option<Model> {
+        let mut next = self.clone();
+        match *action {
+            Action::Increment { counter, step } => {
+                if self.frozen[counter] {
+                    return None;
+                }
+                next.counts[counter] = self.counts[counter].checked_add(step.unwrap_or(1))?;
+            }
+            Action::Decrement { counter, step } => {
+                if self.frozen[counter] {
+                    return None;
+                }
+                next.counts[counter] = self.counts[counter].checked_sub(step.unwrap_or(1))?;
+            }
+            Action::Reset { counter } => {
+                if self.frozen[counter] {
+                    return None;
+                }
+                next.counts[counter] = 0;
+            }
+            Action::Freeze { counter } => next.frozen[counter] = true,
+            Action::Thaw { counter } => next.frozen[counter] = false,
+            Action::Transfer { source, amount } => {
+                let destination = 1 - source;
+                if self.frozen[source] || self.frozen[destination] {
+                    return None;
+                }
+                next.counts[source] = self.counts[source].checked_sub(amount)?;
+                next.counts[destination] = self.counts[destination].checked_add(amount)?;
+            }
+            Action::Batch { ref ops } => {
+                // Every passed counter must be unfrozen, even without operations on it
+                let passed = batch_counter_count(ops);
+                if self.frozen[..passed].iter().any(|frozen| *frozen) {
+                    return None;
+                }
+                for (index, op) in ops {
+                    let count = &mut next.counts[*index as usize];
+                    *count = match *op {
+                        CounterOp::Increment { step } => count.checked_add(step.unwrap_or(1))?,
+                        CounterOp::Decrement { step } => count.checked_sub(step.unwrap_or(1))?,
+                        CounterOp::Set { value } => value,
+                    };
+                }
+            }
+        }
+        Some(next)
+    }
+}
+
+/// Number of counter accounts a batch expects in front of the authority
+fn batch_counter_count(ops: &[(u8, CounterOp)]) -> usize {
+    ops.iter().map(|(index, _)| *index as usize + 1).max().unwrap_or(0)
+}
+
+/// Steps are mostly small so runs stay in range, with occasional extremes
+fn step() -> impl Strategy<Value = u64> {
+    prop_oneof![4 => 0..20u64, 1 => any::<u64>(), 1 => Just(u64::MAX)]
+}
+
+fn counter_op() -> impl Strategy<Value = CounterOp> {
+    prop_oneof![
+        proptest::option::of(step()).prop_map(|step| CounterOp::Increment { step }),
+        proptest::option::of(step()).prop_map(|step| CounterOp::Decrement { step }),
+        step().prop_map(|value| CounterOp::Set { value }),
+    ]
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    let counter = 0..2usize;
+    prop_oneof![
+        3 => (counter.clone(), proptest::option::of(step()))
+            .prop_map(|(counter, step)| Action::Increment { counter, step }),
+        3 => (counter.clone(), proptest::option::of(step()))
+            .prop_map(|(counter, step)| Action::Decrement { counter, step }),
+        1 => counter.clone().prop_map(|counter| Action::Reset { counter }),
+        1 => counter.clone().prop_map(|counter| Action::Freeze { counter }),
+        1 => counter.clone().prop_map(|counter| Action::Thaw { counter }),
+        2 => (counter, step()).prop_map(|(source, amount)| Action::Transfer { source, amount }),
+        2 => proptest::collection::vec((0..2u8, counter_op()), 0..6)
+            .prop_map(|ops| Action::Batch { ops }),
+    ]
+}
+
+struct Fixture {
+    svm: NativeSvm,
+    program_id: Pubkey,
+    authority: Keypair,
+    counters: [Keypair; 2],
+}
+
+impl Fixture {
+    /// Two zeroed counters, the first one with a history extension
+    fn new() -> Self {
+        let mut svm = NativeSvm::new();
+        let program_id = Pubkey::new_unique();
+        svm.add_program(program_id, process_instruction);
+
+        let authority = Keypair::new();
+        svm.airdrop(&authority.pubkey(), 1_000_000_000);
+
+        let counters = [Keypair::new(), Keypair::new()];
+        for (counter, history_capacity) in counters.iter().zip([HISTORY_CAPACITY, 0]) {
+            let instruction = counter_instruction(
+                &program_id,
+                &CounterInstruction::InitializeCounter {
+                    initial_value: 0,
+                    metadata: None,
+                    history_capacity,
+                },
+                vec![
+                    AccountMeta::new(counter.pubkey(), true),
+                    AccountMeta::new(authority.pubkey(), true),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new(find_registry_address(&program_id, &authority.pubkey()).0, false),
+                    AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+                ],
+            );
+            svm.process(&[instruction], &[&authority, counter])
+                .expect("Initialize should succeed");
+        }
+
+        Self {
+            svm,
+            program_id,
+            authority,
+            counters,
+        }
+    }
+
+    fn instruction(&self, action: &Action) -> Instruction {
+        let counter = |index: usize| AccountMeta::new(self.counters[index].pubkey(), false);
+        let (instruction, mut accounts) = match action.clone() {
+            Action::Increment { counter: index, step } => {
+                (CounterInstruction::IncrementCounter { step }, vec![counter(index)])
+            }
+            Action::Decrement { counter: index, step } => {
+                (CounterInstruction::DecrementCounter { step }, vec![counter(index)])
+            }
+            Action::Reset { counter: index } => (CounterInstruction::ResetCounter, vec![counter(index)]),
+            Action::Freeze { counter: index } => (CounterInstruction::FreezeCounter, vec![counter(index)]),
+            Action::Thaw { counter: index } => (CounterInstruction::ThawCounter, vec![counter(index)]),
+            Action::Transfer { source, amount } => (
+                CounterInstruction::TransferBetweenCounters { amount },
+                vec![counter(source), counter(1 - source)],
+            ),
+            Action::Batch { ops } => {
+                let accounts = (0..batch_counter_count(&ops)).map(counter).collect();
+                (CounterInstruction::Batch { ops }, accounts)
+            }
+        };
+        accounts.push(AccountMeta::new_readonly(self.authority.pubkey(), true));
+        accounts.push(AccountMeta::new_readonly(find_config_address(&self.program_id).0, false));
+
+        counter_instruction(&self.program_id, &instruction, accounts)
+    }
+
+    /// Check both counter accounts against the model and their fixed layout
+    fn assert_matches(&self, model: &Model) {
+        for (index, (counter, history_capacity)) in self.counters.iter().zip([HISTORY_CAPACITY, 0]).enumerate() {
+            let account = self
+                .svm
+                .get_account(&counter.pubkey())
+                .expect("Counter account should exist");
+            assert_eq!(account.owner, self.program_id);
+            assert_eq!(account.data.len(), CounterAccount::LEN + history_space(history_capacity));
+
+            let counter_data = CounterAccount::load(&account.data).expect("Counter data should load");
+            assert_eq!(counter_data.count(), model.counts[index]);
+            assert_eq!(counter_data.is_frozen(), model.frozen[index]);
+            assert_eq!({ counter_data.authority }, self.authority.pubkey());
+
+            let history = decode_history(&account.data).expect("History should decode");
+            assert!(history.len() <= history_capacity as usize);
+            if let Some(latest) = history.last() {
+                assert_eq!({ latest.resulting_value }, model.counts[index]);
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn counters_follow_model(actions in proptest::collection::vec(action(), 1..32)) {
+        let mut fixture = Fixture::new();
+        let mut model = Model::default();
+
+        for action in &actions {
+            let instruction = fixture.instruction(action);
+            let result = fixture.svm.process(&[instruction], &[&fixture.authority]);
+            match model.apply(action) {
+                Some(next) => {
+                    prop_assert!(result.is_ok(), "{:?} should succeed: {:?}", action, result);
+                    model = next;
+                }
+                None => prop_assert!(result.is_err(), "{:?} should fail", action),
+            }
+            fixture.assert_matches(&model);
+        }
+    }
+
+    #[test]
+    fn random_instruction_data_is_rejected_safely(
+        tag in prop_oneof![0..=34u8, any::<u8>()],
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+        accounts in proptest::collection::vec((0..6usize, any::<bool>()), 0..8),
+    ) {
+        let fixture = Fixture::new();
+        let mut svm = fixture.svm;
+        let mut data = vec![tag];
+        data.extend_from_slice(&payload);
+
+        let keys = [
+            fixture.counters[0].pubkey(),
+            fixture.counters[1].pubkey(),
+            fixture.authority.pubkey(),
+            find_registry_address(&fixture.program_id, &fixture.authority.pubkey()).0,
+            system_program::id(),
+            find_config_address(&fixture.program_id).0,
+        ];
+        let mut metas: Vec<AccountMeta> = accounts
+            .iter()
+            .map(|(index, writable)| {
+                let signer = keys[*index] == fixture.authority.pubkey();
+                if *writable {
+                    AccountMeta::new(keys[*index], signer)
+                } else {
+                    AccountMeta::new_readonly(keys[*index], signer)
+                }
+            })
+            .collect();
+        metas.push(AccountMeta::new_readonly(keys[5], false));
+        let before: Vec<_> = keys.iter().map(|key| svm.get_account(key).cloned()).collect();
+
+        // Whatever the outcome, the processor must not panic
+        let instruction = Instruction::new_with_bytes(fixture.program_id, &data, metas);
+        let result = svm.process(&[instruction], &[&fixture.authority]);
+
+        let after: Vec<_> = keys.iter().map(|key| svm.get_account(key).cloned()).collect();
+        if result.is_err() {
+            prop_assert_eq!(before, after);
+        }
+
+        // Counters are either closed or still hold a valid layout
+        for (counter, history_capacity) in fixture.counters.iter().zip([HISTORY_CAPACITY, 0]) {
+            if let Some(account) = svm.get_account(&counter.pubkey()) {
+                prop_assert_eq!(account.data.len(), CounterAccount::LEN + history_space(history_capacity));
+                prop_assert!(CounterAccount::load(&account.data).is_ok());
+                prop_assert!(decode_history(&account.data).is_ok());
+            }
+        }
+    }
+}